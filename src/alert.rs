@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use surreal_socket::{
+    dbrecord::{DBRecord, SsUuid},
+    error::SurrealSocketError,
+};
+
+use crate::{
+    product::{Availability, Cents, Product, ProductSummary},
+    surrealdb_client,
+};
+
+/// Request to be notified when a product's price falls to `target_price`, e.g. its
+/// `suggested_alert_price`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    pub product_uuid: SsUuid<Product>,
+    pub target_price: Cents,
+}
+
+impl Alert {
+    /// Whether `product` is at or below the target price and not out of stock
+    pub fn is_triggered_by(&self, product: &Product) -> bool {
+        product.uuid == self.product_uuid
+            && product.price <= self.target_price
+            && product.availability != Availability::OutOfStock
+    }
+}
+
+/// Ready-to-send payload for a triggered alert, for email and push jobs
+#[derive(Serialize)]
+pub struct AlertNotification {
+    pub alert: Alert,
+    pub product: ProductSummary,
+    pub current_price: Cents,
+}
+
+impl Product {
+    /// Payloads for the `alerts` whose product currently triggers them, in the order given.
+    /// Alerts for products that no longer exist are skipped.
+    pub async fn evaluate_alerts_with_payloads(
+        alerts: &[Alert],
+    ) -> Result<Vec<AlertNotification>, SurrealSocketError> {
+        if alerts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT * FROM {} WHERE {} IN $uuids",
+            Self::table(),
+            Self::UUID_FIELD
+        );
+
+        let uuids: Vec<SsUuid<Product>> = alerts.iter().map(|a| a.product_uuid.clone()).collect();
+        let products: Vec<Product> = client.query(query).bind(("uuids", uuids)).await?.take(0)?;
+
+        Ok(notifications(alerts, &products))
+    }
+}
+
+/// Payloads for the `alerts` triggered by one of `products`, in the order given
+fn notifications(alerts: &[Alert], products: &[Product]) -> Vec<AlertNotification> {
+    alerts
+        .iter()
+        .filter_map(|alert| {
+            let product = products.iter().find(|p| alert.is_triggered_by(p))?;
+
+            Some(AlertNotification {
+                alert: alert.clone(),
+                current_price: product.price,
+                product: ProductSummary::from(product.clone()),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_for_a_triggered_alert() {
+        let product = Product::example();
+        let out_of_stock = Product {
+            availability: Availability::OutOfStock,
+            ..Product::example()
+        };
+        let alert = |product: &Product, target: u32| Alert {
+            product_uuid: product.uuid(),
+            target_price: Cents(target),
+        };
+
+        let alerts = [
+            alert(&product, 1999),
+            alert(&product, 2199),
+            alert(&out_of_stock, 2500),
+            alert(&Product::example(), 2500),
+        ];
+
+        let payloads = notifications(&alerts, &[product.clone(), out_of_stock]);
+
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0].alert, alerts[1]);
+        assert_eq!(payloads[0].current_price, Cents(2199));
+
+        let json = serde_json::to_value(&payloads[0]).unwrap();
+        assert_eq!(json["product"]["uuid"], product.uuid.to_uuid_string());
+        assert_eq!(json["product"]["name"], product.name.as_str());
+        assert_eq!(json["current_price"], 2199);
+    }
+}
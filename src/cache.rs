@@ -0,0 +1,181 @@
+use std::path::Path;
+
+use rusqlite::{Connection, params};
+use surreal_socket::error::SurrealSocketError;
+
+use crate::{product::Product, quality::VisibilityConfig, query::ProductQuery};
+
+/// Table the cache keeps products in
+const CACHE_TABLE: &str = "products";
+
+#[derive(Debug)]
+pub enum CacheError {
+    /// Reading products from the database failed
+    Database(SurrealSocketError),
+    Sqlite(rusqlite::Error),
+    /// A cached product couldn't be encoded or decoded
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Database(e) => write!(f, "Failed to read products: {}", e),
+            Self::Sqlite(e) => write!(f, "SQLite cache error: {}", e),
+            Self::Json(e) => write!(f, "Invalid cached product: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+impl From<CacheError> for String {
+    fn from(e: CacheError) -> String {
+        e.to_string()
+    }
+}
+
+impl From<SurrealSocketError> for CacheError {
+    fn from(e: SurrealSocketError) -> Self {
+        Self::Database(e)
+    }
+}
+
+impl From<rusqlite::Error> for CacheError {
+    fn from(e: rusqlite::Error) -> Self {
+        Self::Sqlite(e)
+    }
+}
+
+impl From<serde_json::Error> for CacheError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+impl Product {
+    /// Replaces the contents of the SQLite cache at `path` (created if missing) with every
+    /// product in the database, samples included. Returns the number of products written.
+    pub async fn sync_to_sqlite(path: &Path) -> Result<usize, CacheError> {
+        let products = Self::search(&ProductQuery {
+            exclude_samples: false,
+            ..Default::default()
+        })
+        .await?;
+
+        write_cache(path, &products)
+    }
+
+    /// Products in the SQLite cache at `path` matching `query`, in the same order as
+    /// `search`: cheapest per kg first, ties broken by uuid
+    pub fn load_from_sqlite(path: &Path, query: &ProductQuery) -> Result<Vec<Product>, CacheError> {
+        let connection = open_cache(path)?;
+
+        let mut select = connection.prepare(&format!(
+            "SELECT product FROM {} ORDER BY price_per_kg ASC, uuid ASC",
+            CACHE_TABLE
+        ))?;
+
+        let visibility = VisibilityConfig::from_env();
+        let mut products = Vec::new();
+
+        for row in select.query_map([], |row| row.get::<_, String>(0))? {
+            let product: Product = serde_json::from_str(&row?)?;
+
+            if product.matches_with(query, &visibility) {
+                products.push(product);
+            }
+        }
+
+        if let Some(limit) = query.limit {
+            products.truncate(limit as usize);
+        }
+
+        Ok(products)
+    }
+}
+
+/// Replaces the contents of the cache at `path` with `products`, returning how many were
+/// written
+fn write_cache(path: &Path, products: &[Product]) -> Result<usize, CacheError> {
+    let mut connection = open_cache(path)?;
+    let transaction = connection.transaction()?;
+
+    transaction.execute(&format!("DELETE FROM {}", CACHE_TABLE), [])?;
+
+    {
+        let mut insert = transaction.prepare(&format!(
+            "INSERT INTO {} (uuid, price_per_kg, product) VALUES (?1, ?2, ?3)",
+            CACHE_TABLE
+        ))?;
+
+        for product in products {
+            insert.execute(params![
+                product.uuid.to_uuid_string(),
+                product.price_per_kg.0,
+                serde_json::to_string(product)?,
+            ])?;
+        }
+    }
+
+    transaction.commit()?;
+
+    Ok(products.len())
+}
+
+/// Connection to the cache at `path`, creating its table if needed
+fn open_cache(path: &Path) -> Result<Connection, CacheError> {
+    let connection = Connection::open(path)?;
+
+    connection.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} (\
+             uuid TEXT PRIMARY KEY, \
+             price_per_kg INTEGER NOT NULL, \
+             product TEXT NOT NULL)",
+            CACHE_TABLE
+        ),
+        [],
+    )?;
+
+    Ok(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::product::{Cents, CentsPerKg, FilamentMaterial};
+
+    #[test]
+    fn sync_then_query_round_trips_through_sqlite() {
+        let path = std::env::temp_dir().join(format!(
+            "filamentseek-cache-{}.sqlite",
+            Product::example().uuid.to_uuid_string()
+        ));
+
+        let cheap_pla = Product {
+            price: Cents(1599),
+            price_per_kg: CentsPerKg(1599),
+            ..Product::example()
+        };
+        let pla = Product::example();
+        let petg = Product {
+            material: FilamentMaterial::PETG,
+            ..Product::example()
+        };
+
+        let written = write_cache(&path, &[pla.clone(), petg, cheap_pla.clone()]).unwrap();
+        assert_eq!(written, 3);
+
+        let query = ProductQuery {
+            material: Some(FilamentMaterial::PLA),
+            ..ProductQuery::default()
+        };
+        let loaded = Product::load_from_sqlite(&path, &query).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), 2);
+        assert!(loaded[0] == cheap_pla);
+        assert!(loaded[1] == pla);
+    }
+}
@@ -0,0 +1,121 @@
+use crate::product::Product;
+
+/// Swatch colors for common filament color names, lowercase
+const PALETTE: &[(&str, &str)] = &[
+    ("black", "#000000"),
+    ("white", "#FFFFFF"),
+    ("red", "#FF0000"),
+    ("green", "#008000"),
+    ("blue", "#0000FF"),
+    ("yellow", "#FFFF00"),
+    ("orange", "#FFA500"),
+    ("purple", "#800080"),
+    ("pink", "#FFC0CB"),
+    ("brown", "#8B4513"),
+    ("grey", "#808080"),
+    ("gray", "#808080"),
+    ("silver", "#C0C0C0"),
+    ("gold", "#FFD700"),
+    ("beige", "#F5F5DC"),
+    ("ivory", "#FFFFF0"),
+    ("natural", "#F4EEDC"),
+    ("transparent", "#FFFFFF"),
+    ("clear", "#FFFFFF"),
+    ("navy", "#000080"),
+    ("navy blue", "#000080"),
+    ("sky blue", "#87CEEB"),
+    ("light blue", "#ADD8E6"),
+    ("dark blue", "#00008B"),
+    ("dark green", "#006400"),
+    ("light green", "#90EE90"),
+    ("olive", "#808000"),
+    ("teal", "#008080"),
+    ("cyan", "#00FFFF"),
+    ("magenta", "#FF00FF"),
+    ("violet", "#EE82EE"),
+    ("dark grey", "#404040"),
+    ("dark gray", "#404040"),
+    ("light grey", "#D3D3D3"),
+    ("light gray", "#D3D3D3"),
+    ("bronze", "#CD7F32"),
+    ("copper", "#B87333"),
+];
+
+impl Product {
+    /// Swatch color as `#RRGGBB`, from a hex code given as the color (`#RGB` or `#RRGGBB`) or
+    /// a palette color name. Names with a finish, e.g. "Matte Black", use the last word.
+    /// `None` for colors that aren't recognized.
+    pub fn color_hex(&self) -> Option<String> {
+        let color = self.color.trim();
+
+        if let Some(hex) = parse_hex(color) {
+            return Some(hex);
+        }
+
+        let name = color
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ")
+            .to_lowercase();
+
+        let lookup = |name: &str| {
+            PALETTE
+                .iter()
+                .find(|(palette_name, _)| *palette_name == name)
+                .map(|(_, hex)| (*hex).to_owned())
+        };
+
+        lookup(&name).or_else(|| lookup(name.rsplit(' ').next()?))
+    }
+}
+
+/// Uppercase `#RRGGBB` from `#RGB` or `#RRGGBB`
+fn parse_hex(color: &str) -> Option<String> {
+    let digits = color.strip_prefix('#')?;
+
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let full: String = match digits.len() {
+        3 => digits.chars().flat_map(|c| [c, c]).collect(),
+        6 => digits.to_owned(),
+        _ => return None,
+    };
+
+    Some(format!("#{}", full.to_uppercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn colored(color: &str) -> Product {
+        Product {
+            color: color.to_owned(),
+            ..Product::example()
+        }
+    }
+
+    #[test]
+    fn named_and_hex_colors() {
+        assert_eq!(colored("Red").color_hex().as_deref(), Some("#FF0000"));
+        assert_eq!(
+            colored("  Sky   Blue ").color_hex().as_deref(),
+            Some("#87CEEB")
+        );
+        assert_eq!(
+            colored("Matte Black").color_hex().as_deref(),
+            Some("#000000")
+        );
+        assert_eq!(colored("#1a2b3c").color_hex().as_deref(), Some("#1A2B3C"));
+        assert_eq!(colored("#f0a").color_hex().as_deref(), Some("#FF00AA"));
+    }
+
+    #[test]
+    fn unknown_colors_have_no_hex() {
+        for color in ["Galaxy Sparkle", "", "#12345", "#GGGGGG"] {
+            assert_eq!(colored(color).color_hex(), None, "{color:?}");
+        }
+    }
+}
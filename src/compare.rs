@@ -0,0 +1,244 @@
+use std::cmp::Ordering;
+
+use surreal_socket::dbrecord::SsUuid;
+
+use crate::product::{Product, color_family, normalize_color};
+
+/// Weights of the parts of `Product::similarity_score`, summing to 1
+const SIMILARITY_MATERIAL_WEIGHT: f32 = 0.4;
+const SIMILARITY_DIAMETER_WEIGHT: f32 = 0.2;
+const SIMILARITY_COLOR_WEIGHT: f32 = 0.15;
+const SIMILARITY_BRAND_WEIGHT: f32 = 0.1;
+const SIMILARITY_PRICE_WEIGHT: f32 = 0.15;
+
+/// Winning products per metric. Each list holds every product tied for the best value,
+/// and is empty when no product has a value for that metric.
+#[derive(Clone, Default, PartialEq)]
+pub struct Comparison {
+    /// Lowest price
+    pub price: Vec<SsUuid<Product>>,
+    /// Lowest price per kg
+    pub price_per_kg: Vec<SsUuid<Product>>,
+    /// Highest weight
+    pub weight: Vec<SsUuid<Product>>,
+    /// Longest estimated length
+    pub estimated_length: Vec<SsUuid<Product>>,
+}
+
+pub fn compare_products(products: &[Product]) -> Comparison {
+    Comparison {
+        price: winners(products, |p| Some(p.price.0 as f32), Best::Lowest),
+        price_per_kg: winners(products, |p| Some(p.price_per_kg.0 as f32), Best::Lowest),
+        weight: winners(products, |p| Some(p.weight.0 as f32), Best::Highest),
+        estimated_length: winners(products, Product::estimated_length_m, Best::Highest),
+    }
+}
+
+/// Differences between two products, each delta being this product's value minus the other's
+#[derive(Clone, PartialEq)]
+pub struct ProductComparison {
+    /// Price difference in cents
+    pub price_delta: i64,
+    /// Price per kg difference in cents
+    pub price_per_kg_delta: i64,
+    /// Weight difference in grams
+    pub weight_delta: i64,
+    /// The product with the lower price per kg, `None` when they are equal
+    pub cheaper: Option<SsUuid<Product>>,
+    /// `false` when the materials differ, so the per kg prices are not like for like
+    pub comparable: bool,
+}
+
+impl Product {
+    pub fn compare_to(&self, other: &Product) -> ProductComparison {
+        let cheaper = match self.price_per_kg.cmp(&other.price_per_kg) {
+            Ordering::Less => Some(self.uuid.clone()),
+            Ordering::Greater => Some(other.uuid.clone()),
+            Ordering::Equal => None,
+        };
+
+        ProductComparison {
+            price_delta: self.price.0 as i64 - other.price.0 as i64,
+            price_per_kg_delta: self.price_per_kg.0 as i64 - other.price_per_kg.0 as i64,
+            weight_delta: self.weight.0 as i64 - other.weight.0 as i64,
+            cheaper,
+            comparable: self.material == other.material,
+        }
+    }
+
+    /// How alike two products are for "you might also like" ranking, from 0.0 to 1.0. The
+    /// material counts most (half for the same family, e.g. PLA and PLA+), then diameter,
+    /// color family, brand, and how close the prices per kg are.
+    pub fn similarity_score(&self, other: &Product) -> f32 {
+        let material = if self.material == other.material {
+            1.0
+        } else if self
+            .material
+            .family_tag()
+            .is_some_and(|family| other.material.family_tag() == Some(family))
+        {
+            0.5
+        } else {
+            0.0
+        };
+
+        let diameter = if self.diameter == other.diameter {
+            1.0
+        } else {
+            0.0
+        };
+
+        let (family, other_family) = (color_family(&self.color), color_family(&other.color));
+        let same_color = !self.color.trim().is_empty()
+            && normalize_color(&self.color) == normalize_color(&other.color);
+        let color = if (family != "Other" && family == other_family) || same_color {
+            1.0
+        } else {
+            0.0
+        };
+
+        let brand = match (&self.brand, &other.brand) {
+            (Some(brand), Some(other_brand))
+                if brand.trim().eq_ignore_ascii_case(other_brand.trim()) =>
+            {
+                1.0
+            }
+            _ => 0.0,
+        };
+
+        let (low, high) = (
+            self.price_per_kg.0.min(other.price_per_kg.0),
+            self.price_per_kg.0.max(other.price_per_kg.0),
+        );
+        let price = if high == 0 {
+            0.0
+        } else {
+            low as f32 / high as f32
+        };
+
+        material * SIMILARITY_MATERIAL_WEIGHT
+            + diameter * SIMILARITY_DIAMETER_WEIGHT
+            + color * SIMILARITY_COLOR_WEIGHT
+            + brand * SIMILARITY_BRAND_WEIGHT
+            + price * SIMILARITY_PRICE_WEIGHT
+    }
+}
+
+enum Best {
+    Lowest,
+    Highest,
+}
+
+fn winners(
+    products: &[Product],
+    metric: impl Fn(&Product) -> Option<f32>,
+    best: Best,
+) -> Vec<SsUuid<Product>> {
+    let values: Vec<(&Product, f32)> = products
+        .iter()
+        .filter_map(|p| metric(p).map(|v| (p, v)))
+        .collect();
+
+    let target = values.iter().map(|(_, v)| *v).reduce(|a, b| match best {
+        Best::Lowest => a.min(b),
+        Best::Highest => a.max(b),
+    });
+
+    let Some(target) = target else {
+        return Vec::new();
+    };
+
+    values
+        .into_iter()
+        .filter(|(_, v)| *v == target)
+        .map(|(p, _)| p.uuid.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::product::{Cents, CentsPerKg, Grams};
+
+    fn listing(price: u32, weight: u16) -> Product {
+        let mut product = Product {
+            price: Cents(price),
+            weight: Grams(weight),
+            ..Product::example()
+        };
+        product.price_per_kg = product.calculate_price_per_kg();
+        product
+    }
+
+    #[test]
+    fn cheapest_per_kg_wins_its_metric() {
+        let small = listing(1299, 500);
+        let standard = listing(2199, 1000);
+        let bulk = listing(5999, 3000);
+
+        let comparison = compare_products(&[small.clone(), standard.clone(), bulk.clone()]);
+
+        assert_eq!(bulk.price_per_kg, CentsPerKg(2000));
+        assert!(comparison.price_per_kg == vec![bulk.uuid.clone()]);
+        assert!(comparison.price == vec![small.uuid.clone()]);
+        assert!(comparison.weight == vec![bulk.uuid.clone()]);
+        assert!(comparison.estimated_length == vec![bulk.uuid]);
+    }
+
+    #[test]
+    fn ties_share_the_win() {
+        let first = listing(2199, 1000);
+        let second = listing(2199, 1000);
+
+        let comparison = compare_products(&[first.clone(), second.clone()]);
+
+        assert!(comparison.price_per_kg == vec![first.uuid, second.uuid]);
+        assert!(compare_products(&[]).price.is_empty());
+    }
+
+    #[test]
+    fn compare_to_identifies_the_cheaper_product() {
+        let cheap = listing(1999, 1000);
+        let pricey = listing(2499, 1000);
+
+        let comparison = cheap.compare_to(&pricey);
+        assert!(comparison.cheaper == Some(cheap.uuid.clone()));
+        assert_eq!(comparison.price_delta, -500);
+        assert_eq!(comparison.price_per_kg_delta, -500);
+        assert_eq!(comparison.weight_delta, 0);
+        assert!(comparison.comparable);
+
+        assert!(pricey.compare_to(&cheap).cheaper == Some(cheap.uuid.clone()));
+        assert!(cheap.compare_to(&cheap.clone()).cheaper.is_none());
+
+        let petg = Product {
+            material: crate::product::FilamentMaterial::PETG,
+            ..listing(1999, 1000)
+        };
+        assert!(!cheap.compare_to(&petg).comparable);
+    }
+
+    #[test]
+    fn same_material_and_diameter_scores_higher_than_cross_material() {
+        let product = Product::example();
+        let twin = Product {
+            uuid: SsUuid::new(),
+            brand: Some("Polymaker".to_owned()),
+            ..Product::example()
+        };
+        let abs = Product {
+            uuid: SsUuid::new(),
+            material: crate::product::FilamentMaterial::ABS,
+            diameter: crate::product::FilamentDiameter::D285,
+            ..twin.clone()
+        };
+
+        let twin_score = product.similarity_score(&twin);
+        let abs_score = product.similarity_score(&abs);
+
+        assert!(twin_score > abs_score);
+        assert!((0.0..=1.0).contains(&twin_score));
+        assert!((0.0..=1.0).contains(&abs_score));
+        assert!((product.similarity_score(&Product::example()) - 1.0).abs() < 1e-6);
+    }
+}
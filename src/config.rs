@@ -0,0 +1,256 @@
+use surrealdb::{
+    Surreal,
+    engine::remote::ws::{Client, Ws, Wss},
+    opt::auth::Root,
+};
+
+/// Connection settings for SurrealDB, read from `SURREAL_*` environment variables
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SurrealConfig {
+    /// `host:port`, optionally prefixed with `ws://` or `wss://` (bare addresses use `ws`)
+    pub address: String,
+    pub username: String,
+    pub password: String,
+    pub namespace: String,
+    pub database: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    MissingVar(&'static str),
+    Empty(&'static str),
+    InvalidScheme(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingVar(var) => write!(f, "{} environment variable not set", var),
+            Self::Empty(var) => write!(f, "{} must not be empty", var),
+            Self::InvalidScheme(address) => write!(
+                f,
+                "SURREAL_ADDRESS must use ws:// or wss:// (or no scheme), got {}",
+                address
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<ConfigError> for String {
+    fn from(e: ConfigError) -> String {
+        e.to_string()
+    }
+}
+
+impl SurrealConfig {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    /// `from_env` reading variables through `lookup`
+    fn from_lookup(lookup: impl Fn(&'static str) -> Option<String>) -> Result<Self, ConfigError> {
+        let var = |name: &'static str| {
+            lookup(name).ok_or_else(|| {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(variable = name, "{} environment variable not set", name);
+
+                ConfigError::MissingVar(name)
+            })
+        };
+
+        let config = Self {
+            address: var("SURREAL_ADDRESS")?,
+            username: var("SURREAL_USERNAME")?,
+            password: var("SURREAL_PASSWORD")?,
+            namespace: var("SURREAL_NAMESPACE")?,
+            database: var("SURREAL_DATABASE")?,
+        };
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let (_, host) = self.scheme_and_host()?;
+
+        if host.trim().is_empty() {
+            return Err(ConfigError::Empty("SURREAL_ADDRESS"));
+        }
+
+        for (name, value) in [
+            ("SURREAL_USERNAME", &self.username),
+            ("SURREAL_PASSWORD", &self.password),
+            ("SURREAL_NAMESPACE", &self.namespace),
+            ("SURREAL_DATABASE", &self.database),
+        ] {
+            if value.trim().is_empty() {
+                return Err(ConfigError::Empty(name));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Splits the address into whether it uses TLS and the bare `host:port`
+    fn scheme_and_host(&self) -> Result<(bool, &str), ConfigError> {
+        let address = self.address.trim();
+
+        if let Some(host) = address.strip_prefix("wss://") {
+            Ok((true, host))
+        } else if let Some(host) = address.strip_prefix("ws://") {
+            Ok((false, host))
+        } else if address.contains("://") {
+            Err(ConfigError::InvalidScheme(self.address.clone()))
+        } else {
+            Ok((false, address))
+        }
+    }
+
+    pub async fn connect(&self) -> Result<Surreal<Client>, String> {
+        let (tls, host) = self.scheme_and_host()?;
+
+        let db = if tls {
+            Surreal::new::<Wss>(host).await
+        } else {
+            Surreal::new::<Ws>(host).await
+        }
+        .map_err(|e| "Error connecting to SurrealDB: ".to_owned() + &e.to_string())?;
+
+        db.signin(Root {
+            username: &self.username,
+            password: &self.password,
+        })
+        .await
+        .map_err(|e| "Error signing in to SurrealDB: ".to_owned() + &e.to_string())?;
+
+        db.use_ns(&self.namespace)
+            .use_db(&self.database)
+            .await
+            .map_err(|e| "Error using namespace/database: ".to_owned() + &e.to_string())?;
+
+        Ok(db)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SurrealConfig {
+        SurrealConfig {
+            address: "localhost:8000".to_owned(),
+            username: "root".to_owned(),
+            password: "secret".to_owned(),
+            namespace: "filamentseek".to_owned(),
+            database: "products".to_owned(),
+        }
+    }
+
+    #[test]
+    fn valid_addresses() {
+        for address in [
+            "localhost:8000",
+            "ws://localhost:8000",
+            "wss://db.example.com",
+        ] {
+            let config = SurrealConfig {
+                address: address.to_owned(),
+                ..config()
+            };
+            assert_eq!(config.validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn each_invalid_setting_is_reported() {
+        let http = SurrealConfig {
+            address: "http://localhost:8000".to_owned(),
+            ..config()
+        };
+        assert_eq!(
+            http.validate(),
+            Err(ConfigError::InvalidScheme(
+                "http://localhost:8000".to_owned()
+            ))
+        );
+
+        let no_host = SurrealConfig {
+            address: "ws://".to_owned(),
+            ..config()
+        };
+        assert_eq!(
+            no_host.validate(),
+            Err(ConfigError::Empty("SURREAL_ADDRESS"))
+        );
+
+        let blank_password = SurrealConfig {
+            password: "  ".to_owned(),
+            ..config()
+        };
+        assert_eq!(
+            blank_password.validate(),
+            Err(ConfigError::Empty("SURREAL_PASSWORD"))
+        );
+
+        let blank_database = SurrealConfig {
+            database: String::new(),
+            ..config()
+        };
+        assert_eq!(
+            blank_database.validate(),
+            Err(ConfigError::Empty("SURREAL_DATABASE"))
+        );
+    }
+
+    #[test]
+    fn missing_variable_is_named() {
+        let result = SurrealConfig::from_lookup(|name| {
+            (name != "SURREAL_NAMESPACE").then(|| "value".to_owned())
+        });
+
+        assert_eq!(result, Err(ConfigError::MissingVar("SURREAL_NAMESPACE")));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn missing_variable_warning_names_it() {
+        use std::{
+            io,
+            sync::{Arc, Mutex},
+        };
+
+        #[derive(Clone, Default)]
+        struct Capture(Arc<Mutex<Vec<u8>>>);
+
+        impl io::Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let capture = Capture::default();
+        let writer = capture.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _ = SurrealConfig::from_lookup(|name| {
+                (name != "SURREAL_PASSWORD").then(|| "value".to_owned())
+            });
+        });
+
+        let logs = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("WARN"));
+        assert!(logs.contains("SURREAL_PASSWORD environment variable not set"));
+        assert!(logs.contains("variable=\"SURREAL_PASSWORD\""));
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::{BTreeMap, HashSet};
+
+use surreal_socket::{dbrecord::DBRecord, error::SurrealSocketError};
+
+use crate::{
+    enrich::DIAMETER_SNAP_TOLERANCE,
+    event::{ProductEvent, ProductEventRecord},
+    product::{Cents, FilamentDiameter, Grams, Product, Retailer, normalize_color},
+    surrealdb_client,
+};
+
+/// Net weights, in grams, that spools are commonly sold in
+const STANDARD_WEIGHTS: [u16; 7] = [250, 500, 750, 1000, 2000, 3000, 5000];
+
+/// How far, as a percentage, a weight may be from a standard one for `fuzzy_cluster_key` to
+/// snap it
+const WEIGHT_SNAP_PERCENT: u16 = 3;
+
+/// Retailers in order of preference for the canonical listing of a duplicate group. Retailers
+/// not listed rank after all listed ones, equally.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetailerPriority(pub Vec<Retailer>);
+
+impl Default for RetailerPriority {
+    fn default() -> Self {
+        Self(vec![Retailer::Amazon])
+    }
+}
+
+impl RetailerPriority {
+    /// Position of `retailer`, lower being preferred
+    pub fn rank(&self, retailer: &Retailer) -> usize {
+        self.0
+            .iter()
+            .position(|r| r == retailer)
+            .unwrap_or(self.0.len())
+    }
+}
+
+/// Listings of the same filament, as identified by `Product::content_signature`
+#[derive(Clone, PartialEq)]
+pub struct DuplicateGroup {
+    pub signature: String,
+    pub canonical: Product,
+    pub duplicates: Vec<Product>,
+}
+
+impl DuplicateGroup {
+    /// Moves the duplicates' recorded price changes to the canonical product, so its history
+    /// is one chronological series. Changes the canonical product already has at the same
+    /// time and price are deleted instead. Returns how many were moved.
+    pub async fn merge_price_histories(&self) -> Result<u64, SurrealSocketError> {
+        let canonical = ProductEventRecord::for_product(self.canonical.uuid()).await?;
+
+        let mut records = Vec::new();
+        for duplicate in &self.duplicates {
+            records.extend(ProductEventRecord::for_product(duplicate.uuid()).await?);
+        }
+
+        let (moved, repeated) = split_duplicate_history(&canonical, records);
+
+        let client = surrealdb_client().await?;
+        let table = ProductEventRecord::table();
+        let uuid_field = ProductEventRecord::UUID_FIELD;
+
+        let updates = moved.iter().map(|record| {
+            (
+                format!("UPDATE {table} SET product_uuid = $canonical WHERE {uuid_field} = $uuid"),
+                record,
+            )
+        });
+        let deletes = repeated
+            .iter()
+            .map(|record| (format!("DELETE {table} WHERE {uuid_field} = $uuid"), record));
+
+        for (query, record) in updates.chain(deletes) {
+            client
+                .query(query)
+                .bind(("canonical", self.canonical.uuid()))
+                .bind(("uuid", record.uuid()))
+                .await?
+                .check()?;
+        }
+
+        Ok(moved.len() as u64)
+    }
+}
+
+/// Splits the duplicates' price change `records` into those to move to the canonical
+/// product and those repeating a change (same time and price) it already has or an earlier
+/// record brings, which are to be deleted. Other events are left out of both.
+fn split_duplicate_history(
+    canonical: &[ProductEventRecord],
+    records: Vec<ProductEventRecord>,
+) -> (Vec<ProductEventRecord>, Vec<ProductEventRecord>) {
+    let key = |r: &ProductEventRecord| match r.event {
+        ProductEvent::PriceChanged { current, .. } => {
+            Some((r.occurred_at.timestamp_millis(), current))
+        }
+        _ => None,
+    };
+
+    let mut seen: HashSet<(i64, Cents)> = canonical.iter().filter_map(key).collect();
+    let mut moved = Vec::new();
+    let mut repeated = Vec::new();
+
+    for record in records {
+        let Some(k) = key(&record) else {
+            continue;
+        };
+
+        if seen.insert(k) {
+            moved.push(record);
+        } else {
+            repeated.push(record);
+        }
+    }
+
+    (moved, repeated)
+}
+
+/// Groups `products` sharing a content signature, keeping the listing from the highest
+/// priority retailer as canonical (the earliest created among equals). Products without a
+/// duplicate are left out. Groups are ordered by signature.
+pub fn group_duplicates(
+    products: Vec<Product>,
+    priority: &RetailerPriority,
+) -> Vec<DuplicateGroup> {
+    let mut by_signature: BTreeMap<String, Vec<Product>> = BTreeMap::new();
+
+    for product in products {
+        by_signature
+            .entry(product.content_signature())
+            .or_default()
+            .push(product);
+    }
+
+    by_signature
+        .into_iter()
+        .filter(|(_, listings)| listings.len() > 1)
+        .map(|(signature, mut listings)| {
+            listings.sort_by_key(|p| (priority.rank(&p.retailer), p.created_at));
+            let canonical = listings.remove(0);
+
+            DuplicateGroup {
+                signature,
+                canonical,
+                duplicates: listings,
+            }
+        })
+        .collect()
+}
+
+impl Product {
+    /// Stable hex signature of the normalized brand, material, diameter, weight and color,
+    /// shared by listings of the same filament across retailers
+    pub fn content_signature(&self) -> String {
+        self.signature_with(self.diameter, self.weight)
+    }
+
+    /// `content_signature` with the diameter snapped to a standard one and the weight to
+    /// the nearest of `STANDARD_WEIGHTS` within `WEIGHT_SNAP_PERCENT`, so near-identical
+    /// listings (995 g and 1000 g, 1.74 mm and 1.75 mm) share a key
+    pub fn fuzzy_cluster_key(&self) -> String {
+        let diameter = self.diameter.snap_to_standard(DIAMETER_SNAP_TOLERANCE);
+
+        let weight = STANDARD_WEIGHTS
+            .iter()
+            .map(|standard| (standard, standard.abs_diff(self.weight.0)))
+            .filter(|(standard, diff)| {
+                u32::from(*diff) * 100 <= u32::from(**standard) * u32::from(WEIGHT_SNAP_PERCENT)
+            })
+            .min_by_key(|(_, diff)| *diff)
+            .map_or(self.weight, |(standard, _)| Grams(*standard));
+
+        self.signature_with(diameter, weight)
+    }
+
+    fn signature_with(&self, diameter: FilamentDiameter, weight: Grams) -> String {
+        let brand = self
+            .brand
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ")
+            .to_lowercase();
+
+        let key = [
+            brand,
+            self.material.to_string(),
+            u16::from(diameter).to_string(),
+            weight.0.to_string(),
+            normalize_color(&self.color).to_lowercase(),
+        ]
+        .join("\u{1f}");
+
+        format!("{:016x}", fnv1a_64(key.as_bytes()))
+    }
+
+    /// Other listings by the same retailer with the same content signature but a different
+    /// retailer product id, likely this product relisted under a new id. Operators can use
+    /// them to stitch price and availability history together.
+    pub async fn find_relist_candidates(&self) -> Result<Vec<Product>, SurrealSocketError> {
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT * FROM {} WHERE retailer = $retailer AND material = $material \
+             AND diameter = $diameter AND weight = $weight \
+             AND retailer_product_id != $retailer_product_id",
+            Self::table()
+        );
+
+        let mut response = client
+            .query(query)
+            .bind(("retailer", self.retailer.to_string()))
+            .bind(("material", self.material.to_string()))
+            .bind(("diameter", u16::from(self.diameter)))
+            .bind(("weight", self.weight.0))
+            .bind(("retailer_product_id", self.retailer_product_id.clone()))
+            .await?;
+
+        let candidates: Vec<Product> = response.take(0)?;
+
+        Ok(candidates
+            .into_iter()
+            .filter(|p| p.is_relist_of(self))
+            .collect())
+    }
+
+    /// Whether this is `other` listed again by the same retailer under a different id
+    fn is_relist_of(&self, other: &Product) -> bool {
+        self.retailer == other.retailer
+            && self.retailer_product_id != other.retailer_product_id
+            && self.content_signature() == other.content_signature()
+    }
+}
+
+impl Retailer {
+    /// Product id derived from `url` for listings scraped without one. Amazon URLs yield
+    /// their ASIN; otherwise the host and path, ignoring scheme, `www.`, query, fragment and
+    /// trailing slash, are hashed so the same page always gets the same id.
+    pub fn synthetic_product_id(&self, url: &str) -> String {
+        let without_scheme = url
+            .trim()
+            .split_once("://")
+            .map_or(url.trim(), |(_, rest)| rest);
+        let page = without_scheme
+            .split(['?', '#'])
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches('/');
+
+        let (host, path) = page.split_once('/').unwrap_or((page, ""));
+        let host = host.to_lowercase();
+        let host = host.strip_prefix("www.").unwrap_or(&host);
+
+        if matches!(self, Self::Amazon)
+            && let Some(asin) = path
+                .split_once("dp/")
+                .or_else(|| path.split_once("gp/product/"))
+                .and_then(|(_, rest)| rest.split('/').next())
+                .filter(|asin| asin.len() == 10 && asin.bytes().all(|b| b.is_ascii_alphanumeric()))
+        {
+            return asin.to_uppercase();
+        }
+
+        let key = format!("{}/{}", host, path);
+        format!("url-{:016x}", fnv1a_64(key.as_bytes()))
+    }
+}
+
+/// 64-bit FNV-1a, used instead of `DefaultHasher` because its output must not change
+/// between Rust releases
+fn fnv1a_64(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_ignores_retailer_and_price() {
+        let amazon = Product::example();
+        let elsewhere = Product {
+            retailer: Retailer::Other("SomeShop".to_owned()),
+            retailer_product_id: "HB-PLA-BLK".to_owned(),
+            price: Cents(1899),
+            brand: Some("  HATCHBOX ".to_owned()),
+            color: "black".to_owned(),
+            ..Product::example()
+        };
+        assert_eq!(amazon.content_signature(), elsewhere.content_signature());
+
+        let white = Product {
+            color: "White".to_owned(),
+            ..Product::example()
+        };
+        assert_ne!(amazon.content_signature(), white.content_signature());
+    }
+
+    #[test]
+    fn synthetic_ids_follow_the_page() {
+        let shop = Retailer::Other("SomeShop".to_owned());
+        let id = shop.synthetic_product_id("https://www.someshop.example/pla/black/");
+
+        assert!(id.starts_with("url-"));
+        assert_eq!(
+            shop.synthetic_product_id("http://someshop.example/pla/black?utm_source=x#reviews"),
+            id
+        );
+        assert_ne!(
+            shop.synthetic_product_id("https://someshop.example/pla/white"),
+            id
+        );
+
+        assert_eq!(
+            Retailer::Amazon
+                .synthetic_product_id("https://www.amazon.com/Hatchbox-PLA/dp/b00j0gmmp6/ref=x"),
+            "B00J0GMMP6"
+        );
+    }
+
+    #[test]
+    fn higher_priority_retailer_is_canonical() {
+        let amazon = Product::example();
+        let elsewhere = Product {
+            retailer: Retailer::Other("SomeShop".to_owned()),
+            created_at: amazon.created_at - chrono::Duration::days(30),
+            ..Product::example()
+        };
+        let unrelated = Product {
+            color: "Red".to_owned(),
+            ..Product::example()
+        };
+
+        let groups = group_duplicates(
+            vec![elsewhere.clone(), unrelated, amazon.clone()],
+            &RetailerPriority::default(),
+        );
+
+        assert_eq!(groups.len(), 1);
+        assert!(groups[0].canonical == amazon);
+        assert!(groups[0].duplicates == vec![elsewhere.clone()]);
+
+        let shop_first = RetailerPriority(vec![elsewhere.retailer.clone(), Retailer::Amazon]);
+        let groups = group_duplicates(vec![amazon, elsewhere.clone()], &shop_first);
+        assert!(groups[0].canonical == elsewhere);
+    }
+
+    #[test]
+    fn relisted_product_is_a_candidate() {
+        let old = Product::example();
+        let relisted = Product {
+            retailer_product_id: "B0NEWLIST1".to_owned(),
+            url: "https://www.amazon.com/dp/B0NEWLIST1".to_owned(),
+            price: Cents(1999),
+            ..Product::example()
+        };
+
+        assert!(relisted.is_relist_of(&old));
+        assert!(old.is_relist_of(&relisted));
+
+        let same_listing = Product::example();
+        let other_retailer = Product {
+            retailer: Retailer::Other("SomeShop".to_owned()),
+            ..relisted.clone()
+        };
+        let other_color = Product {
+            color: "White".to_owned(),
+            ..relisted.clone()
+        };
+
+        for product in [same_listing, other_retailer, other_color] {
+            assert!(!product.is_relist_of(&old));
+        }
+    }
+
+    #[test]
+    fn merged_history_is_sorted_and_deduplicated() {
+        let canonical = Product::example();
+        let duplicate = Product::example();
+        let day =
+            |n: i64| crate::clock::FixedClock(canonical.created_at + chrono::Duration::days(n));
+        let change = |product: &Product, n: i64, previous: u32, current: u32| {
+            let event = ProductEvent::PriceChanged {
+                previous: Cents(previous),
+                current: Cents(current),
+            };
+            ProductEventRecord::new(product.uuid(), event, &day(n))
+        };
+
+        let canonical_history = [change(&canonical, 1, 2499, 2299)];
+        let duplicate_history = vec![
+            change(&duplicate, 3, 2099, 2199),
+            change(&duplicate, 1, 2499, 2299),
+            ProductEventRecord::new(duplicate.uuid(), ProductEvent::BackInStock, &day(2)),
+            change(&duplicate, 2, 2299, 2099),
+        ];
+
+        let (moved, repeated) = split_duplicate_history(&canonical_history, duplicate_history);
+
+        assert_eq!(moved.len(), 2);
+        assert_eq!(repeated.len(), 1);
+        assert_eq!(repeated[0].occurred_at, canonical_history[0].occurred_at);
+
+        let mut combined: Vec<ProductEventRecord> =
+            canonical_history.iter().cloned().chain(moved).collect();
+        combined.sort_by_key(|r| r.occurred_at);
+
+        let series: Vec<Cents> = combined
+            .iter()
+            .filter_map(|r| match r.event {
+                ProductEvent::PriceChanged { current, .. } => Some(current),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(series, vec![Cents(2299), Cents(2099), Cents(2199)]);
+    }
+
+    #[test]
+    fn near_identical_weights_share_a_fuzzy_key() {
+        let full = Product::example();
+        let short = Product {
+            weight: Grams(995),
+            diameter: FilamentDiameter::Other(174),
+            ..Product::example()
+        };
+        let half = Product {
+            weight: Grams(500),
+            ..Product::example()
+        };
+
+        assert_ne!(full.content_signature(), short.content_signature());
+        assert_eq!(full.fuzzy_cluster_key(), short.fuzzy_cluster_key());
+        assert_ne!(full.fuzzy_cluster_key(), half.fuzzy_cluster_key());
+
+        let odd = Product {
+            weight: Grams(1200),
+            ..Product::example()
+        };
+        assert_eq!(odd.fuzzy_cluster_key(), odd.content_signature());
+    }
+}
@@ -0,0 +1,968 @@
+use surreal_socket::{dbrecord::DBRecord, error::SurrealSocketError};
+
+use crate::{
+    product::{
+        Availability, Cents, Currency, FilamentDiameter, FilamentMaterial, Grams, Product,
+        ProductRequest, Retailer, SpoolType, normalize_color,
+    },
+    surrealdb_client,
+};
+
+/// How long `ProductRequest::from_url` waits for a product page
+#[cfg(feature = "reqwest")]
+const PAGE_FETCH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Largest pack size taken from a listing, beyond which a number is more likely something else
+const MAX_PACK_QUANTITY: u16 = 50;
+
+/// Distance in hundredths of a millimeter within which `normalize` snaps a diameter to a
+/// standard one
+pub(crate) const DIAMETER_SNAP_TOLERANCE: u16 = 5;
+
+/// Words that follow the spool count in names like "4 Pack" or "3 Rolls"
+const PACK_WORDS: &[&str] = &["pack", "pk", "spools", "rolls", "count", "ct"];
+
+/// Amazon storefront hosts and the ISO country code each sells in
+const AMAZON_REGIONS: &[(&str, &str)] = &[
+    ("amazon.com", "US"),
+    ("amazon.ca", "CA"),
+    ("amazon.com.mx", "MX"),
+    ("amazon.com.br", "BR"),
+    ("amazon.co.uk", "GB"),
+    ("amazon.de", "DE"),
+    ("amazon.fr", "FR"),
+    ("amazon.it", "IT"),
+    ("amazon.es", "ES"),
+    ("amazon.nl", "NL"),
+    ("amazon.se", "SE"),
+    ("amazon.pl", "PL"),
+    ("amazon.com.tr", "TR"),
+    ("amazon.ae", "AE"),
+    ("amazon.in", "IN"),
+    ("amazon.sg", "SG"),
+    ("amazon.co.jp", "JP"),
+    ("amazon.com.au", "AU"),
+];
+
+impl FilamentMaterial {
+    /// Material named in free text such as a listing title, e.g. "PLA+" in
+    /// "eSUN PLA+ 1.75mm 1kg Black"
+    pub fn infer_from_text(text: &str) -> Option<FilamentMaterial> {
+        Self::infer_with_confidence(text).map(|(material, _)| material)
+    }
+
+    /// `infer_from_text` with a confidence below 1.0: 0.9 for the material's own name, less
+    /// for indirect or ambiguous keywords such as "PLA Pro", "PA6" or "PC"
+    pub fn infer_with_confidence(text: &str) -> Option<(FilamentMaterial, f32)> {
+        let upper = text.to_uppercase();
+        let words = words(&upper);
+        let has = |word: &str| words.contains(&word);
+
+        Some(if has("PLA+") {
+            (Self::PLAPlus, 0.9)
+        } else if has("PLA") && (has("PLUS") || has("PRO")) {
+            (Self::PLAPlus, 0.7)
+        } else if has("PCTG") {
+            (Self::PCTG, 0.9)
+        } else if has("PETG") {
+            (Self::PETG, 0.9)
+        } else if has("PLA") {
+            (Self::PLA, 0.9)
+        } else if has("ABS") {
+            (Self::ABS, 0.9)
+        } else if has("ASA") {
+            (Self::ASA, 0.9)
+        } else if has("TPU") {
+            (Self::TPU, 0.9)
+        } else if has("NYLON") {
+            (Self::Nylon, 0.9)
+        } else if has("PA6") || has("PA12") {
+            (Self::Nylon, 0.7)
+        } else if has("POLYCARBONATE") {
+            (Self::PC, 0.9)
+        } else if has("PC") {
+            (Self::PC, 0.5)
+        } else {
+            return None;
+        })
+    }
+
+    /// Whether the material still has to be determined
+    pub fn is_undetermined(&self) -> bool {
+        matches!(self, Self::Unspecified | Self::Unknown)
+    }
+}
+
+impl FilamentDiameter {
+    /// Diameter given in millimeters in free text, e.g. "1.75mm" or "2.85 mm"
+    pub fn infer_from_text(text: &str) -> Option<FilamentDiameter> {
+        quantities(text)
+            .into_iter()
+            .find(|(value, unit)| unit == "mm" && (1.0..=3.5).contains(value))
+            .and_then(|(value, _)| FilamentDiameter::from_mm(value))
+    }
+}
+
+impl SpoolType {
+    /// Spool type named in free text, e.g. "refill" in "Prusament PLA Refill 1kg". Refills
+    /// win over masterspool mentions, as refill listings often name the spool they fit.
+    pub fn infer_from_text(text: &str) -> Option<SpoolType> {
+        let lower = text.to_lowercase();
+        let words = words(&lower);
+        let has = |word: &str| words.contains(&word);
+        let has_pair = |first: &str, second: &str| {
+            words
+                .windows(2)
+                .any(|pair| pair[0] == first && pair[1] == second)
+        };
+
+        if has("refill") || has("spoolless") {
+            Some(SpoolType::Refill)
+        } else if has("masterspool") || has_pair("master", "spool") || has_pair("reusable", "spool")
+        {
+            Some(SpoolType::Reusable)
+        } else if has("cardboard") {
+            Some(SpoolType::Cardboard)
+        } else if has_pair("plastic", "spool") {
+            Some(SpoolType::Plastic)
+        } else {
+            None
+        }
+    }
+}
+
+impl Grams {
+    /// Weight given in free text, e.g. "1kg", "0.5 kg" or "250g"
+    pub fn infer_from_text(text: &str) -> Option<Grams> {
+        quantities(text).into_iter().find_map(|(value, unit)| {
+            let grams = match unit.as_str() {
+                "kg" | "kgs" => value * 1000.0,
+                "g" | "gr" | "gram" | "grams" => value,
+                _ => return None,
+            }
+            .round();
+
+            (grams > 0.0 && grams <= u16::MAX as f32).then_some(Grams(grams as u16))
+        })
+    }
+}
+
+/// Amazon short link hosts, which identify the retailer but not the storefront
+const AMAZON_SHORT_HOSTS: [&str; 2] = ["amzn.to", "amzn.eu"];
+
+/// Settings for `Product::enrich_with`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EnrichConfig {
+    /// Retailer for products ingested with a blank retailer whose URL doesn't identify one.
+    /// Without it such products keep their blank `Other("")` retailer.
+    pub default_retailer: Option<Retailer>,
+}
+
+impl EnrichConfig {
+    /// Reads the default retailer from `FILAMENTSEEK_DEFAULT_RETAILER`, where unset or blank
+    /// means none
+    pub fn from_env() -> Self {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    /// `from_env` reading variables through `lookup`
+    fn from_lookup(lookup: impl Fn(&'static str) -> Option<String>) -> Self {
+        Self {
+            default_retailer: lookup("FILAMENTSEEK_DEFAULT_RETAILER")
+                .map(|r| r.trim().to_owned())
+                .filter(|r| !r.is_empty())
+                .map(Retailer::from),
+        }
+    }
+}
+
+impl Retailer {
+    /// Retailer whose domain `url` is on, if it is a known one
+    pub fn infer_from_url(url: &str) -> Option<Retailer> {
+        let host = url_host(url);
+
+        (AMAZON_REGIONS
+            .iter()
+            .any(|(storefront, _)| *storefront == host)
+            || AMAZON_SHORT_HOSTS.contains(&host.as_str()))
+        .then_some(Retailer::Amazon)
+    }
+
+    /// Country the storefront at `url` sells in, for retailers whose domain identifies it
+    pub fn infer_region(&self, url: &str) -> Option<&'static str> {
+        if !matches!(self, Self::Amazon) {
+            return None;
+        }
+
+        let host = url_host(url);
+
+        AMAZON_REGIONS
+            .iter()
+            .find(|(storefront, _)| *storefront == host)
+            .map(|(_, region)| *region)
+    }
+
+    /// Whether this is an `Other` retailer with a blank name, as left by URL-only ingestion
+    fn is_blank(&self) -> bool {
+        matches!(self, Self::Other(name) if name.trim().is_empty())
+    }
+}
+
+impl Product {
+    /// `enrich_with` the config from the environment
+    pub fn enrich(&mut self) -> bool {
+        self.enrich_with(&EnrichConfig::from_env())
+    }
+
+    /// Fills an undetermined material, a missing weight, pack quantity and spool type from
+    /// the product name, and a blank retailer and missing region from the URL. A blank retailer
+    /// the URL doesn't identify is set to `config.default_retailer`, if any. Returns whether
+    /// anything changed.
+    pub fn enrich_with(&mut self, config: &EnrichConfig) -> bool {
+        let mut changed = false;
+
+        if self.retailer.is_blank()
+            && let Some(retailer) =
+                Retailer::infer_from_url(&self.url).or_else(|| config.default_retailer.clone())
+        {
+            self.retailer = retailer;
+            changed = true;
+        }
+
+        if self.material.is_undetermined()
+            && let Some((material, confidence)) =
+                FilamentMaterial::infer_with_confidence(&self.name)
+        {
+            self.material = material;
+            self.material_confidence = Some(confidence);
+            changed = true;
+        }
+
+        if self.weight.0 == 0
+            && let Some(weight) = Grams::infer_from_text(&self.name)
+        {
+            self.weight = weight;
+            self.price_per_kg = self.calculate_price_per_kg();
+            changed = true;
+        }
+
+        if self.pack_quantity.is_none()
+            && let Some(pack_quantity) = self.detect_pack_quantity()
+        {
+            self.pack_quantity = Some(pack_quantity);
+            changed = true;
+        }
+
+        if self.region.is_none()
+            && let Some(region) = self.retailer.infer_region(&self.url)
+        {
+            self.region = Some(region.to_owned());
+            changed = true;
+        }
+
+        if self.spool_type == SpoolType::Unknown
+            && let Some(spool_type) = SpoolType::infer_from_text(&self.name)
+        {
+            self.spool_type = spool_type;
+            changed = true;
+        }
+
+        changed
+    }
+
+    /// Most reliable material available with its confidence: the `material` field when it
+    /// is determined (at its recorded `material_confidence`), else one inferred from the
+    /// name, else the undetermined material at 0.0. Doesn't modify the product; see `enrich`.
+    pub fn resolve_material(&self) -> (FilamentMaterial, f32) {
+        if !self.material.is_undetermined() {
+            return (self.material.clone(), self.material_confidence());
+        }
+
+        FilamentMaterial::infer_with_confidence(&self.name).unwrap_or((self.material.clone(), 0.0))
+    }
+
+    /// Whether the diameter stated in the name agrees with `diameter`, or `None` when the
+    /// name states none
+    pub fn diameter_matches_name(&self) -> Option<bool> {
+        FilamentDiameter::infer_from_text(&self.name).map(|diameter| diameter == self.diameter)
+    }
+
+    /// Collapses runs of whitespace in the name, color and brand to single spaces and trims
+    /// them. Tabs, newlines and non-breaking spaces all count as whitespace. An all-blank
+    /// brand becomes `None`.
+    pub fn normalize_whitespace(&mut self) {
+        let collapse = |s: &str| s.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+        self.name = collapse(&self.name);
+        self.color = collapse(&self.color);
+        self.brand = self
+            .brand
+            .as_deref()
+            .map(collapse)
+            .filter(|b| !b.is_empty());
+    }
+
+    /// Cleans up fields as entered by scrapers before the product is persisted: collapses
+    /// whitespace (see `normalize_whitespace`), normalizes the color, resolves an `Other`
+    /// material naming a known one (e.g. "petg") and snaps a near-standard diameter.
+    /// Idempotent.
+    pub fn normalize(&mut self) {
+        self.normalize_whitespace();
+
+        self.color = normalize_color(&self.color);
+
+        if let FilamentMaterial::Other(other) = &self.material {
+            let other = other.trim();
+
+            self.material = match FilamentMaterial::infer_from_text(other) {
+                Some(material) if words(other).len() == 1 => material,
+                _ => FilamentMaterial::Other(other.to_owned()),
+            };
+        }
+
+        self.diameter = self.diameter.snap_to_standard(DIAMETER_SNAP_TOLERANCE);
+    }
+
+    /// Number of spools in a multi-spool bundle, from counts in the name such as "4 Pack",
+    /// "Pack of 3", "4-pk" or "2x1kg", or else from a total weight that is a multiple of the
+    /// per-spool weight the name states. `None` for single spools and when the two disagree.
+    pub fn detect_pack_quantity(&self) -> Option<u16> {
+        let words = words(&self.name);
+        let lower: Vec<String> = words.iter().map(|w| w.to_lowercase()).collect();
+        let pack_size = |n: u16| (2..=MAX_PACK_QUANTITY).contains(&n).then_some(n);
+
+        let from_name = lower.iter().enumerate().find_map(|(i, word)| {
+            let next = lower.get(i + 1).map(String::as_str);
+
+            if let Ok(n) = word.parse::<u16>()
+                && next.is_some_and(|next| PACK_WORDS.contains(&next))
+            {
+                return pack_size(n);
+            }
+
+            if word == "pack" && next == Some("of") {
+                return lower.get(i + 2)?.parse().ok().and_then(pack_size);
+            }
+
+            let digits = word.len() - word.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let (count, rest) = word.split_at(digits);
+
+            if !count.is_empty()
+                && (PACK_WORDS.contains(&rest)
+                    || rest.strip_prefix('x').is_some_and(|r| {
+                        r.is_empty() || r.starts_with(|c: char| c.is_ascii_digit())
+                    }))
+            {
+                return count.parse().ok().and_then(pack_size);
+            }
+
+            None
+        });
+
+        let from_weight = Grams::infer_from_text(&self.name)
+            .filter(|per_spool| per_spool.0 > 0 && self.weight > *per_spool)
+            .and_then(|per_spool| {
+                let ratio = self.weight.0 as f32 / per_spool.0 as f32;
+                ((ratio - ratio.round()).abs() <= 0.05).then(|| ratio.round() as u16)
+            })
+            .and_then(pack_size);
+
+        match (from_name, from_weight) {
+            (Some(name), Some(weight)) if name != weight => None,
+            (name, weight) => name.or(weight),
+        }
+    }
+
+    /// Runs `enrich` over stored products with an undetermined material, no weight or an
+    /// Amazon listing without a region, and saves those it improved. Returns how many were improved.
+    pub async fn reenrich_incomplete() -> Result<u64, SurrealSocketError> {
+        let config = EnrichConfig::from_env();
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT * FROM {} WHERE material IN ['Unspecified', 'Unknown'] OR weight = 0 \
+             OR (retailer = 'Amazon' AND region = NONE)",
+            Self::table()
+        );
+
+        let products: Vec<Product> = client.query(query).await?.take(0)?;
+        let mut improved = 0;
+
+        for mut product in products {
+            if product.enrich_with(&config) {
+                product.save().await?;
+                improved += 1;
+            }
+        }
+
+        Ok(improved)
+    }
+}
+
+/// Failure to pre-fill a `ProductRequest` from a product page
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EnrichError {
+    /// The page couldn't be fetched
+    Fetch(String),
+    /// The page answered with a non-success status
+    Status(u16),
+    /// The page has neither an `og:title` nor a `<title>`
+    MissingTitle,
+    /// The page states a price that can't be read
+    InvalidPrice(String),
+}
+
+impl std::fmt::Display for EnrichError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fetch(e) => write!(f, "Error fetching product page: {}", e),
+            Self::Status(status) => write!(f, "Product page returned status {}", status),
+            Self::MissingTitle => write!(f, "Product page has no title"),
+            Self::InvalidPrice(e) => write!(f, "Product page price is invalid: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EnrichError {}
+
+impl From<EnrichError> for String {
+    fn from(e: EnrichError) -> String {
+        e.to_string()
+    }
+}
+
+impl ProductRequest {
+    /// Fetches the product page at `url` and pre-fills a request from it, as `from_html`
+    #[cfg(feature = "reqwest")]
+    pub async fn from_url(url: &str) -> Result<ProductRequest, EnrichError> {
+        let response = reqwest::Client::builder()
+            .timeout(PAGE_FETCH_TIMEOUT)
+            .build()
+            .map_err(|e| EnrichError::Fetch(e.to_string()))?
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| EnrichError::Fetch(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(EnrichError::Status(response.status().as_u16()));
+        }
+
+        let html = response
+            .text()
+            .await
+            .map_err(|e| EnrichError::Fetch(e.to_string()))?;
+
+        Self::from_html(url, &html)
+    }
+
+    /// Request pre-filled from the HTML of the product page at `url`: the name from
+    /// `og:title` (or `<title>`), price and currency from `product:price:*` or `og:price:*`
+    /// meta tags, material, diameter, weight and spool type inferred from the name, and the
+    /// retailer, region and a synthetic product id from the URL. A missing price is left at
+    /// zero, and a diameter the name doesn't state at 1.75 mm, for review before insert.
+    pub fn from_html(url: &str, html: &str) -> Result<ProductRequest, EnrichError> {
+        let meta = meta_tags(html);
+        let meta_value = |keys: &[&str]| {
+            keys.iter().find_map(|key| {
+                meta.iter()
+                    .find(|(name, _)| name == key)
+                    .map(|(_, content)| content.trim().to_owned())
+                    .filter(|content| !content.is_empty())
+            })
+        };
+
+        let name = meta_value(&["og:title", "twitter:title"])
+            .or_else(|| html_title(html))
+            .ok_or(EnrichError::MissingTitle)?;
+
+        let currency = meta_value(&[
+            "product:price:currency",
+            "og:price:currency",
+            "priceCurrency",
+        ])
+        .map(|c| Currency::from(c.to_uppercase()))
+        .unwrap_or_default();
+
+        let price = meta_value(&["product:price:amount", "og:price:amount", "price"])
+            .map(|amount| Cents::from_amount_str(&amount, &currency, None))
+            .transpose()
+            .map_err(EnrichError::InvalidPrice)?
+            .unwrap_or(Cents(0));
+
+        let retailer =
+            Retailer::infer_from_url(url).unwrap_or_else(|| Retailer::Other(url_host(url)));
+        let (material, _) = FilamentMaterial::infer_with_confidence(&name)
+            .unwrap_or((FilamentMaterial::Unspecified, 0.0));
+
+        Ok(ProductRequest {
+            brand: None,
+            price,
+            currency,
+            url: url.to_owned(),
+            material,
+            diameter: FilamentDiameter::infer_from_text(&name).unwrap_or(FilamentDiameter::D175),
+            weight: Grams::infer_from_text(&name).unwrap_or(Grams(0)),
+            retailer_product_id: retailer.synthetic_product_id(url),
+            region: retailer.infer_region(url).map(str::to_owned),
+            retailer,
+            color: String::new(),
+            availability: Availability::Unknown,
+            source: Some("page-metadata".to_owned()),
+            spool_weight: None,
+            pack_quantity: None,
+            nozzle_temp: None,
+            bed_temp: None,
+            stock_quantity: None,
+            list_price: None,
+            tags: Vec::new(),
+            shipping: None,
+            spool_type: SpoolType::infer_from_text(&name).unwrap_or_default(),
+            name,
+        })
+    }
+}
+
+/// (`property`, `name` or `itemprop`; `content`) of each `<meta>` tag in `html`, with
+/// entities in the content decoded
+fn meta_tags(html: &str) -> Vec<(String, String)> {
+    let lower = html.to_ascii_lowercase();
+    let mut tags = Vec::new();
+    let mut rest = 0;
+
+    while let Some(start) = lower[rest..].find("<meta") {
+        let start = rest + start;
+        let Some(len) = lower[start..].find('>') else {
+            break;
+        };
+        let tag = &html[start..start + len];
+        rest = start + len;
+
+        let key = ["property", "name", "itemprop"]
+            .iter()
+            .find_map(|attribute| html_attribute(tag, attribute));
+
+        if let Some(key) = key
+            && let Some(content) = html_attribute(tag, "content")
+        {
+            tags.push((key, unescape_html(&content)));
+        }
+    }
+
+    tags
+}
+
+/// Value of `attribute` in the start tag `tag`, quoted with `"` or `'`
+fn html_attribute(tag: &str, attribute: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut from = 0;
+
+    while let Some(found) = lower[from..].find(attribute) {
+        let at = from + found;
+        from = at + attribute.len();
+
+        let preceded_by_space = lower[..at].ends_with(|c: char| c.is_whitespace());
+        let after = lower[from..].trim_start();
+
+        if !preceded_by_space || !after.starts_with('=') {
+            continue;
+        }
+
+        let value_start = tag.len() - after.len() + 1;
+        let value = tag[value_start..].trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let value = &value[1..];
+
+        return value.find(quote).map(|end| value[..end].to_owned());
+    }
+
+    None
+}
+
+/// Text of the `<title>` element, whitespace collapsed and entities decoded
+fn html_title(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+
+    let title = unescape_html(&html[start..end])
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    (!title.is_empty()).then_some(title)
+}
+
+/// Decodes the entities common in titles and meta content
+fn unescape_html(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// Lowercased host of `url` without `www.`
+fn url_host(url: &str) -> String {
+    let without_scheme = url
+        .trim()
+        .split_once("://")
+        .map_or(url.trim(), |(_, rest)| rest);
+    let host = without_scheme
+        .split(['/', '?', '#', ':'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+
+    host.strip_prefix("www.").map(str::to_owned).unwrap_or(host)
+}
+
+/// Words split on anything but letters, digits and `+`
+fn words(text: &str) -> Vec<&str> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '+'))
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+/// Numbers directly followed by a unit, e.g. "1.75mm" or "1 kg" as (1.75, "mm") and
+/// (1.0, "kg"). Units are lowercased.
+fn quantities(text: &str) -> Vec<(f32, String)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut found = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let starts_number =
+            chars[i].is_ascii_digit() && (i == 0 || !chars[i - 1].is_alphanumeric());
+
+        if !starts_number {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+            i += 1;
+        }
+        let number: String = chars[start..i].iter().collect();
+
+        let mut j = i;
+        while j < chars.len() && chars[j] == ' ' {
+            j += 1;
+        }
+        let unit_start = j;
+        while j < chars.len() && chars[j].is_alphabetic() {
+            j += 1;
+        }
+        let unit: String = chars[unit_start..j]
+            .iter()
+            .collect::<String>()
+            .to_lowercase();
+
+        if !unit.is_empty()
+            && let Ok(value) = number.trim_end_matches('.').parse::<f32>()
+        {
+            found.push((value, unit));
+        }
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enrich_fills_material_and_weight_from_name() {
+        let mut product = Product {
+            name: "eSUN PETG 1.75mm 0.5kg Blue".to_owned(),
+            material: FilamentMaterial::Unknown,
+            material_confidence: Some(0.0),
+            weight: Grams(0),
+            price: Cents(1200),
+            ..Product::example()
+        };
+
+        assert!(product.enrich());
+        assert_eq!(product.material, FilamentMaterial::PETG);
+        assert_eq!(product.material_confidence, Some(0.9));
+        assert_eq!(product.weight, Grams(500));
+        assert_eq!(product.price_per_kg.0, 2400);
+
+        assert!(!product.enrich());
+    }
+
+    #[test]
+    fn enrich_keeps_known_fields() {
+        let mut product = Product {
+            name: "Hatchbox ABS 2kg".to_owned(),
+            ..Product::example()
+        };
+
+        assert!(!product.enrich());
+        assert_eq!(product.material, FilamentMaterial::PLA);
+        assert_eq!(product.weight, Grams(1000));
+    }
+
+    #[test]
+    fn diameter_in_name_agrees_conflicts_or_is_absent() {
+        let named = |name: &str| Product {
+            name: name.to_owned(),
+            ..Product::example()
+        };
+
+        assert_eq!(
+            named("eSUN PLA+ 1.75mm 1kg").diameter_matches_name(),
+            Some(true)
+        );
+        assert_eq!(
+            named("eSUN PLA+ 2.85 mm 1kg").diameter_matches_name(),
+            Some(false)
+        );
+        assert_eq!(named("eSUN PLA+ 1kg Black").diameter_matches_name(), None);
+    }
+
+    #[test]
+    fn region_inferred_from_amazon_storefront() {
+        let url = "https://www.amazon.co.uk/dp/B0ABC123XY";
+
+        assert_eq!(Retailer::Amazon.infer_region(url), Some("GB"));
+        assert_eq!(
+            Retailer::Amazon.infer_region("https://www.amazon.com/dp/B0ABC123XY"),
+            Some("US")
+        );
+        assert_eq!(Retailer::Other("Shop".to_owned()).infer_region(url), None);
+
+        let mut product = Product {
+            url: url.to_owned(),
+            region: None,
+            ..Product::example()
+        };
+        assert!(product.enrich());
+        assert_eq!(product.region.as_deref(), Some("GB"));
+    }
+
+    #[test]
+    fn pack_quantity_from_name_and_weight() {
+        let bundle = |name: &str, weight: u32| Product {
+            name: name.to_owned(),
+            weight: Grams(weight),
+            pack_quantity: None,
+            ..Product::example()
+        };
+
+        assert_eq!(
+            bundle("4 Pack 1kg PLA", 4000).detect_pack_quantity(),
+            Some(4)
+        );
+        assert_eq!(
+            bundle("4 Pack 1kg PLA", 1000).detect_pack_quantity(),
+            Some(4)
+        );
+        assert_eq!(
+            bundle("PLA 1kg, Pack of 3", 3000).detect_pack_quantity(),
+            Some(3)
+        );
+        assert_eq!(
+            bundle("PLA 1kg Black", 3000).detect_pack_quantity(),
+            Some(3)
+        );
+        assert_eq!(bundle("4 Pack 1kg PLA", 3000).detect_pack_quantity(), None);
+        assert_eq!(bundle("PLA 1kg Black", 1000).detect_pack_quantity(), None);
+
+        let mut product = bundle("4 Pack 1kg PLA", 4000);
+        assert!(product.enrich());
+        assert_eq!(product.pack_quantity, Some(4));
+    }
+
+    #[test]
+    fn normalize_cleans_once_and_is_idempotent() {
+        let mut product = Product {
+            name: "  Hatchbox   PETG\t1.75mm\n1kg ".to_owned(),
+            color: "  dark   BLUE ".to_owned(),
+            brand: Some(" \u{a0} ".to_owned()),
+            material: FilamentMaterial::Other(" petg ".to_owned()),
+            diameter: FilamentDiameter::Other(176),
+            ..Product::example()
+        };
+
+        product.normalize();
+
+        assert_eq!(product.name, "Hatchbox PETG 1.75mm 1kg");
+        assert_eq!(product.color, "Dark Blue");
+        assert_eq!(product.brand, None);
+        assert_eq!(product.material, FilamentMaterial::PETG);
+        assert_eq!(product.diameter, FilamentDiameter::D175);
+
+        let once = product.clone();
+        product.normalize();
+        assert!(product == once);
+    }
+
+    #[test]
+    fn unknown_host_falls_back_to_the_default_retailer() {
+        let blank = || Product {
+            url: "https://filament.example.org/p/123".to_owned(),
+            retailer: Retailer::Other(String::new()),
+            ..Product::example()
+        };
+        let config = EnrichConfig {
+            default_retailer: Some(Retailer::Other("Example Filament".to_owned())),
+        };
+
+        let mut product = blank();
+        assert!(product.enrich_with(&config));
+        assert_eq!(
+            product.retailer,
+            Retailer::Other("Example Filament".to_owned())
+        );
+
+        let mut product = blank();
+        assert!(!product.enrich_with(&EnrichConfig::default()));
+        assert_eq!(product.retailer, Retailer::Other(String::new()));
+
+        let mut product = Product {
+            url: "https://amzn.to/3abcdef".to_owned(),
+            ..blank()
+        };
+        assert!(product.enrich_with(&config));
+        assert_eq!(product.retailer, Retailer::Amazon);
+    }
+
+    #[test]
+    fn default_retailer_is_read_from_the_environment() {
+        let config = EnrichConfig::from_lookup(|name| {
+            (name == "FILAMENTSEEK_DEFAULT_RETAILER").then(|| " Example Filament ".to_owned())
+        });
+        assert_eq!(
+            config.default_retailer,
+            Some(Retailer::Other("Example Filament".to_owned()))
+        );
+
+        let blank = EnrichConfig::from_lookup(|_| Some("  ".to_owned()));
+        assert_eq!(blank, EnrichConfig::default());
+        assert_eq!(EnrichConfig::from_lookup(|_| None), EnrichConfig::default());
+    }
+
+    #[test]
+    fn spool_type_from_name() {
+        assert_eq!(
+            SpoolType::infer_from_text("PLA Refill"),
+            Some(SpoolType::Refill)
+        );
+        assert_eq!(
+            SpoolType::infer_from_text("Refill for MasterSpool, PETG 1kg"),
+            Some(SpoolType::Refill)
+        );
+        assert_eq!(
+            SpoolType::infer_from_text("PLA 1kg on a Master Spool"),
+            Some(SpoolType::Reusable)
+        );
+        assert_eq!(
+            SpoolType::infer_from_text("PLA 1kg Cardboard Spool"),
+            Some(SpoolType::Cardboard)
+        );
+        assert_eq!(SpoolType::infer_from_text("PLA 1kg Black"), None);
+
+        let mut product = Product {
+            name: "Prusament PLA Refill 1kg".to_owned(),
+            spool_type: SpoolType::Unknown,
+            ..Product::example()
+        };
+        assert!(product.enrich());
+        assert_eq!(product.spool_type, SpoolType::Refill);
+    }
+
+    #[test]
+    fn from_html_prefills_a_request_from_a_mock_page() {
+        let html = r#"<html><head>
+            <title>Ignored &amp; replaced</title>
+            <meta property="og:title" content="Hatchbox PETG Filament 1.75mm 1kg, Blue">
+            <meta property="product:price:amount" content="24.99">
+            <meta property="product:price:currency" content="eur">
+            </head><body></body></html>"#;
+
+        let request =
+            ProductRequest::from_html("https://www.amazon.de/dp/B00TEST123", html).unwrap();
+
+        assert_eq!(request.material, FilamentMaterial::PETG);
+        assert_eq!(request.diameter, FilamentDiameter::D175);
+        assert_eq!(request.weight, Grams(1000));
+        assert_eq!(request.price, Cents(2499));
+        assert_eq!(request.currency, Currency::EUR);
+        assert_eq!(request.retailer, Retailer::Amazon);
+        assert_eq!(request.region.as_deref(), Some("DE"));
+
+        let yen = r#"<meta property="og:title" content="Bambu Lab PLA Basic 1.75mm 1kg">
+            <meta property="product:price:amount" content="2,480">
+            <meta property="product:price:currency" content="JPY">"#;
+        let request = ProductRequest::from_html("https://www.amazon.co.jp/dp/B0TEST", yen).unwrap();
+
+        assert_eq!(request.price, Cents(2480));
+        assert_eq!(request.currency, Currency::JPY);
+    }
+
+    #[test]
+    fn from_html_falls_back_to_the_title_and_needs_one() {
+        let html = "<title>Generic  PLA 2.85mm &amp; more</title>";
+        let request = ProductRequest::from_html("https://shop.example.com/pla", html).unwrap();
+
+        assert_eq!(request.material, FilamentMaterial::PLA);
+        assert_eq!(request.diameter, FilamentDiameter::D285);
+        assert_eq!(request.price, Cents(0));
+        assert_eq!(
+            request.retailer,
+            Retailer::Other("shop.example.com".to_owned())
+        );
+
+        assert!(matches!(
+            ProductRequest::from_html("https://shop.example.com/pla", "<p>none</p>"),
+            Err(EnrichError::MissingTitle)
+        ));
+    }
+
+    #[test]
+    fn normalize_whitespace_collapses_nbsp_and_double_spaces() {
+        let mut product = Product {
+            name: "Hatchbox\u{a0}PLA  1.75mm \u{a0} 1kg\u{a0}".to_owned(),
+            color: "Jet\u{a0}\u{a0}Black".to_owned(),
+            brand: Some("  Hatch\u{a0}box ".to_owned()),
+            ..Product::example()
+        };
+
+        product.normalize_whitespace();
+
+        assert_eq!(product.name, "Hatchbox PLA 1.75mm 1kg");
+        assert_eq!(product.color, "Jet Black");
+        assert_eq!(product.brand.as_deref(), Some("Hatch box"));
+    }
+
+    #[test]
+    fn resolve_material_prefers_explicit_then_inferred() {
+        let named = |name: &str, material| Product {
+            name: name.to_owned(),
+            material,
+            material_confidence: None,
+            ..Product::example()
+        };
+
+        assert_eq!(
+            named("Hatchbox PLA 1kg", FilamentMaterial::PETG).resolve_material(),
+            (FilamentMaterial::PETG, 1.0)
+        );
+
+        let (material, confidence) =
+            named("Hatchbox PLA 1kg", FilamentMaterial::Unspecified).resolve_material();
+        assert_eq!(material, FilamentMaterial::PLA);
+        assert!(confidence > 0.0);
+
+        assert_eq!(
+            named("Mystery spool", FilamentMaterial::Unspecified).resolve_material(),
+            (FilamentMaterial::Unspecified, 0.0)
+        );
+    }
+}
@@ -0,0 +1,1283 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use surreal_socket::{
+    dbrecord::{DBRecord, SsUuid},
+    error::SurrealSocketError,
+};
+use utoipa::ToSchema;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    product::{Availability, Cents, Product},
+    stats::quantile,
+    surrealdb_client,
+};
+
+/// Notable change to a product, consumed by notification jobs
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type")]
+pub enum ProductEvent {
+    PriceChanged {
+        previous: Cents,
+        current: Cents,
+    },
+    BackInStock,
+    WentOutOfStock,
+    /// The product was deleted, and its earlier events with it
+    Deleted,
+}
+
+/// Smallest price move notification jobs alert on, so users aren't told about every tiny
+/// fluctuation. Every change is still recorded as a `PriceChanged` event; the threshold only
+/// picks which of them notify (see `ProductEventRecord::notable_price_changes`), measured from
+/// the last notified price. A move is notable when it reaches either threshold that is set;
+/// with neither set, every change is.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PriceChangeThreshold {
+    /// Absolute change in cents, or 0 for none
+    pub min_cents: u32,
+    /// Change relative to the previous price, in percent, or 0.0 for none
+    pub min_percent: f32,
+}
+
+impl PriceChangeThreshold {
+    /// Reads the thresholds from `FILAMENTSEEK_MIN_PRICE_CHANGE_CENTS` and
+    /// `FILAMENTSEEK_MIN_PRICE_CHANGE_PERCENT`, where unset or unparseable means none
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().map(|v| v.trim().to_owned());
+
+        Self {
+            min_cents: var("FILAMENTSEEK_MIN_PRICE_CHANGE_CENTS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            min_percent: var("FILAMENTSEEK_MIN_PRICE_CHANGE_PERCENT")
+                .and_then(|v| v.parse().ok())
+                .filter(|p: &f32| p.is_finite())
+                .unwrap_or(0.0),
+        }
+    }
+
+    /// Whether a move from `previous` to `current` is large enough to notify
+    pub fn is_notable(&self, previous: Cents, current: Cents) -> bool {
+        if previous == current {
+            return false;
+        }
+
+        let delta = previous.0.abs_diff(current.0);
+        let by_cents = self.min_cents > 0 && delta >= self.min_cents;
+        let by_percent = self.min_percent > 0.0
+            && (previous.0 == 0 || delta as f32 * 100.0 / previous.0 as f32 >= self.min_percent);
+
+        (self.min_cents == 0 && self.min_percent <= 0.0) || by_cents || by_percent
+    }
+}
+
+impl ProductEvent {
+    /// Events implied by `current` replacing `previous`
+    pub fn between(previous: &Product, current: &Product) -> Vec<ProductEvent> {
+        let mut events = Vec::new();
+
+        if previous.price != current.price {
+            events.push(ProductEvent::PriceChanged {
+                previous: previous.price,
+                current: current.price,
+            });
+        }
+
+        if let Some(event) = Self::availability_change(previous.availability, current.availability)
+        {
+            events.push(event);
+        }
+
+        events
+    }
+
+    fn availability_change(previous: Availability, current: Availability) -> Option<ProductEvent> {
+        if previous == Availability::OutOfStock && current.is_purchasable() {
+            Some(ProductEvent::BackInStock)
+        } else if previous.is_purchasable() && current == Availability::OutOfStock {
+            Some(ProductEvent::WentOutOfStock)
+        } else {
+            None
+        }
+    }
+}
+
+/// Which way the price last moved, for "was $22, now $19 ↓" displays
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum PriceDirection {
+    Up,
+    Down,
+    Same,
+}
+
+/// Simple purchase hint from `Product::buy_recommendation`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum BuyHint {
+    BuyNow,
+    Wait,
+    Neutral,
+}
+
+/// Price history `Product::buy_recommendation` judges "normal" prices over
+const BUY_HINT_WINDOW: Duration = Duration::days(90);
+
+/// Least `price_stability` for a below-normal price to be trusted as a bargain rather than
+/// noise in an erratic history
+const BUY_HINT_MIN_STABILITY: f32 = 0.6;
+
+/// Highest `price_percentile` within the material still counted as cheap
+const BUY_HINT_MAX_PERCENTILE: f32 = 0.25;
+
+/// Stored occurrence of a `ProductEvent`
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProductEventRecord {
+    pub uuid: SsUuid<ProductEventRecord>,
+    pub product_uuid: SsUuid<Product>,
+    pub event: ProductEvent,
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl DBRecord for ProductEventRecord {
+    fn uuid(&self) -> SsUuid<Self> {
+        self.uuid.to_owned()
+    }
+
+    const TABLE_NAME: &'static str = "product_events";
+}
+
+impl ProductEventRecord {
+    /// New record of `event` against `product_uuid`, occurring at `clock`'s current time
+    pub fn new(product_uuid: SsUuid<Product>, event: ProductEvent, clock: &dyn Clock) -> Self {
+        ProductEventRecord {
+            uuid: SsUuid::new(),
+            product_uuid,
+            event,
+            occurred_at: clock.now(),
+        }
+    }
+
+    /// Stores `event` against `product_uuid`
+    pub async fn emit(
+        product_uuid: SsUuid<Product>,
+        event: ProductEvent,
+    ) -> Result<ProductEventRecord, SurrealSocketError> {
+        Self::emit_with(product_uuid, event, &SystemClock).await
+    }
+
+    /// `emit`, stamping the record with `clock`'s current time
+    pub async fn emit_with(
+        product_uuid: SsUuid<Product>,
+        event: ProductEvent,
+        clock: &dyn Clock,
+    ) -> Result<ProductEventRecord, SurrealSocketError> {
+        let record = Self::new(product_uuid, event, clock);
+
+        let client = surrealdb_client().await?;
+        let query = format!("CREATE {} CONTENT $record", Self::table());
+        client
+            .query(query)
+            .bind(("record", record.clone()))
+            .await?
+            .check()?;
+
+        Ok(record)
+    }
+
+    /// Events stored against `product_uuid`, oldest first
+    pub async fn for_product(
+        product_uuid: SsUuid<Product>,
+    ) -> Result<Vec<ProductEventRecord>, SurrealSocketError> {
+        let client = surrealdb_client().await?;
+        let query = format!(
+            "SELECT * FROM {} WHERE product_uuid = $product_uuid",
+            Self::table()
+        );
+
+        let mut records: Vec<ProductEventRecord> = client
+            .query(query)
+            .bind(("product_uuid", product_uuid))
+            .await?
+            .take(0)?;
+
+        records.sort_by_key(|r| r.occurred_at);
+        Ok(records)
+    }
+
+    /// The `PriceChanged` records among `records` (oldest first) that notification jobs
+    /// should alert on: those moving the price by at least `threshold` from the last notified
+    /// price, the price before the first change to begin with. Each is returned with
+    /// `previous` set to that last notified price, so a run of small cuts that adds up to the
+    /// threshold notifies as one change and chained events never skip a step unnoticed.
+    pub fn notable_price_changes(
+        records: &[ProductEventRecord],
+        threshold: &PriceChangeThreshold,
+    ) -> Vec<ProductEventRecord> {
+        let mut notified: Option<Cents> = None;
+        let mut notable = Vec::new();
+
+        for record in records {
+            let ProductEvent::PriceChanged { previous, current } = record.event else {
+                continue;
+            };
+            let last_notified = *notified.get_or_insert(previous);
+
+            if threshold.is_notable(last_notified, current) {
+                notified = Some(current);
+                notable.push(ProductEventRecord {
+                    event: ProductEvent::PriceChanged {
+                        previous: last_notified,
+                        current,
+                    },
+                    ..record.clone()
+                });
+            }
+        }
+
+        notable
+    }
+}
+
+/// Settings for recording price history, the `PriceChanged` events
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PriceHistoryConfig {
+    /// A price change recorded less than this after the latest one is only recorded if it
+    /// moves the price away from what that one recorded, so racing re-scrapes of the same
+    /// change don't add near-duplicate rows. Zero records every change.
+    pub min_history_interval: Duration,
+}
+
+impl PriceHistoryConfig {
+    /// Reads the interval in seconds from `FILAMENTSEEK_MIN_HISTORY_INTERVAL_SECS`, where
+    /// unset or unparseable means zero
+    pub fn from_env() -> Self {
+        Self {
+            min_history_interval: std::env::var("FILAMENTSEEK_MIN_HISTORY_INTERVAL_SECS")
+                .ok()
+                .and_then(|s| s.trim().parse::<u32>().ok())
+                .map_or_else(Duration::zero, |secs| Duration::seconds(secs.into())),
+        }
+    }
+
+    /// Whether a change to `price` at `now` gets a new row after the `latest` recorded
+    /// change: always if the price differs from the one `latest` recorded, otherwise only
+    /// once `min_history_interval` has passed since it
+    fn records(&self, latest: Option<&PriceChange>, price: Cents, now: DateTime<Utc>) -> bool {
+        latest.is_none_or(|l| l.current != price || now - l.at >= self.min_history_interval)
+    }
+}
+
+impl Product {
+    /// Emits the events implied by this product replacing `previous` and returns them. `save`
+    /// calls this with the record it overwrote, so every update is covered. The threshold in
+    /// `PriceChangeThreshold` isn't applied here but by `price_notifications`.
+    pub async fn emit_update_events(
+        &self,
+        previous: &Product,
+    ) -> Result<Vec<ProductEvent>, SurrealSocketError> {
+        self.emit_update_events_with(previous, &PriceHistoryConfig::from_env(), &SystemClock)
+            .await
+    }
+
+    /// `emit_update_events`, stamping the events with `clock`'s current time. A price change
+    /// `config` says adds no price history row (see `PriceHistoryConfig`) is left out.
+    pub async fn emit_update_events_with(
+        &self,
+        previous: &Product,
+        config: &PriceHistoryConfig,
+        clock: &dyn Clock,
+    ) -> Result<Vec<ProductEvent>, SurrealSocketError> {
+        let mut events = ProductEvent::between(previous, self);
+
+        let is_price_change = |e: &ProductEvent| matches!(e, ProductEvent::PriceChanged { .. });
+        if events.iter().any(is_price_change) {
+            let changes = price_changes(ProductEventRecord::for_product(self.uuid()).await?);
+            if !config.records(changes.last(), self.price, clock.now()) {
+                events.retain(|e| !is_price_change(e));
+            }
+        }
+
+        for event in &events {
+            ProductEventRecord::emit_with(self.uuid(), event.clone(), clock).await?;
+        }
+
+        Ok(events)
+    }
+
+    /// Price changes to notify users of, as `ProductEventRecord::notable_price_changes` picks
+    /// them from the whole history with `PriceChangeThreshold::from_env`. Jobs take those that
+    /// occurred since their last run.
+    pub async fn price_notifications(&self) -> Result<Vec<ProductEventRecord>, SurrealSocketError> {
+        let records = ProductEventRecord::for_product(self.uuid()).await?;
+        Ok(ProductEventRecord::notable_price_changes(
+            &records,
+            &PriceChangeThreshold::from_env(),
+        ))
+    }
+
+    /// Price before the most recent recorded price change, or `None` without one
+    pub async fn previous_price(&self) -> Result<Option<Cents>, SurrealSocketError> {
+        let changes = price_changes(ProductEventRecord::for_product(self.uuid()).await?);
+        Ok(changes.last().map(|c| c.previous))
+    }
+
+    /// Direction of the current price relative to `previous_price`; `Same` without history
+    pub async fn price_direction(&self) -> Result<PriceDirection, SurrealSocketError> {
+        Ok(self.direction_from(self.previous_price().await?))
+    }
+
+    /// Direction of the current price relative to `previous`
+    fn direction_from(&self, previous: Option<Cents>) -> PriceDirection {
+        match previous {
+            Some(previous) if self.price < previous => PriceDirection::Down,
+            Some(previous) if self.price > previous => PriceDirection::Up,
+            _ => PriceDirection::Same,
+        }
+    }
+
+    /// Current price as a ratio of the time-weighted average price over the last `window`
+    /// (or since creation, if later), from recorded `PriceChanged` events. Below 1.0 means
+    /// cheaper than usual. `None` when no price change has been recorded.
+    pub async fn price_vs_rolling_average(
+        &self,
+        window: Duration,
+    ) -> Result<Option<f32>, SurrealSocketError> {
+        self.price_vs_rolling_average_with(window, &SystemClock)
+            .await
+    }
+
+    /// `price_vs_rolling_average` as of `clock`
+    pub async fn price_vs_rolling_average_with(
+        &self,
+        window: Duration,
+        clock: &dyn Clock,
+    ) -> Result<Option<f32>, SurrealSocketError> {
+        let changes = price_changes(ProductEventRecord::for_product(self.uuid()).await?);
+        Ok(self.price_vs_average(&changes, window, clock.now()))
+    }
+
+    /// `price_vs_rolling_average` from already loaded `changes`
+    fn price_vs_average(
+        &self,
+        changes: &[PriceChange],
+        window: Duration,
+        now: DateTime<Utc>,
+    ) -> Option<f32> {
+        if changes.is_empty() {
+            return None;
+        }
+
+        let timeline = self.price_timeline(changes, window, now);
+        let start = timeline[0].0;
+
+        let weighted: f64 = timeline
+            .iter()
+            .zip(timeline.iter().skip(1).map(|(at, _)| *at).chain([now]))
+            .map(|((since, price), until)| {
+                price.0 as f64 * (until - *since).num_milliseconds() as f64
+            })
+            .sum();
+
+        let span = (now - start).num_milliseconds() as f64;
+        if span <= 0.0 || weighted <= 0.0 {
+            return None;
+        }
+
+        Some((self.price.0 as f64 / (weighted / span)) as f32)
+    }
+
+    /// How stable the price has been over the last `window` (or since creation, if later),
+    /// from 1.0 for a constant price down to 0.0 at a time-weighted coefficient of variation
+    /// of 1 or more. `None` when no price change has been recorded.
+    pub async fn price_stability(
+        &self,
+        window: Duration,
+    ) -> Result<Option<f32>, SurrealSocketError> {
+        self.price_stability_with(window, &SystemClock).await
+    }
+
+    /// `price_stability` as of `clock`
+    pub async fn price_stability_with(
+        &self,
+        window: Duration,
+        clock: &dyn Clock,
+    ) -> Result<Option<f32>, SurrealSocketError> {
+        let changes = price_changes(ProductEventRecord::for_product(self.uuid()).await?);
+        Ok(self.stability(&changes, window, clock.now()))
+    }
+
+    /// `price_stability` from already loaded `changes`
+    fn stability(
+        &self,
+        changes: &[PriceChange],
+        window: Duration,
+        now: DateTime<Utc>,
+    ) -> Option<f32> {
+        if changes.is_empty() {
+            return None;
+        }
+
+        let timeline = self.price_timeline(changes, window, now);
+
+        let segments: Vec<(f64, f64)> = timeline
+            .iter()
+            .zip(timeline.iter().skip(1).map(|(at, _)| *at).chain([now]))
+            .map(|((since, price), until)| {
+                (price.0 as f64, (until - *since).num_milliseconds() as f64)
+            })
+            .collect();
+
+        let span: f64 = segments.iter().map(|(_, duration)| duration).sum();
+        if span <= 0.0 {
+            return None;
+        }
+
+        let mean = segments
+            .iter()
+            .map(|(price, duration)| price * duration)
+            .sum::<f64>()
+            / span;
+        if mean <= 0.0 {
+            return None;
+        }
+
+        let variance = segments
+            .iter()
+            .map(|(price, duration)| (price - mean).powi(2) * duration)
+            .sum::<f64>()
+            / span;
+
+        let cv = variance.sqrt() / mean;
+        Some((1.0 - cv).clamp(0.0, 1.0) as f32)
+    }
+
+    /// Drop from the highest price over the last `window` to the current price, as a
+    /// fraction (0.2 for 20% cheaper). `None` without a drop or without recorded history.
+    pub async fn price_drop_percent(
+        &self,
+        window: Duration,
+    ) -> Result<Option<f32>, SurrealSocketError> {
+        self.price_drop_percent_with(window, &SystemClock).await
+    }
+
+    /// `price_drop_percent` as of `clock`
+    pub async fn price_drop_percent_with(
+        &self,
+        window: Duration,
+        clock: &dyn Clock,
+    ) -> Result<Option<f32>, SurrealSocketError> {
+        let changes = price_changes(ProductEventRecord::for_product(self.uuid()).await?);
+        Ok(self.price_drop(&changes, window, clock.now()))
+    }
+
+    /// Whether the current price is at least `threshold_pct` percent below the median of
+    /// the prices in effect over the last `window`. False without recorded history.
+    pub async fn is_on_notable_sale(
+        &self,
+        window: Duration,
+        threshold_pct: u8,
+    ) -> Result<bool, SurrealSocketError> {
+        self.is_on_notable_sale_with(window, threshold_pct, &SystemClock)
+            .await
+    }
+
+    /// `is_on_notable_sale` as of `clock`
+    pub async fn is_on_notable_sale_with(
+        &self,
+        window: Duration,
+        threshold_pct: u8,
+        clock: &dyn Clock,
+    ) -> Result<bool, SurrealSocketError> {
+        let changes = price_changes(ProductEventRecord::for_product(self.uuid()).await?);
+        Ok(self.notable_sale(&changes, window, threshold_pct, clock.now()))
+    }
+
+    /// `is_on_notable_sale` from already loaded `changes`
+    fn notable_sale(
+        &self,
+        changes: &[PriceChange],
+        window: Duration,
+        threshold_pct: u8,
+        now: DateTime<Utc>,
+    ) -> bool {
+        if changes.is_empty() {
+            return false;
+        }
+
+        let mut prices: Vec<f64> = self
+            .price_timeline(changes, window, now)
+            .into_iter()
+            .map(|(_, price)| price.0 as f64)
+            .collect();
+        prices.sort_by(f64::total_cmp);
+
+        let median = quantile(&prices, 0.5);
+        let threshold = median * (1.0 - f64::from(threshold_pct.min(100)) / 100.0);
+
+        (self.price.0 as f64) <= threshold && (self.price.0 as f64) < median
+    }
+
+    /// Band of "normal" prices over the last `window`: the 25th to 75th percentile of the
+    /// prices in effect, as spool prices (the weight doesn't change, so this is the same band
+    /// as for price per kg). `None` without recorded history.
+    pub async fn normal_price_range(
+        &self,
+        window: Duration,
+    ) -> Result<Option<(Cents, Cents)>, SurrealSocketError> {
+        self.normal_price_range_with(window, &SystemClock).await
+    }
+
+    /// `normal_price_range` as of `clock`
+    pub async fn normal_price_range_with(
+        &self,
+        window: Duration,
+        clock: &dyn Clock,
+    ) -> Result<Option<(Cents, Cents)>, SurrealSocketError> {
+        let changes = price_changes(ProductEventRecord::for_product(self.uuid()).await?);
+        Ok(self.normal_range(&changes, window, clock.now()))
+    }
+
+    /// `normal_price_range` from already loaded `changes`
+    fn normal_range(
+        &self,
+        changes: &[PriceChange],
+        window: Duration,
+        now: DateTime<Utc>,
+    ) -> Option<(Cents, Cents)> {
+        if changes.is_empty() {
+            return None;
+        }
+
+        let mut prices: Vec<f64> = self
+            .price_timeline(changes, window, now)
+            .into_iter()
+            .map(|(_, price)| price.0 as f64)
+            .collect();
+        prices.sort_by(f64::total_cmp);
+
+        let low = quantile(&prices, 0.25).round() as u32;
+        let high = quantile(&prices, 0.75).round() as u32;
+
+        Some((Cents(low), Cents(high)))
+    }
+
+    /// `BuyNow` when the price is below its normal range over the last 90 days, the history
+    /// is stable and the price per kg is in the cheapest quarter of its material; `Wait` when
+    /// the price last went up and is above the material's median; otherwise `Neutral`
+    pub async fn buy_recommendation(&self) -> Result<BuyHint, SurrealSocketError> {
+        self.buy_recommendation_with(&SystemClock).await
+    }
+
+    /// `buy_recommendation` as of `clock`
+    pub async fn buy_recommendation_with(
+        &self,
+        clock: &dyn Clock,
+    ) -> Result<BuyHint, SurrealSocketError> {
+        let percentile = self.price_percentile().await?;
+        let changes = price_changes(ProductEventRecord::for_product(self.uuid()).await?);
+
+        Ok(self.buy_hint(&changes, percentile, clock.now()))
+    }
+
+    /// `buy_recommendation` from already loaded `changes` and the price per kg `percentile`
+    /// within the material
+    fn buy_hint(&self, changes: &[PriceChange], percentile: f32, now: DateTime<Utc>) -> BuyHint {
+        let below_normal = self
+            .normal_range(changes, BUY_HINT_WINDOW, now)
+            .is_some_and(|(low, _)| self.price < low);
+        let stable = self
+            .stability(changes, BUY_HINT_WINDOW, now)
+            .is_some_and(|s| s >= BUY_HINT_MIN_STABILITY);
+
+        if below_normal && stable && percentile <= BUY_HINT_MAX_PERCENTILE {
+            return BuyHint::BuyNow;
+        }
+
+        let previous = changes.last().map(|c| c.previous);
+        if self.direction_from(previous) == PriceDirection::Up && percentile > 0.5 {
+            return BuyHint::Wait;
+        }
+
+        BuyHint::Neutral
+    }
+
+    /// Products with the largest `price_drop_percent` over `window`, biggest drop first
+    pub async fn top_price_drops(
+        window: Duration,
+        limit: usize,
+    ) -> Result<Vec<(Product, f32)>, SurrealSocketError> {
+        Self::top_price_drops_with(window, limit, &SystemClock).await
+    }
+
+    /// `top_price_drops` as of `clock`
+    pub async fn top_price_drops_with(
+        window: Duration,
+        limit: usize,
+        clock: &dyn Clock,
+    ) -> Result<Vec<(Product, f32)>, SurrealSocketError> {
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT * FROM {} WHERE event.type = 'PriceChanged'",
+            ProductEventRecord::table()
+        );
+
+        let mut records: Vec<ProductEventRecord> = client.query(query).await?.take(0)?;
+        records.sort_by_key(|r| r.occurred_at);
+
+        let mut by_product: HashMap<String, Vec<ProductEventRecord>> = HashMap::new();
+        for record in records {
+            by_product
+                .entry(record.product_uuid.to_uuid_string())
+                .or_default()
+                .push(record);
+        }
+
+        let uuids: Vec<SsUuid<Product>> = by_product
+            .values()
+            .filter_map(|records| records.first().map(|r| r.product_uuid.clone()))
+            .collect();
+
+        let query = format!(
+            "SELECT * FROM {} WHERE {} IN $uuids",
+            Self::table(),
+            Self::UUID_FIELD
+        );
+
+        let products: Vec<Product> = client.query(query).bind(("uuids", uuids)).await?.take(0)?;
+
+        Ok(largest_price_drops(
+            products,
+            by_product,
+            window,
+            limit,
+            clock.now(),
+        ))
+    }
+
+    /// Products with no recorded price change, whose price can't yet be judged against their
+    /// own history (e.g. so deal badges can be held back)
+    pub async fn without_price_history() -> Result<Vec<Product>, SurrealSocketError> {
+        let client = surrealdb_client().await?;
+        Ok(client
+            .query(Self::without_price_history_statement())
+            .await?
+            .take(0)?)
+    }
+
+    fn without_price_history_statement() -> String {
+        format!(
+            "SELECT * FROM {} WHERE {} NOT IN \
+             (SELECT VALUE product_uuid FROM {} WHERE event.type = 'PriceChanged')",
+            Self::table(),
+            Self::UUID_FIELD,
+            ProductEventRecord::table()
+        )
+    }
+
+    /// `price_drop_percent` from already loaded `changes`
+    pub(crate) fn price_drop(
+        &self,
+        changes: &[PriceChange],
+        window: Duration,
+        now: DateTime<Utc>,
+    ) -> Option<f32> {
+        if changes.is_empty() {
+            return None;
+        }
+
+        let highest = self
+            .price_timeline(changes, window, now)
+            .into_iter()
+            .map(|(_, price)| price)
+            .max()?;
+
+        (highest > self.price).then(|| (highest.0 - self.price.0) as f32 / highest.0 as f32)
+    }
+
+    /// Price at `points` evenly spaced times over the last `window` (or since creation, if
+    /// later), most recent last and ending now, for a small price chart
+    pub async fn price_sparkline(
+        &self,
+        points: usize,
+        window: Duration,
+    ) -> Result<Vec<(DateTime<Utc>, Cents)>, SurrealSocketError> {
+        self.price_sparkline_with(points, window, &SystemClock)
+            .await
+    }
+
+    /// `price_sparkline` as of `clock`
+    pub async fn price_sparkline_with(
+        &self,
+        points: usize,
+        window: Duration,
+        clock: &dyn Clock,
+    ) -> Result<Vec<(DateTime<Utc>, Cents)>, SurrealSocketError> {
+        let changes = if points > 1 {
+            price_changes(ProductEventRecord::for_product(self.uuid()).await?)
+        } else {
+            Vec::new()
+        };
+
+        Ok(self.sparkline(&changes, points, window, clock.now()))
+    }
+
+    /// `price_sparkline` from already loaded `changes`
+    fn sparkline(
+        &self,
+        changes: &[PriceChange],
+        points: usize,
+        window: Duration,
+        now: DateTime<Utc>,
+    ) -> Vec<(DateTime<Utc>, Cents)> {
+        if points == 0 {
+            return Vec::new();
+        }
+
+        if points == 1 {
+            return vec![(now, self.price)];
+        }
+
+        let timeline = self.price_timeline(changes, window, now);
+
+        let start = timeline[0].0;
+        let step = (now - start) / (points as i32 - 1);
+
+        (0..points)
+            .map(|i| {
+                let at = if i == points - 1 {
+                    now
+                } else {
+                    start + step * i as i32
+                };
+
+                let price = timeline
+                    .iter()
+                    .rev()
+                    .find(|(since, _)| *since <= at)
+                    .map_or(self.price, |(_, price)| *price);
+
+                (at, price)
+            })
+            .collect()
+    }
+
+    /// Prices in effect over the last `window` (or since creation, if later) with the time
+    /// each took effect, the first at the start of the window. `changes` are oldest first.
+    fn price_timeline(
+        &self,
+        changes: &[PriceChange],
+        window: Duration,
+        now: DateTime<Utc>,
+    ) -> Vec<(DateTime<Utc>, Cents)> {
+        let start = (now - window).max(self.created_at);
+        let mut in_window = changes.iter().filter(|c| c.at > start).peekable();
+
+        let initial = in_window.peek().map_or(self.price, |c| c.previous);
+        let mut timeline = vec![(start, initial)];
+        timeline.extend(in_window.map(|c| (c.at, c.current)));
+
+        timeline
+    }
+}
+
+/// A `PriceChanged` event with the time it occurred
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PriceChange {
+    pub at: DateTime<Utc>,
+    pub previous: Cents,
+    pub current: Cents,
+}
+
+/// `products` with their `price_drop` over `window` from their records in `by_product`
+/// (keyed by uuid string), biggest drop first and at most `limit` of them
+fn largest_price_drops(
+    products: Vec<Product>,
+    mut by_product: HashMap<String, Vec<ProductEventRecord>>,
+    window: Duration,
+    limit: usize,
+    now: DateTime<Utc>,
+) -> Vec<(Product, f32)> {
+    let mut drops: Vec<(Product, f32)> = products
+        .into_iter()
+        .filter_map(|product| {
+            let records = by_product.remove(&product.uuid.to_uuid_string())?;
+            let drop = product.price_drop(&price_changes(records), window, now)?;
+            Some((product, drop))
+        })
+        .collect();
+
+    drops.sort_by(|a, b| b.1.total_cmp(&a.1));
+    drops.truncate(limit);
+
+    drops
+}
+
+/// The price changes among `records`, in the same order
+pub(crate) fn price_changes(records: Vec<ProductEventRecord>) -> Vec<PriceChange> {
+    records
+        .into_iter()
+        .filter_map(|r| match r.event {
+            ProductEvent::PriceChanged { previous, current } => Some(PriceChange {
+                at: r.occurred_at,
+                previous,
+                current,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_occurs_at_clock_time() {
+        let frozen = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let record = ProductEventRecord::new(
+            Product::example().uuid(),
+            ProductEvent::BackInStock,
+            &crate::clock::FixedClock(frozen),
+        );
+
+        assert_eq!(record.occurred_at, frozen);
+    }
+
+    #[test]
+    fn back_in_stock_on_restock() {
+        let previous = Product {
+            availability: Availability::OutOfStock,
+            ..Product::example()
+        };
+        let current = Product {
+            availability: Availability::InStock,
+            ..previous.clone()
+        };
+
+        assert_eq!(
+            ProductEvent::between(&previous, &current),
+            vec![ProductEvent::BackInStock]
+        );
+        assert_eq!(
+            ProductEvent::between(&current, &previous),
+            vec![ProductEvent::WentOutOfStock]
+        );
+    }
+
+    /// `PriceChanged` records for successive `prices` of the example product, a day apart
+    fn price_steps(prices: &[u32]) -> Vec<ProductEventRecord> {
+        let product = Product::example();
+
+        prices
+            .windows(2)
+            .enumerate()
+            .map(|(day, pair)| {
+                let at = crate::clock::FixedClock(product.created_at + Duration::days(day as i64));
+                let event = ProductEvent::PriceChanged {
+                    previous: Cents(pair[0]),
+                    current: Cents(pair[1]),
+                };
+                ProductEventRecord::new(product.uuid(), event, &at)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_price_change_is_recorded_but_small_ones_dont_notify() {
+        let threshold = PriceChangeThreshold {
+            min_cents: 100,
+            min_percent: 0.0,
+        };
+        let previous = Product::example();
+        let cent_cheaper = Product {
+            price: Cents(previous.price.0 - 1),
+            ..previous.clone()
+        };
+
+        assert_eq!(
+            ProductEvent::between(&previous, &cent_cheaper),
+            vec![ProductEvent::PriceChanged {
+                previous: previous.price,
+                current: cent_cheaper.price,
+            }]
+        );
+
+        let cent = price_steps(&[2199, 2198]);
+        assert!(ProductEventRecord::notable_price_changes(&cent, &threshold).is_empty());
+
+        let two_dollars = price_steps(&[2199, 1999]);
+        assert!(ProductEventRecord::notable_price_changes(&two_dollars, &threshold) == two_dollars);
+    }
+
+    #[test]
+    fn small_steps_adding_up_to_the_threshold_notify_from_the_last_notified_price() {
+        let threshold = PriceChangeThreshold {
+            min_cents: 100,
+            min_percent: 0.0,
+        };
+        let records = price_steps(&[2199, 2149, 2099, 2049, 2001, 1999]);
+
+        let notified: Vec<ProductEvent> =
+            ProductEventRecord::notable_price_changes(&records, &threshold)
+                .into_iter()
+                .map(|r| r.event)
+                .collect();
+
+        assert_eq!(
+            notified,
+            vec![
+                ProductEvent::PriceChanged {
+                    previous: Cents(2199),
+                    current: Cents(2099),
+                },
+                ProductEvent::PriceChanged {
+                    previous: Cents(2099),
+                    current: Cents(1999),
+                },
+            ]
+        );
+        assert_eq!(price_changes(records).len(), 5);
+    }
+
+    #[test]
+    fn percent_threshold() {
+        let threshold = PriceChangeThreshold {
+            min_cents: 0,
+            min_percent: 5.0,
+        };
+
+        assert!(!threshold.is_notable(Cents(2000), Cents(1950)));
+        assert!(threshold.is_notable(Cents(2000), Cents(1900)));
+        assert!(threshold.is_notable(Cents(0), Cents(1900)));
+    }
+
+    #[test]
+    fn default_threshold_notifies_every_change() {
+        let threshold = PriceChangeThreshold::default();
+
+        assert!(threshold.is_notable(Cents(2000), Cents(1999)));
+        assert!(!threshold.is_notable(Cents(2000), Cents(2000)));
+    }
+
+    #[test]
+    fn no_stock_event_between_purchasable_states() {
+        let previous = Product {
+            availability: Availability::LowStock,
+            ..Product::example()
+        };
+        let current = Product {
+            availability: Availability::InStock,
+            ..previous.clone()
+        };
+
+        assert!(ProductEvent::between(&previous, &current).is_empty());
+    }
+
+    /// A price change `days` after `Product::example()` was created
+    fn change_after(days: i64, previous: u32, current: u32) -> PriceChange {
+        PriceChange {
+            at: Product::example().created_at + Duration::days(days),
+            previous: Cents(previous),
+            current: Cents(current),
+        }
+    }
+
+    #[test]
+    fn price_below_rolling_average_after_a_drop() {
+        let product = Product::example();
+        let now = product.created_at + Duration::days(20);
+        let changes = [change_after(10, 2999, product.price.0)];
+
+        let ratio = product
+            .price_vs_average(&changes, Duration::days(30), now)
+            .unwrap();
+
+        assert!((ratio - 2199.0 / 2599.0).abs() < 1e-4);
+        assert!(ratio < 1.0);
+        assert!(
+            product
+                .price_vs_average(&[], Duration::days(30), now)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn twenty_percent_drop_from_the_window_high() {
+        let product = Product::example();
+        let now = product.created_at + Duration::days(20);
+        let window = Duration::days(30);
+        let changes = [
+            change_after(5, 2399, 2749),
+            change_after(15, 2749, product.price.0),
+        ];
+
+        let drop = product.price_drop(&changes, window, now).unwrap();
+        assert!((drop - 0.2).abs() < 1e-3);
+
+        let rise = [change_after(15, 1999, product.price.0)];
+        assert_eq!(product.price_drop(&rise, window, now), None);
+        assert_eq!(product.price_drop(&[], window, now), None);
+    }
+
+    #[test]
+    fn largest_drops_come_first() {
+        let now = Product::example().created_at + Duration::days(20);
+        let changed_on_day_10 = |product: &Product, from: u32| {
+            let at = crate::clock::FixedClock(product.created_at + Duration::days(10));
+            let event = ProductEvent::PriceChanged {
+                previous: Cents(from),
+                current: product.price,
+            };
+            ProductEventRecord::new(product.uuid(), event, &at)
+        };
+
+        let ten_percent = Product {
+            price: Cents(900),
+            ..Product::example()
+        };
+        let thirty_percent = Product {
+            price: Cents(700),
+            ..Product::example()
+        };
+        let rose = Product {
+            price: Cents(1100),
+            ..Product::example()
+        };
+        let untracked = Product::example();
+
+        let by_product: HashMap<String, Vec<ProductEventRecord>> =
+            [(&ten_percent, 1000), (&thirty_percent, 1000), (&rose, 1000)]
+                .into_iter()
+                .map(|(p, from)| (p.uuid.to_uuid_string(), vec![changed_on_day_10(p, from)]))
+                .collect();
+
+        let products = vec![ten_percent.clone(), untracked, rose, thirty_percent.clone()];
+
+        let drops = largest_price_drops(
+            products.clone(),
+            by_product.clone(),
+            Duration::days(30),
+            10,
+            now,
+        );
+        assert_eq!(drops.len(), 2);
+        assert!(drops[0].0 == thirty_percent);
+        assert!((drops[0].1 - 0.3).abs() < 1e-4);
+        assert!(drops[1].0 == ten_percent);
+        assert!((drops[1].1 - 0.1).abs() < 1e-4);
+
+        let top = largest_price_drops(products, by_product, Duration::days(30), 1, now);
+        assert_eq!(top.len(), 1);
+        assert!(top[0].0 == thirty_percent);
+    }
+
+    #[test]
+    fn sparkline_downsamples_dense_history_over_the_window() {
+        let product = Product::example();
+        let now = product.created_at + Duration::days(60);
+        let window = Duration::days(30);
+
+        let price_on = |day: i64| if day % 2 == 0 { 2499 } else { 2299 };
+        let mut changes: Vec<PriceChange> = (1..59)
+            .map(|day| change_after(day, price_on(day - 1), price_on(day)))
+            .collect();
+        changes.push(change_after(59, price_on(58), product.price.0));
+
+        let sparkline = product.sparkline(&changes, 10, window, now);
+
+        assert_eq!(sparkline.len(), 10);
+        assert_eq!(sparkline[0].0, now - window);
+        assert_eq!(sparkline[9], (now, product.price));
+        assert!(sparkline.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        assert!(product.sparkline(&changes, 0, window, now).is_empty());
+        assert_eq!(
+            product.sparkline(&changes, 1, window, now),
+            vec![(now, product.price)]
+        );
+    }
+
+    #[test]
+    fn without_price_history_excludes_products_with_price_changes() {
+        let sql = Product::without_price_history_statement();
+
+        assert!(sql.starts_with(&format!(
+            "SELECT * FROM products WHERE {} NOT IN ",
+            Product::UUID_FIELD
+        )));
+        assert!(sql.ends_with(
+            "(SELECT VALUE product_uuid FROM product_events WHERE event.type = 'PriceChanged')"
+        ));
+
+        let with_history = Product::example();
+        let record = ProductEventRecord::new(
+            with_history.uuid(),
+            ProductEvent::PriceChanged {
+                previous: Cents(2499),
+                current: with_history.price,
+            },
+            &SystemClock,
+        );
+        let stored = serde_json::to_value(&record).unwrap();
+
+        assert_eq!(stored["event"]["type"], "PriceChanged");
+        assert_eq!(
+            stored["product_uuid"],
+            serde_json::to_value(with_history.uuid()).unwrap()
+        );
+    }
+
+    #[test]
+    fn rapid_same_price_updates_add_one_history_row() {
+        let config = PriceHistoryConfig {
+            min_history_interval: Duration::minutes(5),
+        };
+        let start = Product::example().created_at;
+        let record = |rows: &mut Vec<PriceChange>, config: &PriceHistoryConfig, secs, price| {
+            let now = start + Duration::seconds(secs);
+            if config.records(rows.last(), Cents(price), now) {
+                rows.push(PriceChange {
+                    at: now,
+                    previous: Cents(2499),
+                    current: Cents(price),
+                });
+            }
+        };
+
+        let mut rows = Vec::new();
+        record(&mut rows, &config, 0, 1999);
+        record(&mut rows, &config, 10, 1999);
+        assert_eq!(rows.len(), 1);
+
+        record(&mut rows, &config, 20, 1799);
+        assert_eq!(rows.len(), 2);
+        record(&mut rows, &config, 20 + 5 * 60, 1799);
+        assert_eq!(rows.len(), 3);
+
+        let mut unthrottled = Vec::new();
+        record(&mut unthrottled, &PriceHistoryConfig::default(), 0, 1999);
+        record(&mut unthrottled, &PriceHistoryConfig::default(), 10, 1999);
+        assert_eq!(unthrottled.len(), 2);
+    }
+
+    #[test]
+    fn discounted_price_is_a_notable_sale() {
+        let product = Product::example();
+        let now = product.created_at + Duration::days(40);
+        let window = Duration::days(30);
+        let changes = [
+            change_after(12, 2999, 2899),
+            change_after(20, 2899, 2999),
+            change_after(30, 2999, 2949),
+            change_after(35, 2949, product.price.0),
+        ];
+
+        assert!(product.notable_sale(&changes, window, 20, now));
+        assert!(!product.notable_sale(&changes, window, 30, now));
+        assert!(!product.notable_sale(&[], window, 20, now));
+    }
+
+    #[test]
+    fn flat_history_is_more_stable_than_a_volatile_one() {
+        let product = Product::example();
+        let now = product.created_at + Duration::days(30);
+        let window = Duration::days(30);
+
+        let flat = [change_after(29, 2249, product.price.0)];
+        let volatile: Vec<PriceChange> = (1..30)
+            .map(|day| {
+                let price = |day: i64| match day {
+                    29 => product.price.0,
+                    d if d % 2 == 0 => 1099,
+                    _ => 3999,
+                };
+                change_after(day, price(day - 1), price(day))
+            })
+            .collect();
+
+        let flat = product.stability(&flat, window, now).unwrap();
+        let volatile = product.stability(&volatile, window, now).unwrap();
+
+        assert!(flat > 0.95, "{flat}");
+        assert!(volatile < 0.6, "{volatile}");
+        assert_eq!(product.stability(&[], window, now), None);
+    }
+
+    #[test]
+    fn previous_price_and_direction_after_a_drop() {
+        let product = Product::example();
+        let records: Vec<ProductEventRecord> = [(2, 1999, 2299), (5, 2299, product.price.0)]
+            .into_iter()
+            .map(|(day, previous, current)| {
+                let at = crate::clock::FixedClock(product.created_at + Duration::days(day));
+                let event = ProductEvent::PriceChanged {
+                    previous: Cents(previous),
+                    current: Cents(current),
+                };
+                ProductEventRecord::new(product.uuid(), event, &at)
+            })
+            .chain([ProductEventRecord::new(
+                product.uuid(),
+                ProductEvent::WentOutOfStock,
+                &SystemClock,
+            )])
+            .collect();
+
+        let previous = price_changes(records).last().map(|c| c.previous);
+
+        assert_eq!(previous, Some(Cents(2299)));
+        assert_eq!(product.direction_from(previous), PriceDirection::Down);
+        assert_eq!(
+            product.direction_from(Some(Cents(1999))),
+            PriceDirection::Up
+        );
+        assert_eq!(product.direction_from(None), PriceDirection::Same);
+    }
+
+    #[test]
+    fn normal_price_range_spans_the_middle_of_a_spread_history() {
+        let product = Product::example();
+        let now = product.created_at + Duration::days(25);
+        let changes = [
+            change_after(5, 2000, 2400),
+            change_after(10, 2400, 1800),
+            change_after(15, 1800, 2200),
+            change_after(20, 2200, product.price.0),
+        ];
+
+        let (low, high) = product
+            .normal_range(&changes, Duration::days(30), now)
+            .unwrap();
+
+        assert_eq!((low, high), (Cents(2000), Cents(2200)));
+        assert!(low <= product.price && product.price <= high);
+        assert_eq!(product.normal_range(&[], Duration::days(30), now), None);
+    }
+
+    #[test]
+    fn buy_hint_for_a_bargain_and_an_overpriced_product() {
+        let bargain = Product::example();
+        let now = bargain.created_at + Duration::days(85);
+        let history = [
+            change_after(1, 2500, 2600),
+            change_after(2, 2600, 2500),
+            change_after(3, 2500, 2550),
+            change_after(80, 2550, bargain.price.0),
+        ];
+
+        assert_eq!(bargain.buy_hint(&history, 0.1, now), BuyHint::BuyNow);
+        assert_eq!(bargain.buy_hint(&history, 0.6, now), BuyHint::Neutral);
+
+        let overpriced = Product {
+            price: Cents(2999),
+            ..Product::example()
+        };
+        let rise = [change_after(10, 2499, 2999)];
+
+        assert_eq!(overpriced.buy_hint(&rise, 0.9, now), BuyHint::Wait);
+        assert_eq!(overpriced.buy_hint(&[], 0.9, now), BuyHint::Neutral);
+    }
+}
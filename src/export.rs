@@ -0,0 +1,939 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use surreal_socket::error::SurrealSocketError;
+
+use crate::{
+    product::{Availability, FilamentMaterial, Grams, Product, ProductResponse},
+    query::ProductQuery,
+};
+
+/// CSV columns, in the order `Product::as_csv_row` produces them
+const CSV_COLUMNS: [&str; 14] = [
+    "uuid",
+    "name",
+    "brand",
+    "price",
+    "currency",
+    "price_per_kg",
+    "url",
+    "material",
+    "diameter_mm",
+    "weight_g",
+    "retailer",
+    "retailer_product_id",
+    "color",
+    "availability",
+];
+
+/// Surface finishes `Product::embedding_text` looks for in names and colors
+const FINISHES: [&str; 7] = [
+    "matte", "silk", "satin", "glossy", "metallic", "marble", "glitter",
+];
+
+/// Title of the feed produced by `build_feed`
+const FEED_TITLE: &str = "FilamentSeek deals";
+
+/// Permanent id of the feed produced by `build_feed`
+const FEED_ID: &str = "urn:filamentseek:deals";
+
+/// Feed-level author, which Atom requires when entries have none
+const FEED_AUTHOR: &str = "FilamentSeek";
+
+/// Columns of a comparison table, in `ComparisonRow` field order
+const COMPARISON_COLUMNS: [&str; 7] = [
+    "Name",
+    "Brand",
+    "Price",
+    "Price per kg",
+    "Price per meter",
+    "Retailer",
+    "URL",
+];
+
+/// Columns of a Google Merchant feed, in `MerchantRow` field order
+const MERCHANT_COLUMNS: [&str; 8] = [
+    "id",
+    "title",
+    "description",
+    "link",
+    "price",
+    "availability",
+    "custom_label_0",
+    "custom_label_1",
+];
+
+/// Product as a Google Merchant feed item
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerchantRow {
+    pub id: String,
+    pub title: String,
+    pub description: String,
+    pub link: String,
+    /// e.g. "19.95 USD"
+    pub price: String,
+    /// One of Merchant's "in_stock" or "out_of_stock"
+    pub availability: String,
+    /// Material, e.g. "PLA"
+    pub custom_label_0: String,
+    /// Diameter in millimeters, e.g. "1.75"
+    pub custom_label_1: String,
+}
+
+impl MerchantRow {
+    fn fields(&self) -> [&str; 8] {
+        [
+            &self.id,
+            &self.title,
+            &self.description,
+            &self.link,
+            &self.price,
+            &self.availability,
+            &self.custom_label_0,
+            &self.custom_label_1,
+        ]
+    }
+}
+
+/// Writes `rows` as a tab-separated Google Merchant feed with a header row
+pub fn write_merchant_feed<W: Write>(rows: &[MerchantRow], mut writer: W) -> io::Result<()> {
+    write_tsv_record(&mut writer, MERCHANT_COLUMNS.into_iter())?;
+
+    for row in rows {
+        write_tsv_record(&mut writer, row.fields().into_iter())?;
+    }
+
+    writer.flush()
+}
+
+/// Product as shown in a spreadsheet comparison table, with display-formatted values
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComparisonRow {
+    pub name: String,
+    pub brand: String,
+    /// e.g. "$21.99"
+    pub price: String,
+    /// e.g. "$21.99/kg", empty when the weight is unknown
+    pub price_per_kg: String,
+    /// e.g. "$0.07/m", empty when the length can't be estimated
+    pub price_per_meter: String,
+    pub retailer: String,
+    pub url: String,
+}
+
+impl ComparisonRow {
+    fn fields(&self) -> [&str; 7] {
+        [
+            &self.name,
+            &self.brand,
+            &self.price,
+            &self.price_per_kg,
+            &self.price_per_meter,
+            &self.retailer,
+            &self.url,
+        ]
+    }
+}
+
+/// Field separator for `write_comparison_table`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delimiter {
+    /// CSV, quoting fields as needed
+    Comma,
+    /// TSV, with tabs and line breaks inside fields replaced by spaces
+    Tab,
+}
+
+/// Writes `rows` as a table with a header row
+pub fn write_comparison_table<W: Write>(
+    rows: &[ComparisonRow],
+    delimiter: Delimiter,
+    mut writer: W,
+) -> io::Result<()> {
+    let write_row = |writer: &mut W, fields: [&str; 7]| match delimiter {
+        Delimiter::Comma => write_csv_record(writer, fields.into_iter()),
+        Delimiter::Tab => write_tsv_record(writer, fields.into_iter()),
+    };
+
+    write_row(&mut writer, COMPARISON_COLUMNS)?;
+
+    for row in rows {
+        write_row(&mut writer, row.fields())?;
+    }
+
+    writer.flush()
+}
+
+/// One entry of an Atom feed
+#[derive(Clone, Debug, PartialEq)]
+pub struct AtomEntry {
+    pub id: String,
+    pub title: String,
+    pub link: String,
+    pub summary: String,
+    pub updated: DateTime<Utc>,
+}
+
+impl AtomEntry {
+    fn to_xml(&self) -> String {
+        format!(
+            "  <entry>\n    <id>{}</id>\n    <title>{}</title>\n    <link href=\"{}\"/>\n    \
+             <updated>{}</updated>\n    <summary>{}</summary>\n  </entry>\n",
+            escape_xml(&self.id),
+            escape_xml(&self.title),
+            escape_xml(&self.link),
+            self.updated.to_rfc3339_opts(SecondsFormat::Secs, true),
+            escape_xml(&self.summary)
+        )
+    }
+}
+
+/// Atom feed with one entry per product, in the given order
+pub fn build_feed(products: &[Product]) -> String {
+    let updated = products
+        .iter()
+        .map(|p| p.updated_at)
+        .max()
+        .unwrap_or(DateTime::UNIX_EPOCH);
+
+    let mut feed = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n  \
+         <id>{}</id>\n  <title>{}</title>\n  <updated>{}</updated>\n  \
+         <author><name>{}</name></author>\n",
+        FEED_ID,
+        FEED_TITLE,
+        updated.to_rfc3339_opts(SecondsFormat::Secs, true),
+        FEED_AUTHOR
+    );
+
+    for product in products {
+        feed += &product.to_atom_entry().to_xml();
+    }
+
+    feed += "</feed>\n";
+    feed
+}
+
+impl Product {
+    /// Column names matching `as_csv_row`, for exporters that need the field set
+    pub fn field_headers() -> Vec<&'static str> {
+        CSV_COLUMNS.to_vec()
+    }
+
+    /// Field values for one CSV row, with prices in dollars and diameter in millimeters. The
+    /// price per kg is left blank for a product without a known weight.
+    pub fn as_csv_row(&self) -> Vec<String> {
+        let price_per_kg = if self.weight.0 > 0 {
+            self.price_per_kg.amount_string(&self.currency)
+        } else {
+            String::new()
+        };
+
+        vec![
+            self.uuid.to_uuid_string(),
+            self.name.clone(),
+            self.brand.clone().unwrap_or_default(),
+            self.price.amount_string(&self.currency),
+            self.currency.to_string(),
+            price_per_kg,
+            self.url.clone(),
+            self.material.to_string(),
+            self.diameter.mm_string(),
+            self.weight.0.to_string(),
+            self.retailer.to_string(),
+            self.retailer_product_id.clone(),
+            self.color.clone(),
+            self.availability.to_string(),
+        ]
+    }
+
+    /// OpenGraph meta tags (property, content) for link previews. The description only
+    /// quotes a price per kg when the weight is known.
+    pub fn to_opengraph(&self) -> Vec<(String, String)> {
+        let mut description = format!("{}, {} mm", self.material, self.diameter.mm_string());
+        if self.weight.0 > 0 {
+            description.push_str(&format!(
+                ", {}",
+                self.price_per_kg.price_string(&self.currency)
+            ));
+        }
+
+        [
+            ("og:type", "product".to_owned()),
+            ("og:title", self.name.clone()),
+            ("og:description", description),
+            ("og:url", self.url.clone()),
+            (
+                "product:price:amount",
+                self.price.amount_string(&self.currency),
+            ),
+            ("product:price:currency", self.currency.to_string()),
+        ]
+        .into_iter()
+        .map(|(property, content)| (property.to_owned(), content))
+        .collect()
+    }
+
+    /// Feed entry for deal feeds, summarizing price and material. The summary only quotes a
+    /// price per kg when the weight is known.
+    pub fn to_atom_entry(&self) -> AtomEntry {
+        let mut price = format!(
+            "{} {}",
+            self.price.amount_string(&self.currency),
+            self.currency
+        );
+        if self.weight.0 > 0 {
+            price.push_str(&format!(
+                " ({})",
+                self.price_per_kg.price_string(&self.currency)
+            ));
+        }
+
+        AtomEntry {
+            id: format!("urn:uuid:{}", self.uuid.to_uuid_string()),
+            title: self.name.clone(),
+            link: self.url.clone(),
+            summary: format!(
+                "{} at {}, {} mm, {}",
+                price,
+                self.retailer,
+                self.diameter.mm_string(),
+                self.material
+            ),
+            updated: self.updated_at,
+        }
+    }
+
+    /// One-line description for chat bots and notifications, e.g. "Hatchbox Black PLA,
+    /// 1.75mm, 1kg — $19.95 ($19.95/kg) on Amazon". Unknown brand, color, material, weight
+    /// and retailer are left out.
+    pub fn humanized_summary(&self) -> String {
+        let material = (!self.material.is_undetermined()).then(|| self.material.to_string());
+        let color = Some(self.color.trim().to_owned()).filter(|c| !c.is_empty());
+
+        let title = [self.brand.clone(), color, material]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        let mut specs = vec![format!("{}mm", self.diameter.mm_string())];
+
+        if self.weight.0 > 0 {
+            specs.push(weight_label(self.weight));
+        }
+
+        let mut summary = if title.is_empty() {
+            specs.join(", ")
+        } else {
+            format!("{}, {}", title, specs.join(", "))
+        };
+
+        summary += &format!(" — {}", self.price.price_string(&self.currency));
+
+        if self.weight.0 > 0 {
+            summary += &format!(
+                " ({})",
+                self.calculate_price_per_kg().price_string(&self.currency)
+            );
+        }
+
+        let retailer = self.retailer.to_string();
+        if !retailer.trim().is_empty() {
+            summary += &format!(" on {}", retailer);
+        }
+
+        summary
+    }
+
+    /// Descriptive sentence for an embedding model, e.g. "Hatchbox filament made of polylactic
+    /// acid (PLA) in Black with a matte finish, 1.75 mm diameter, 1kg spool." The finish is
+    /// spotted in the name or color. Unknown brand, material, color and weight are left out.
+    pub fn embedding_text(&self) -> String {
+        let mut text = match self.brand.as_deref().map(str::trim) {
+            Some(brand) if !brand.is_empty() => format!("{} filament", brand),
+            _ => "Filament".to_owned(),
+        };
+
+        if !self.material.is_undetermined() {
+            text += &format!(" made of {}", self.material.full_name());
+        }
+
+        let color = self
+            .color
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        if !color.is_empty() {
+            text += &format!(" in {}", color);
+        }
+
+        let described = format!("{} {}", self.name, self.color).to_lowercase();
+        if let Some(finish) = FINISHES.iter().find(|f| {
+            described
+                .split(|c: char| !c.is_alphanumeric())
+                .any(|word| word == **f)
+        }) {
+            text += &format!(" with a {} finish", finish);
+        }
+
+        text += &format!(", {} mm diameter", self.diameter.mm_string());
+
+        if self.weight.0 > 0 {
+            text += &format!(", {} spool", weight_label(self.weight));
+        }
+
+        text + "."
+    }
+
+    /// Row for a spreadsheet comparison table
+    pub fn comparison_row(&self) -> ComparisonRow {
+        let price_per_meter = self
+            .estimated_length_m()
+            .filter(|length| *length > 0.0)
+            .map(|length| {
+                let major = self.price.0 as f32 / 10f32.powi(self.currency.minor_units() as i32);
+                format!(
+                    "{}/m",
+                    self.currency.label(&format!("{:.2}", major / length))
+                )
+            })
+            .unwrap_or_default();
+        let price_per_kg = if self.weight.0 > 0 {
+            self.price_per_kg.price_string(&self.currency)
+        } else {
+            String::new()
+        };
+
+        ComparisonRow {
+            name: self.name.clone(),
+            brand: self.brand.clone().unwrap_or_default(),
+            price: self.price.price_string(&self.currency),
+            price_per_kg,
+            price_per_meter,
+            retailer: self.retailer.to_string(),
+            url: self.url.clone(),
+        }
+    }
+
+    /// Item for a Google Merchant shopping feed. Products of unknown availability are listed
+    /// as out of stock rather than advertised.
+    pub fn to_merchant_row(&self) -> MerchantRow {
+        let availability = match self.availability {
+            Availability::InStock | Availability::LowStock => "in_stock",
+            Availability::OutOfStock | Availability::Unknown => "out_of_stock",
+        };
+
+        let description = match &self.brand {
+            Some(brand) => format!(
+                "{} {} filament, {} mm, {} g",
+                brand,
+                self.material,
+                self.diameter.mm_string(),
+                self.weight.0
+            ),
+            None => format!(
+                "{} filament, {} mm, {} g",
+                self.material,
+                self.diameter.mm_string(),
+                self.weight.0
+            ),
+        };
+
+        MerchantRow {
+            id: self.uuid.to_uuid_string(),
+            title: self.name.clone(),
+            description,
+            link: self.url.clone(),
+            price: format!(
+                "{} {}",
+                self.price.amount_string(&self.currency),
+                self.currency
+            ),
+            availability: availability.to_owned(),
+            custom_label_0: self.material.to_string(),
+            custom_label_1: self.diameter.mm_string(),
+        }
+    }
+
+    /// Writes `products` as CSV with a header row
+    pub fn write_csv<W: Write>(products: &[Product], mut writer: W) -> io::Result<()> {
+        write_csv_record(&mut writer, Self::field_headers().into_iter())?;
+
+        for product in products {
+            write_csv_record(&mut writer, product.as_csv_row().iter().map(String::as_str))?;
+        }
+
+        writer.flush()
+    }
+
+    /// Writes `products` as newline-delimited JSON, one product per line
+    pub fn write_ndjson<W: Write>(products: &[Product], mut writer: W) -> io::Result<()> {
+        for product in products {
+            serde_json::to_writer(&mut writer, product)?;
+            writer.write_all(b"\n")?;
+        }
+
+        writer.flush()
+    }
+
+    /// Reads products written by `write_ndjson`, skipping blank lines
+    pub fn read_ndjson<R: Read>(reader: R) -> io::Result<Vec<Product>> {
+        let mut products = Vec::new();
+
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+
+            if !line.trim().is_empty() {
+                products.push(serde_json::from_str(&line)?);
+            }
+        }
+
+        Ok(products)
+    }
+
+    /// Gzip-compressed `write_ndjson`
+    #[cfg(feature = "flate2")]
+    pub fn write_ndjson_gz<W: Write>(products: &[Product], writer: W) -> io::Result<()> {
+        let mut encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+        Self::write_ndjson(products, &mut encoder)?;
+        encoder.finish()?.flush()
+    }
+
+    /// Reads products written by `write_ndjson_gz`
+    #[cfg(feature = "flate2")]
+    pub fn read_ndjson_gz<R: Read>(reader: R) -> io::Result<Vec<Product>> {
+        Self::read_ndjson(flate2::read::GzDecoder::new(reader))
+    }
+
+    /// Products matching `query`, in search order, as a JSON array of `ProductResponse`
+    pub async fn export_json(query: &ProductQuery) -> Result<String, SurrealSocketError> {
+        Ok(Self::json_array(Self::search(query).await?)?)
+    }
+
+    /// `products` as a JSON array of `ProductResponse`
+    fn json_array(products: Vec<Product>) -> serde_json::Result<String> {
+        let responses: Vec<ProductResponse> =
+            products.into_iter().map(ProductResponse::from).collect();
+
+        serde_json::to_string(&responses)
+    }
+}
+
+impl FilamentMaterial {
+    /// Chemical name with the abbreviation, e.g. "polylactic acid (PLA)". `Other` materials
+    /// are given as stored.
+    pub fn full_name(&self) -> String {
+        match self {
+            Self::PLA => "polylactic acid (PLA)".to_owned(),
+            Self::PLAPlus => "enhanced polylactic acid (PLA+)".to_owned(),
+            Self::ABS => "acrylonitrile butadiene styrene (ABS)".to_owned(),
+            Self::PETG => "polyethylene terephthalate glycol (PETG)".to_owned(),
+            Self::TPU => "thermoplastic polyurethane (TPU)".to_owned(),
+            Self::Nylon => "polyamide (nylon)".to_owned(),
+            Self::PC => "polycarbonate (PC)".to_owned(),
+            Self::ASA => "acrylonitrile styrene acrylate (ASA)".to_owned(),
+            Self::PCTG => "polycyclohexylenedimethylene terephthalate glycol (PCTG)".to_owned(),
+            Self::Unspecified | Self::Unknown => "unspecified material".to_owned(),
+            Self::Other(other) => other.trim().to_owned(),
+        }
+    }
+}
+
+/// Weight as "1kg", "2.5kg" or "750g"
+fn weight_label(weight: Grams) -> String {
+    if weight.0 % 1000 == 0 && weight.0 > 0 {
+        format!("{}kg", weight.0 / 1000)
+    } else if weight.0 >= 1000 {
+        format!("{}kg", weight.0 as f32 / 1000.0)
+    } else {
+        format!("{}g", weight.0)
+    }
+}
+
+fn write_csv_record<'a, W: Write>(
+    writer: &mut W,
+    fields: impl Iterator<Item = &'a str>,
+) -> io::Result<()> {
+    let line = fields
+        .map(escape_csv_field)
+        .collect::<Vec<String>>()
+        .join(",");
+    writeln!(writer, "{}", line)
+}
+
+/// Writes one tab-separated line, with tabs and line breaks inside fields replaced by spaces
+fn write_tsv_record<'a, W: Write>(
+    writer: &mut W,
+    fields: impl Iterator<Item = &'a str>,
+) -> io::Result<()> {
+    let line = fields
+        .map(|f| f.replace(['\t', '\n', '\r'], " "))
+        .collect::<Vec<String>>()
+        .join("\t");
+    writeln!(writer, "{}", line)
+}
+
+/// Quotes a field when it contains a delimiter, quote or line break
+fn escape_csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Escapes text for XML element content and attribute values
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::product::{Cents, CentsPerKg, Currency, Grams, Retailer};
+
+    #[test]
+    fn csv_row_has_a_value_per_column() {
+        let product = Product::example();
+        let row = product.as_csv_row();
+
+        assert_eq!(row.len(), CSV_COLUMNS.len());
+        assert_eq!(row[0], product.uuid.to_uuid_string());
+        assert_eq!(row[3], "21.99");
+        assert_eq!(row[8], "1.75");
+        assert_eq!(row[9], "1000");
+
+        let mut csv = Vec::new();
+        Product::write_csv(&[product], &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some(CSV_COLUMNS.join(",").as_str()));
+        assert!(
+            lines
+                .next()
+                .unwrap()
+                .contains("\"Hatchbox PLA 3D Printer Filament, 1.75 mm, 1 kg Spool, Black\"")
+        );
+    }
+
+    #[test]
+    fn csv_price_per_kg_blank_without_weight() {
+        let column = CSV_COLUMNS
+            .iter()
+            .position(|c| *c == "price_per_kg")
+            .unwrap();
+        let weightless = Product {
+            weight: Grams(0),
+            price_per_kg: CentsPerKg(0),
+            ..Product::example()
+        };
+
+        assert_eq!(Product::example().as_csv_row()[column], "21.99");
+        assert_eq!(weightless.as_csv_row()[column], "");
+    }
+
+    fn in_yen() -> Product {
+        Product {
+            currency: Currency::JPY,
+            ..Product::example()
+        }
+    }
+
+    #[test]
+    fn comparison_row_money_in_product_currency() {
+        let usd = Product::example().comparison_row();
+        assert_eq!(usd.price, "$21.99");
+        assert_eq!(usd.price_per_kg, "$21.99/kg");
+        assert!(usd.price_per_meter.starts_with('$') && usd.price_per_meter.ends_with("/m"));
+
+        let jpy = in_yen().comparison_row();
+        assert_eq!(jpy.price, "2199 JPY");
+        assert_eq!(jpy.price_per_kg, "2199 JPY/kg");
+        assert!(jpy.price_per_meter.ends_with(" JPY/m"));
+    }
+
+    #[test]
+    fn csv_price_per_kg_in_product_currency() {
+        let column = CSV_COLUMNS
+            .iter()
+            .position(|c| *c == "price_per_kg")
+            .unwrap();
+
+        assert_eq!(Product::example().as_csv_row()[column], "21.99");
+        assert_eq!(in_yen().as_csv_row()[column], "2199");
+    }
+
+    fn few_products() -> Vec<Product> {
+        vec![
+            Product::example(),
+            in_yen(),
+            Product {
+                material: FilamentMaterial::Other("PLA Wood".to_owned()),
+                ..Product::example()
+            },
+        ]
+    }
+
+    #[test]
+    fn ndjson_round_trips() {
+        let products = few_products();
+
+        let mut ndjson = Vec::new();
+        Product::write_ndjson(&products, &mut ndjson).unwrap();
+        assert_eq!(ndjson.iter().filter(|b| **b == b'\n').count(), 3);
+
+        ndjson.extend_from_slice(b"\n  \n");
+        assert!(Product::read_ndjson(ndjson.as_slice()).unwrap() == products);
+    }
+
+    #[cfg(feature = "flate2")]
+    #[test]
+    fn gzipped_ndjson_round_trips() {
+        let products = few_products();
+
+        let mut compressed = Vec::new();
+        Product::write_ndjson_gz(&products, &mut compressed).unwrap();
+
+        assert_eq!(&compressed[..2], [0x1f, 0x8b]);
+        assert!(Product::read_ndjson_gz(compressed.as_slice()).unwrap() == products);
+    }
+
+    #[test]
+    fn headers_line_up_with_csv_rows() {
+        let headers = Product::field_headers();
+
+        assert_eq!(headers.len(), Product::example().as_csv_row().len());
+        assert_eq!(headers.first(), Some(&"uuid"));
+        assert_eq!(headers.last(), Some(&"availability"));
+    }
+
+    #[test]
+    fn opengraph_has_title_and_price() {
+        let tags = Product::example().to_opengraph();
+        let tag = |property: &str| {
+            tags.iter()
+                .find(|(p, _)| p == property)
+                .map(|(_, content)| content.as_str())
+        };
+
+        assert_eq!(
+            tag("og:title"),
+            Some("Hatchbox PLA 3D Printer Filament, 1.75 mm, 1 kg Spool, Black")
+        );
+        assert_eq!(tag("product:price:amount"), Some("21.99"));
+        assert_eq!(tag("product:price:currency"), Some("USD"));
+        assert_eq!(tag("og:description"), Some("PLA, 1.75 mm, $21.99/kg"));
+    }
+
+    #[test]
+    fn opengraph_description_omits_price_per_kg_without_weight() {
+        let product = Product {
+            weight: Grams(0),
+            ..Product::example()
+        };
+        let tags = product.to_opengraph();
+
+        assert!(tags.contains(&("og:description".to_owned(), "PLA, 1.75 mm".to_owned())));
+    }
+
+    #[test]
+    fn json_export_of_a_material_filtered_subset() {
+        let query = ProductQuery {
+            material: Some(FilamentMaterial::PETG),
+            ..ProductQuery::default()
+        };
+        let petg = Product {
+            material: FilamentMaterial::PETG,
+            name: "Hatchbox PETG 1.75 mm 1 kg Black".to_owned(),
+            ..Product::example()
+        };
+        let products = vec![Product::example(), petg.clone(), Product::example()];
+
+        let subset: Vec<Product> = products.into_iter().filter(|p| p.matches(&query)).collect();
+        let json = Product::json_array(subset).unwrap();
+
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0]["material"], "PETG");
+        assert_eq!(parsed[0]["name"], petg.name.as_str());
+    }
+
+    #[test]
+    fn atom_feed_has_an_entry_per_product() {
+        let escaped = Product {
+            name: "PLA & PETG <sampler>".to_owned(),
+            ..Product::example()
+        };
+        let products = [Product::example(), escaped];
+
+        let feed = build_feed(&products);
+
+        assert!(feed.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n"));
+        assert!(feed.contains("<feed xmlns=\"http://www.w3.org/2005/Atom\">"));
+        assert!(feed.trim_end().ends_with("</feed>"));
+        assert_eq!(feed.matches("<entry>").count(), products.len());
+        assert_eq!(feed.matches("</entry>").count(), products.len());
+
+        for product in &products {
+            let id = format!("<id>urn:uuid:{}</id>", product.uuid.to_uuid_string());
+            assert!(feed.contains(&id));
+        }
+        for element in ["<title>", "<link href=", "<updated>", "<summary>"] {
+            assert!(feed.matches(element).count() >= products.len(), "{element}");
+        }
+
+        assert!(feed.contains("<title>PLA &amp; PETG &lt;sampler&gt;</title>"));
+        assert!(feed.contains("<updated>2025-10-09T08:53:20Z</updated>"));
+    }
+
+    #[test]
+    fn atom_summary_omits_price_per_kg_without_weight() {
+        let weighed = Product::example().to_atom_entry();
+        assert_eq!(
+            weighed.summary,
+            "21.99 USD ($21.99/kg) at Amazon, 1.75 mm, PLA"
+        );
+
+        let weightless = Product {
+            weight: Grams(0),
+            ..Product::example()
+        };
+        assert_eq!(
+            weightless.to_atom_entry().summary,
+            "21.99 USD at Amazon, 1.75 mm, PLA"
+        );
+    }
+
+    #[test]
+    fn comparison_table_rows_match_products() {
+        let mut cheaper = Product {
+            price: Cents(1799),
+            name: "Hatchbox PLA\t1.75 mm".to_owned(),
+            ..Product::example()
+        };
+        cheaper.price_per_kg = cheaper.calculate_price_per_kg();
+        let rows = [
+            Product::example().comparison_row(),
+            cheaper.comparison_row(),
+        ];
+
+        assert_eq!(
+            rows[1].price_per_kg,
+            cheaper.price_per_kg.price_string(&cheaper.currency)
+        );
+        assert_eq!(rows[1].price_per_kg, "$17.99/kg");
+
+        let mut tsv = Vec::new();
+        write_comparison_table(&rows, Delimiter::Tab, &mut tsv).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        let lines: Vec<&str> = tsv.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], COMPARISON_COLUMNS.join("\t"));
+        assert!(lines[2].starts_with("Hatchbox PLA 1.75 mm\tHatchbox\t$17.99\t$17.99/kg\t"));
+
+        let mut csv = Vec::new();
+        write_comparison_table(&rows, Delimiter::Comma, &mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn comparison_row_omits_price_per_kg_without_weight() {
+        let weightless = Product {
+            weight: Grams(0),
+            ..Product::example()
+        };
+
+        assert_eq!(weightless.comparison_row().price_per_kg, "");
+    }
+
+    #[test]
+    fn merchant_row_price_and_required_fields() {
+        let product = Product::example();
+        let row = product.to_merchant_row();
+
+        assert_eq!(row.price, "21.99 USD");
+        assert_eq!(row.id, product.uuid.to_uuid_string());
+        assert_eq!(row.title, product.name);
+        assert_eq!(row.link, product.url);
+        assert_eq!(row.description, "Hatchbox PLA filament, 1.75 mm, 1000 g");
+        assert_eq!(row.availability, "in_stock");
+        assert_eq!(row.custom_label_0, "PLA");
+        assert_eq!(row.custom_label_1, "1.75");
+
+        assert_eq!(in_yen().to_merchant_row().price, "2199 JPY");
+        let unknown = Product {
+            availability: Availability::Unknown,
+            ..Product::example()
+        };
+        assert_eq!(unknown.to_merchant_row().availability, "out_of_stock");
+
+        let mut feed = Vec::new();
+        write_merchant_feed(&[row], &mut feed).unwrap();
+        let feed = String::from_utf8(feed).unwrap();
+        let lines: Vec<&str> = feed.lines().collect();
+        assert_eq!(lines[0], MERCHANT_COLUMNS.join("\t"));
+        assert_eq!(lines[1].split('\t').count(), MERCHANT_COLUMNS.len());
+    }
+
+    #[test]
+    fn humanized_summary_of_a_full_product() {
+        assert_eq!(
+            Product::example().humanized_summary(),
+            "Hatchbox Black PLA, 1.75mm, 1kg — $21.99 ($21.99/kg) on Amazon"
+        );
+
+        let yen = Product {
+            price: Cents(2598),
+            currency: Currency::JPY,
+            ..Product::example()
+        };
+        assert_eq!(
+            yen.humanized_summary(),
+            "Hatchbox Black PLA, 1.75mm, 1kg — 2598 JPY (2598 JPY/kg) on Amazon"
+        );
+    }
+
+    #[test]
+    fn humanized_summary_omits_unknown_pieces() {
+        let sparse = Product {
+            brand: None,
+            color: " ".to_owned(),
+            material: FilamentMaterial::Unspecified,
+            weight: Grams(0),
+            retailer: Retailer::Other(String::new()),
+            ..Product::example()
+        };
+
+        assert_eq!(sparse.humanized_summary(), "1.75mm — $21.99");
+    }
+
+    #[test]
+    fn embedding_text_names_the_material_and_color() {
+        let product = Product {
+            name: "Hatchbox PLA 1.75mm 1kg".to_owned(),
+            color: "Matte  Black".to_owned(),
+            ..Product::example()
+        };
+
+        let text = product.embedding_text();
+
+        assert!(text.contains("polylactic acid (PLA)"));
+        assert!(text.contains("in Matte Black"));
+        assert_eq!(
+            text,
+            format!(
+                "Hatchbox filament made of {} in Matte Black with a matte finish, \
+                 1.75 mm diameter, 1kg spool.",
+                FilamentMaterial::PLA.full_name()
+            )
+        );
+    }
+}
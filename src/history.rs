@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use surreal_socket::{
+    dbrecord::{DBRecord, SsUuid},
+    error::SurrealSocketError,
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    product::{Availability, Product, Retailer},
+    surrealdb_client,
+};
+
+/// Availability a product had from `recorded_at` until the next entry, for restock analytics
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct AvailabilityHistory {
+    pub uuid: SsUuid<AvailabilityHistory>,
+    pub product_uuid: SsUuid<Product>,
+    pub availability: Availability,
+    /// Stored as milliseconds since the epoch so entries sort correctly
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub recorded_at: DateTime<Utc>,
+}
+
+impl DBRecord for AvailabilityHistory {
+    fn uuid(&self) -> SsUuid<Self> {
+        self.uuid.to_owned()
+    }
+
+    const TABLE_NAME: &'static str = "availability_history";
+}
+
+impl AvailabilityHistory {
+    /// `record_transition_with` the system clock
+    pub async fn record_transition(product: &Product) -> Result<(), SurrealSocketError> {
+        Self::record_transition_with(product, &SystemClock).await
+    }
+
+    /// Appends `product`'s availability, timestamped from `clock`, unless it is the same as
+    /// the latest entry. Called from the product update hook, so each insert and save records
+    /// a transition if any.
+    pub async fn record_transition_with(
+        product: &Product,
+        clock: &dyn Clock,
+    ) -> Result<(), SurrealSocketError> {
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT * FROM {} WHERE product_uuid = $product_uuid \
+             ORDER BY recorded_at DESC LIMIT 1",
+            Self::table()
+        );
+
+        let latest: Option<AvailabilityHistory> = client
+            .query(query)
+            .bind(("product_uuid", product.uuid()))
+            .await?
+            .take(0)?;
+
+        if !Self::is_transition(latest.as_ref(), product.availability) {
+            return Ok(());
+        }
+
+        let entry = AvailabilityHistory {
+            uuid: SsUuid::new(),
+            product_uuid: product.uuid(),
+            availability: product.availability,
+            recorded_at: clock.now(),
+        };
+
+        let query = format!("CREATE {} CONTENT $entry", Self::table());
+        client.query(query).bind(("entry", entry)).await?.check()?;
+
+        Ok(())
+    }
+
+    /// Whether `availability` differs from the `latest` entry, if there is one
+    fn is_transition(latest: Option<&AvailabilityHistory>, availability: Availability) -> bool {
+        latest.is_none_or(|l| l.availability != availability)
+    }
+}
+
+impl Product {
+    /// Applies a scraped availability snapshot of `retailer`'s listings, keyed by retailer
+    /// product id. Each product whose availability changed is saved, which logs the
+    /// transition and emits its stock events. Ids without a stored product are ignored.
+    /// Returns the number of products updated.
+    pub async fn apply_availability_snapshot(
+        retailer: &Retailer,
+        statuses: &HashMap<String, Availability>,
+    ) -> Result<usize, SurrealSocketError> {
+        let statuses: HashMap<String, Availability> = statuses
+            .iter()
+            .map(|(id, availability)| (retailer.normalize_product_id(id), *availability))
+            .collect();
+
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT * FROM {} WHERE retailer = $retailer AND retailer_product_id IN $ids",
+            Self::table()
+        );
+
+        let products: Vec<Product> = client
+            .query(query)
+            .bind(("retailer", retailer.to_string()))
+            .bind(("ids", statuses.keys().cloned().collect::<Vec<String>>()))
+            .await?
+            .take(0)?;
+
+        let changed = availability_changes(products, &statuses);
+
+        for product in &changed {
+            product.save().await?;
+        }
+
+        Ok(changed.len())
+    }
+
+    /// Recorded availability transitions, oldest first
+    pub async fn availability_history(
+        &self,
+    ) -> Result<Vec<AvailabilityHistory>, SurrealSocketError> {
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT * FROM {} WHERE product_uuid = $product_uuid ORDER BY recorded_at ASC",
+            AvailabilityHistory::table()
+        );
+
+        let mut response = client
+            .query(query)
+            .bind(("product_uuid", self.uuid()))
+            .await?;
+
+        Ok(response.take(0)?)
+    }
+}
+
+/// `products` whose availability differs from their entry in `statuses` (keyed by
+/// normalized retailer product id), with that availability applied
+fn availability_changes(
+    products: Vec<Product>,
+    statuses: &HashMap<String, Availability>,
+) -> Vec<Product> {
+    products
+        .into_iter()
+        .filter_map(|product| {
+            let availability = *statuses.get(&product.retailer_product_id)?;
+
+            (availability != product.availability).then(|| Product {
+                availability,
+                ..product
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn in_out_in_records_three_entries() {
+        let start = DateTime::from_timestamp(1_760_000_000, 0).unwrap();
+        let product = Product::example();
+        let mut history: Vec<AvailabilityHistory> = Vec::new();
+
+        let transitions = [
+            Availability::InStock,
+            Availability::InStock,
+            Availability::OutOfStock,
+            Availability::InStock,
+        ];
+
+        for (hour, availability) in transitions.into_iter().enumerate() {
+            if AvailabilityHistory::is_transition(history.last(), availability) {
+                history.push(AvailabilityHistory {
+                    uuid: SsUuid::new(),
+                    product_uuid: product.uuid(),
+                    availability,
+                    recorded_at: start + Duration::hours(hour as i64),
+                });
+            }
+        }
+
+        let states: Vec<Availability> = history.iter().map(|e| e.availability).collect();
+        assert_eq!(
+            states,
+            vec![
+                Availability::InStock,
+                Availability::OutOfStock,
+                Availability::InStock
+            ]
+        );
+    }
+
+    #[test]
+    fn snapshot_flips_listed_products_out_of_stock() {
+        use crate::event::ProductEvent;
+
+        let listing = |id: &str| Product {
+            retailer_product_id: id.to_owned(),
+            availability: Availability::InStock,
+            ..Product::example()
+        };
+        let products = vec![
+            listing("B0AAAAAAA1"),
+            listing("B0AAAAAAA2"),
+            listing("B0AAAAAAA3"),
+        ];
+
+        let snapshot: HashMap<String, Availability> = [
+            (" b0aaaaaaa1 ", Availability::OutOfStock),
+            ("B0AAAAAAA2", Availability::InStock),
+            ("B0UNKNOWN0", Availability::OutOfStock),
+        ]
+        .into_iter()
+        .map(|(id, availability)| (Retailer::Amazon.normalize_product_id(id), availability))
+        .collect();
+
+        let changed = availability_changes(products.clone(), &snapshot);
+
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].retailer_product_id, "B0AAAAAAA1");
+        assert_eq!(changed[0].availability, Availability::OutOfStock);
+        assert_eq!(
+            ProductEvent::between(&products[0], &changed[0]),
+            vec![ProductEvent::WentOutOfStock]
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use surreal_socket::error::SurrealSocketError;
+
+use crate::product::{Product, Retailer};
+
+/// Settings enforced when products are written as new records
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IngestConfig {
+    /// Retailers products may be ingested from, or any when `None`
+    pub allowed_retailers: Option<Vec<Retailer>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IngestError {
+    RetailerNotAllowed(Retailer),
+}
+
+impl std::fmt::Display for IngestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RetailerNotAllowed(retailer) => {
+                write!(
+                    f,
+                    "Retailer {:?} is not allowed for ingestion",
+                    retailer.to_string()
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for IngestError {}
+
+impl From<IngestError> for String {
+    fn from(e: IngestError) -> String {
+        e.to_string()
+    }
+}
+
+/// Error from `Product::insert` and the other writes of new records
+#[derive(Debug)]
+pub enum InsertError {
+    /// Rejected by `IngestConfig` before anything was written
+    Rejected(IngestError),
+    Db(SurrealSocketError),
+}
+
+impl From<IngestError> for InsertError {
+    fn from(e: IngestError) -> Self {
+        Self::Rejected(e)
+    }
+}
+
+impl From<SurrealSocketError> for InsertError {
+    fn from(e: SurrealSocketError) -> Self {
+        Self::Db(e)
+    }
+}
+
+/// For callers that report every failure as a database error, e.g. `touch_or_insert`
+impl From<InsertError> for SurrealSocketError {
+    fn from(e: InsertError) -> Self {
+        match e {
+            InsertError::Rejected(e) => String::from(e).into(),
+            InsertError::Db(e) => e,
+        }
+    }
+}
+
+impl IngestConfig {
+    /// Reads the allowed retailers as a comma-separated list from
+    /// `FILAMENTSEEK_ALLOWED_RETAILERS`, where unset or blank allows any
+    pub fn from_env() -> Self {
+        let allowed: Vec<Retailer> = std::env::var("FILAMENTSEEK_ALLOWED_RETAILERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|r| !r.is_empty())
+            .map(|r| Retailer::from(r.to_owned()))
+            .collect();
+
+        Self {
+            allowed_retailers: (!allowed.is_empty()).then_some(allowed),
+        }
+    }
+
+    /// Whether `product` may be ingested under this config
+    pub fn check(&self, product: &Product) -> Result<(), IngestError> {
+        match &self.allowed_retailers {
+            Some(allowed) if !allowed.contains(&product.retailer) => {
+                Err(IngestError::RetailerNotAllowed(product.retailer.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::SystemClock;
+
+    use super::*;
+
+    #[test]
+    fn allowlist_admits_listed_retailers_only() {
+        let config = IngestConfig {
+            allowed_retailers: Some(vec![Retailer::Amazon]),
+        };
+        let blocked = Product {
+            retailer: Retailer::Other("SomeShop".to_owned()),
+            ..Product::example()
+        };
+
+        assert_eq!(config.check(&Product::example()), Ok(()));
+        assert_eq!(
+            config.check(&blocked),
+            Err(IngestError::RetailerNotAllowed(blocked.retailer.clone()))
+        );
+        assert_eq!(
+            String::from(config.check(&blocked).unwrap_err()),
+            "Retailer \"SomeShop\" is not allowed for ingestion"
+        );
+        assert_eq!(IngestConfig::default().check(&blocked), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn inserts_reject_disallowed_retailers_before_writing() {
+        let config = IngestConfig {
+            allowed_retailers: Some(vec![Retailer::Amazon]),
+        };
+        let blocked = Product {
+            retailer: Retailer::Other("SomeShop".to_owned()),
+            ..Product::example()
+        };
+        let rejected = |e: InsertError| match e {
+            InsertError::Rejected(IngestError::RetailerNotAllowed(retailer)) => {
+                retailer == blocked.retailer
+            }
+            _ => false,
+        };
+
+        let single = blocked.insert_with(&config, &SystemClock).await;
+        assert!(single.is_err_and(rejected));
+
+        let many = Product::insert_many_without_hook_with(
+            &[Product::example(), blocked.clone()],
+            &config,
+            &SystemClock,
+        )
+        .await;
+        assert!(many.is_err_and(rejected));
+    }
+}
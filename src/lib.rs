@@ -1,53 +1,66 @@
-use surrealdb::{Surreal, engine::remote::ws::Ws, opt::auth::Root};
+use surrealdb::Surreal;
 
+pub mod alert;
+#[cfg(feature = "sqlite-cache")]
+pub mod cache;
+pub mod clock;
+pub mod color;
+pub mod compare;
+pub mod config;
+pub mod dedupe;
+pub mod enrich;
+pub mod event;
+pub mod export;
+pub mod history;
+pub mod ingest;
+pub mod loose;
+#[cfg(feature = "prometheus")]
+pub mod metrics;
 pub mod product;
+#[cfg(feature = "grpc")]
+pub mod proto;
+pub mod quality;
+pub mod query;
+#[cfg(feature = "reqwest")]
+pub mod reachability;
+pub mod share;
+pub mod stats;
+pub mod tags;
+pub mod wishlist;
 
-pub async fn surrealdb_client() -> Result<Surreal<surrealdb::engine::remote::ws::Client>, String> {
-    let surreal_address = if let Ok(addr) = std::env::var("SURREAL_ADDRESS") {
-        addr
-    } else {
-        return Err("SURREAL_ADDRESS environment variable not set".to_owned());
-    };
-
-    let surreal_username = if let Ok(user) = std::env::var("SURREAL_USERNAME") {
-        user
-    } else {
-        return Err("SURREAL_USERNAME environment variable not set".to_owned());
-    };
+use config::SurrealConfig;
 
-    let surreal_password = if let Ok(pass) = std::env::var("SURREAL_PASSWORD") {
-        pass
-    } else {
-        return Err("SURREAL_PASSWORD environment variable not set".to_owned());
-    };
-
-    let surreal_namespace = if let Ok(ns) = std::env::var("SURREAL_NAMESPACE") {
-        ns
-    } else {
-        return Err("SURREAL_NAMESPACE environment variable not set".to_owned());
-    };
+pub async fn surrealdb_client() -> Result<Surreal<surrealdb::engine::remote::ws::Client>, String> {
+    SurrealConfig::from_env()?.connect().await
+}
 
-    let surreal_database = if let Ok(db) = std::env::var("SURREAL_DATABASE") {
-        db
-    } else {
-        return Err("SURREAL_DATABASE environment variable not set".to_owned());
-    };
+/// In-memory database holding `products`, for checking queries against actual results
+#[cfg(test)]
+pub(crate) async fn seeded_client(
+    products: Vec<product::Product>,
+) -> Surreal<surrealdb::engine::local::Db> {
+    use surreal_socket::dbrecord::DBRecord;
 
-    let db = Surreal::new::<Ws>(surreal_address)
+    let client = Surreal::new::<surrealdb::engine::local::Mem>(())
         .await
-        .map_err(|e| "Error connecting to SurrealDB: ".to_owned() + &e.to_string())?;
-
-    db.signin(Root {
-        username: &surreal_username,
-        password: &surreal_password,
-    })
-    .await
-    .map_err(|e| "Error signing in to SurrealDB: ".to_owned() + &e.to_string())?;
-
-    db.use_ns(&surreal_namespace)
-        .use_db(&surreal_database)
+        .expect("in-memory database starts");
+    client
+        .use_ns("test")
+        .use_db("test")
         .await
-        .map_err(|e| "Error using namespace/database: ".to_owned() + &e.to_string())?;
+        .expect("in-memory database selected");
+
+    for product in products {
+        client
+            .query(format!(
+                "CREATE {} CONTENT $product",
+                product::Product::table()
+            ))
+            .bind(("product", product))
+            .await
+            .and_then(|response| response.check())
+            .expect("product seeded");
+    }
 
-    Ok(db)
+    client
 }
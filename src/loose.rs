@@ -0,0 +1,400 @@
+use serde_json::{Map, Value};
+
+use crate::product::{
+    Availability, Cents, Currency, FilamentDiameter, FilamentMaterial, Grams,
+    MAX_PLAUSIBLE_DIAMETER_MM, ProductRequest, Retailer, SpoolType,
+};
+
+/// Keys generic scrapers use for each field, preferred first
+const NAME_KEYS: &[&str] = &["name", "title", "product_name"];
+const BRAND_KEYS: &[&str] = &["brand", "manufacturer", "vendor"];
+const PRICE_KEYS: &[&str] = &["price", "current_price", "sale_price"];
+const PRICE_CENTS_KEYS: &[&str] = &["price_cents"];
+const CURRENCY_KEYS: &[&str] = &["currency", "currency_code"];
+const URL_KEYS: &[&str] = &["url", "link", "product_url"];
+const MATERIAL_KEYS: &[&str] = &["material", "filament_type"];
+const DIAMETER_KEYS: &[&str] = &["diameter", "diameter_mm", "filament_diameter"];
+const WEIGHT_KEYS: &[&str] = &["weight", "weight_g", "net_weight"];
+const RETAILER_KEYS: &[&str] = &["retailer", "store", "seller"];
+const RETAILER_ID_KEYS: &[&str] = &["retailer_product_id", "product_id", "asin", "sku"];
+const COLOR_KEYS: &[&str] = &["color", "colour"];
+const AVAILABILITY_KEYS: &[&str] = &["availability", "stock_status"];
+const SOURCE_KEYS: &[&str] = &["source"];
+const SPOOL_WEIGHT_KEYS: &[&str] = &["spool_weight", "spool_weight_g"];
+const REGION_KEYS: &[&str] = &["region", "country"];
+const PACK_QUANTITY_KEYS: &[&str] = &["pack_quantity", "pack_size", "quantity"];
+const STOCK_QUANTITY_KEYS: &[&str] = &["stock_quantity", "stock"];
+const LIST_PRICE_KEYS: &[&str] = &["list_price", "original_price", "msrp"];
+const SHIPPING_KEYS: &[&str] = &["shipping", "shipping_cost", "shipping_price"];
+const TAGS_KEYS: &[&str] = &["tags", "labels"];
+
+impl ProductRequest {
+    /// Best-effort request from loosely shaped scraper output. Accepts common alternative
+    /// field names, prices in the listing's currency as numbers or strings like "$19.95",
+    /// diameters in millimeters or hundredths, and weights as grams, kilograms or text like
+    /// "1kg".
+    /// Missing material and retailer are left undetermined for `Product::enrich`. Returns
+    /// every field that couldn't be read, not just the first.
+    pub fn from_loose_value(value: &Value) -> Result<ProductRequest, Vec<String>> {
+        let Some(object) = value.as_object() else {
+            return Err(vec!["Expected a JSON object".to_owned()]);
+        };
+
+        let mut fields = LooseFields {
+            object,
+            issues: Vec::new(),
+        };
+
+        let name = fields.required("name", NAME_KEYS, LooseFields::string);
+        let currency = fields
+            .optional("currency", CURRENCY_KEYS, LooseFields::string)
+            .map(|c| Currency::from(c.to_uppercase()))
+            .unwrap_or_default();
+        let amount = |value: &Value| LooseFields::amount(value, &currency);
+        let price = match fields.optional("price_cents", PRICE_CENTS_KEYS, LooseFields::cents) {
+            Some(price) => Some(price),
+            None => fields.required("price", PRICE_KEYS, amount),
+        };
+        let url = fields.required("url", URL_KEYS, LooseFields::string);
+        let diameter = fields.required("diameter", DIAMETER_KEYS, LooseFields::diameter);
+        let weight = fields.required("weight", WEIGHT_KEYS, LooseFields::grams);
+
+        let request = ProductRequest {
+            name: name.unwrap_or_default(),
+            brand: fields.optional("brand", BRAND_KEYS, LooseFields::string),
+            price: price.unwrap_or(Cents(0)),
+            currency: currency.clone(),
+            url: url.unwrap_or_default(),
+            material: fields
+                .optional("material", MATERIAL_KEYS, LooseFields::material)
+                .unwrap_or(FilamentMaterial::Unspecified),
+            diameter: diameter.unwrap_or(FilamentDiameter::D175),
+            weight: weight.unwrap_or(Grams(0)),
+            retailer: fields
+                .optional("retailer", RETAILER_KEYS, LooseFields::string)
+                .map(Retailer::from)
+                .unwrap_or_else(|| Retailer::Other(String::new())),
+            retailer_product_id: fields
+                .optional("retailer_product_id", RETAILER_ID_KEYS, LooseFields::string)
+                .unwrap_or_default(),
+            color: fields
+                .optional("color", COLOR_KEYS, LooseFields::string)
+                .unwrap_or_default(),
+            availability: fields
+                .optional("availability", AVAILABILITY_KEYS, LooseFields::availability)
+                .unwrap_or_default(),
+            source: fields.optional("source", SOURCE_KEYS, LooseFields::string),
+            spool_weight: fields.optional("spool_weight", SPOOL_WEIGHT_KEYS, LooseFields::grams),
+            region: fields.optional("region", REGION_KEYS, LooseFields::string),
+            pack_quantity: fields
+                .optional("pack_quantity", PACK_QUANTITY_KEYS, LooseFields::integer)
+                .and_then(|n| fields.narrow("pack_quantity", n)),
+            nozzle_temp: None,
+            bed_temp: None,
+            stock_quantity: fields
+                .optional("stock_quantity", STOCK_QUANTITY_KEYS, LooseFields::integer)
+                .and_then(|n| fields.narrow("stock_quantity", n)),
+            list_price: fields.optional("list_price", LIST_PRICE_KEYS, amount),
+            tags: fields
+                .optional("tags", TAGS_KEYS, LooseFields::tags)
+                .unwrap_or_default(),
+            shipping: fields.optional("shipping", SHIPPING_KEYS, amount),
+            spool_type: SpoolType::Unknown,
+        };
+
+        if fields.issues.is_empty() {
+            Ok(request)
+        } else {
+            Err(fields.issues)
+        }
+    }
+}
+
+/// Lookup of loosely named fields, collecting coercion issues as it goes
+struct LooseFields<'a> {
+    object: &'a Map<String, Value>,
+    issues: Vec<String>,
+}
+
+impl<'a> LooseFields<'a> {
+    /// First non-null value under any of `keys`
+    fn get(&self, keys: &[&str]) -> Option<&'a Value> {
+        keys.iter()
+            .filter_map(|key| self.object.get(*key))
+            .find(|value| !value.is_null())
+    }
+
+    /// `field` coerced with `coerce`, if present. Values that can't be coerced are recorded
+    /// as issues and read as absent.
+    fn optional<T>(
+        &mut self,
+        field: &str,
+        keys: &[&str],
+        coerce: impl Fn(&Value) -> Result<T, String>,
+    ) -> Option<T> {
+        let value = self.get(keys)?;
+
+        match coerce(value) {
+            Ok(coerced) => Some(coerced),
+            Err(e) => {
+                self.issues.push(format!("{}: {}", field, e));
+                None
+            }
+        }
+    }
+
+    /// `optional`, additionally recording an issue when `field` is absent
+    fn required<T>(
+        &mut self,
+        field: &str,
+        keys: &[&str],
+        coerce: impl Fn(&Value) -> Result<T, String>,
+    ) -> Option<T> {
+        if self.get(keys).is_none() {
+            self.issues.push(format!("{}: missing", field));
+            return None;
+        }
+
+        self.optional(field, keys, coerce)
+    }
+
+    fn narrow<T: TryFrom<u64>>(&mut self, field: &str, n: u64) -> Option<T> {
+        let narrowed = T::try_from(n).ok();
+
+        if narrowed.is_none() {
+            self.issues.push(format!("{}: {} out of range", field, n));
+        }
+
+        narrowed
+    }
+
+    /// Trimmed non-empty string, accepting numbers as their decimal form
+    fn string(value: &Value) -> Result<String, String> {
+        let s = match value {
+            Value::String(s) => s.trim().to_owned(),
+            Value::Number(n) => n.to_string(),
+            other => return Err(format!("expected a string, got {}", other)),
+        };
+
+        if s.is_empty() {
+            return Err("empty".to_owned());
+        }
+
+        Ok(s)
+    }
+
+    fn integer(value: &Value) -> Result<u64, String> {
+        match value {
+            Value::Number(n) => n
+                .as_u64()
+                .ok_or_else(|| format!("expected a non-negative integer, got {}", n)),
+            Value::String(s) => s
+                .trim()
+                .parse()
+                .map_err(|_| format!("expected a non-negative integer, got {:?}", s)),
+            other => Err(format!("expected an integer, got {}", other)),
+        }
+    }
+
+    fn cents(value: &Value) -> Result<Cents, String> {
+        let cents = Self::integer(value)?;
+        u32::try_from(cents)
+            .map(Cents)
+            .map_err(|_| format!("{} cents out of range", cents))
+    }
+
+    /// Amount in `currency`'s major unit as a number or a string such as "$19.95" or "1995"
+    fn amount(value: &Value, currency: &Currency) -> Result<Cents, String> {
+        match value {
+            Value::Number(n) => n
+                .as_f64()
+                .and_then(|amount| Cents::from_amount(amount, currency))
+                .ok_or_else(|| format!("invalid {} amount {}", currency, n)),
+            Value::String(s) => Cents::from_amount_str(s, currency, None),
+            other => Err(format!("expected a price, got {}", other)),
+        }
+    }
+
+    /// Diameter in millimeters ("1.75", "1.75mm", 1.75) or hundredths of a millimeter (175)
+    fn diameter(value: &Value) -> Result<FilamentDiameter, String> {
+        let number = match value {
+            Value::Number(n) => n.as_f64(),
+            Value::String(s) => {
+                if let Some(diameter) = FilamentDiameter::infer_from_text(s) {
+                    return Ok(diameter);
+                }
+
+                s.trim().parse::<f64>().ok()
+            }
+            _ => None,
+        };
+
+        let diameter = number.and_then(|n| {
+            if n <= MAX_PLAUSIBLE_DIAMETER_MM as f64 {
+                FilamentDiameter::from_mm(n as f32)
+            } else if n.fract() == 0.0 && n <= u16::MAX as f64 {
+                FilamentDiameter::try_from(n as u16).ok()
+            } else {
+                None
+            }
+        });
+
+        diameter.ok_or_else(|| format!("invalid diameter {}", value))
+    }
+
+    /// Weight as integer grams, float kilograms or text like "1kg" or "250 g"
+    fn grams(value: &Value) -> Result<Grams, String> {
+        let grams = match value {
+            Value::Number(n) => match n.as_u64() {
+                Some(grams) => Some(grams as f64),
+                None => n.as_f64().map(|kg| (kg * 1000.0).round()),
+            },
+            Value::String(s) => match s.trim().parse::<u64>() {
+                Ok(grams) => Some(grams as f64),
+                Err(_) => Grams::infer_from_text(s).map(|g| g.0 as f64),
+            },
+            _ => None,
+        };
+
+        grams
+            .filter(|g| (0.0..=u16::MAX as f64).contains(g))
+            .map(|g| Grams(g as u16))
+            .ok_or_else(|| format!("invalid weight {}", value))
+    }
+
+    /// Material by name, falling back to spotting one in free text like "pla filament"
+    fn material(value: &Value) -> Result<FilamentMaterial, String> {
+        let name = Self::string(value)?;
+
+        match FilamentMaterial::from(name.clone()) {
+            FilamentMaterial::Other(_) => {
+                Ok(FilamentMaterial::infer_from_text(&name)
+                    .unwrap_or(FilamentMaterial::Other(name)))
+            }
+            material => Ok(material),
+        }
+    }
+
+    /// Availability by variant name or a common spelling like "in stock" or "out_of_stock"
+    fn availability(value: &Value) -> Result<Availability, String> {
+        let s = Self::string(value)?;
+        let key: String = s
+            .to_lowercase()
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .collect();
+
+        match key.as_str() {
+            "instock" | "available" => Ok(Availability::InStock),
+            "lowstock" | "limited" => Ok(Availability::LowStock),
+            "outofstock" | "soldout" | "unavailable" => Ok(Availability::OutOfStock),
+            "unknown" => Ok(Availability::Unknown),
+            _ => Err(format!("unknown availability {:?}", s)),
+        }
+    }
+
+    /// Array of strings, or one comma-separated string
+    fn tags(value: &Value) -> Result<Vec<String>, String> {
+        let tags: Vec<String> = match value {
+            Value::Array(items) => items.iter().filter_map(|t| Self::string(t).ok()).collect(),
+            Value::String(s) => s.split(',').map(|t| t.trim().to_owned()).collect(),
+            other => return Err(format!("expected tags, got {}", other)),
+        };
+
+        Ok(tags
+            .into_iter()
+            .filter(|t| !t.is_empty())
+            .map(|t| t.to_lowercase())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(price: Value, currency: &str) -> Value {
+        serde_json::json!({
+            "title": "Hatchbox PLA 1.75mm 1kg",
+            "price": price,
+            "currency": currency,
+            "link": "https://www.amazon.com/dp/B00J0GMMP6",
+            "diameter": "1.75mm",
+            "weight": "1kg",
+        })
+    }
+
+    #[test]
+    fn prices_read_in_listing_currency() {
+        let usd = ProductRequest::from_loose_value(&listing("$19.95".into(), "usd")).unwrap();
+        assert_eq!(usd.price, Cents(1995));
+        assert_eq!(usd.currency, Currency::USD);
+
+        let jpy = ProductRequest::from_loose_value(&listing("1995".into(), "JPY")).unwrap();
+        assert_eq!(jpy.price, Cents(1995));
+        assert_eq!(jpy.currency, Currency::JPY);
+
+        let jpy = ProductRequest::from_loose_value(&listing(1995.into(), "JPY")).unwrap();
+        assert_eq!(jpy.price, Cents(1995));
+    }
+
+    #[test]
+    fn clean_value_reads_every_field() {
+        let value = serde_json::json!({
+            "name": "eSUN PLA+ 1.75mm 1kg Black",
+            "brand": "eSUN",
+            "price": 19.99,
+            "currency": "USD",
+            "url": "https://www.amazon.com/dp/B01EKEMDA6",
+            "material": "PLA+",
+            "diameter": 175,
+            "weight": 1000,
+            "color": "Black",
+            "stock_quantity": "12",
+        });
+
+        let request = ProductRequest::from_loose_value(&value).unwrap();
+
+        assert_eq!(request.name, "eSUN PLA+ 1.75mm 1kg Black");
+        assert_eq!(request.brand.as_deref(), Some("eSUN"));
+        assert_eq!(request.price, Cents(1999));
+        assert_eq!(request.material, FilamentMaterial::PLAPlus);
+        assert_eq!(request.diameter, FilamentDiameter::D175);
+        assert_eq!(request.weight, Grams(1000));
+        assert_eq!(request.stock_quantity, Some(12));
+    }
+
+    #[test]
+    fn messy_value_coerces_variants_and_reports_every_issue() {
+        let coerced = ProductRequest::from_loose_value(&serde_json::json!({
+            "title": "  Sunlu PETG  ",
+            "sale_price": "$1,234.50",
+            "link": "https://example.com/petg",
+            "filament_diameter": 2.85,
+            "net_weight": 0.5,
+        }))
+        .unwrap();
+        assert_eq!(coerced.name, "Sunlu PETG");
+        assert_eq!(coerced.price, Cents(123450));
+        assert_eq!(coerced.diameter, FilamentDiameter::D285);
+        assert_eq!(coerced.weight, Grams(500));
+
+        let issues = ProductRequest::from_loose_value(&serde_json::json!({
+            "title": "Mystery filament",
+            "price": "cheap",
+            "diameter": "huge",
+            "stock": -3,
+        }))
+        .unwrap_err();
+
+        for field in ["price", "url", "diameter", "weight", "stock_quantity"] {
+            assert!(
+                issues
+                    .iter()
+                    .any(|issue| issue.starts_with(&format!("{field}:"))),
+                "{field} in {issues:?}"
+            );
+        }
+        assert!(ProductRequest::from_loose_value(&Value::Null).is_err());
+    }
+}
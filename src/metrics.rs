@@ -0,0 +1,123 @@
+use std::fmt::Write;
+
+use serde::Deserialize;
+use surreal_socket::{dbrecord::DBRecord, error::SurrealSocketError};
+
+use crate::{
+    product::{FilamentMaterial, Product},
+    surrealdb_client,
+};
+
+/// Aggregate catalog gauges in the Prometheus text exposition format
+pub async fn render_metrics() -> Result<String, SurrealSocketError> {
+    #[derive(Deserialize)]
+    struct MaterialCount {
+        material: FilamentMaterial,
+        count: u64,
+    }
+
+    #[derive(Deserialize)]
+    struct Totals {
+        count: u64,
+        average_price_per_kg: Option<f64>,
+    }
+
+    let client = surrealdb_client().await?;
+
+    let query = format!(
+        "SELECT count() AS count, math::mean(price_per_kg) AS average_price_per_kg FROM {table} GROUP ALL;
+         SELECT material, count() AS count FROM {table} GROUP BY material;",
+        table = Product::table()
+    );
+
+    let mut response = client.query(query).await?;
+    let totals: Option<Totals> = response.take(0)?;
+    let mut materials: Vec<MaterialCount> = response.take(1)?;
+    materials.sort_by_key(|m| m.material.to_string());
+
+    let (count, average) = totals.map_or((0, 0.0), |t| {
+        (t.count, t.average_price_per_kg.unwrap_or_default())
+    });
+    let materials: Vec<(FilamentMaterial, u64)> = materials
+        .into_iter()
+        .map(|m| (m.material, m.count))
+        .collect();
+
+    Ok(format_metrics(count, average, &materials))
+}
+
+/// The gauges of `render_metrics`, with `materials` in the order given
+fn format_metrics(count: u64, average: f64, materials: &[(FilamentMaterial, u64)]) -> String {
+    let mut out = String::new();
+
+    gauge_header(
+        &mut out,
+        "filamentseek_products_total",
+        "Number of listed products",
+    );
+    let _ = writeln!(out, "filamentseek_products_total {}", count);
+
+    gauge_header(
+        &mut out,
+        "filamentseek_products_by_material",
+        "Number of listed products per material",
+    );
+    for (material, count) in materials {
+        let _ = writeln!(
+            out,
+            "filamentseek_products_by_material{{material=\"{}\"}} {}",
+            escape_label(&material.to_string()),
+            count
+        );
+    }
+
+    gauge_header(
+        &mut out,
+        "filamentseek_average_price_per_kg_cents",
+        "Average price per kg across all products, in cents",
+    );
+    let _ = writeln!(out, "filamentseek_average_price_per_kg_cents {}", average);
+
+    out
+}
+
+fn gauge_header(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_names_each_metric() {
+        let out = format_metrics(
+            3,
+            2250.5,
+            &[
+                (FilamentMaterial::PETG, 1),
+                (FilamentMaterial::Other("PLA \"Silk\"".to_owned()), 2),
+            ],
+        );
+
+        for name in [
+            "filamentseek_products_total",
+            "filamentseek_products_by_material",
+            "filamentseek_average_price_per_kg_cents",
+        ] {
+            assert!(out.contains(&format!("# TYPE {} gauge\n", name)));
+        }
+        assert!(out.contains("filamentseek_products_total 3\n"));
+        assert!(out.contains("filamentseek_products_by_material{material=\"PETG\"} 1\n"));
+        assert!(out.contains(r#"filamentseek_products_by_material{material="PLA \"Silk\""} 2"#));
+        assert!(out.contains("filamentseek_average_price_per_kg_cents 2250.5\n"));
+    }
+}
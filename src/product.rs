@@ -1,6 +1,10 @@
-use std::str::FromStr;
+use std::{
+    collections::{BTreeMap, HashMap, hash_map::Entry},
+    str::FromStr,
+};
 
 use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use surreal_socket::{
     dbrecord::{DBRecord, SsUuid},
@@ -8,14 +12,26 @@ use surreal_socket::{
 };
 use utoipa::ToSchema;
 
-use crate::surrealdb_client;
+use crate::{
+    clock::{Clock, FixedClock, SystemClock},
+    event::{PriceHistoryConfig, ProductEvent, ProductEventRecord},
+    history::AvailabilityHistory,
+    ingest::{IngestConfig, InsertError},
+    surrealdb_client,
+};
 
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Product {
     pub uuid: SsUuid<Product>,
     pub name: String,
+    pub brand: Option<String>,
     pub price: Cents,
-    pub price_per_kg: Cents,
+    #[serde(default)]
+    pub currency: Currency,
+    /// Missing from records that predate it, which load as zero until `post_update_hook`
+    /// recomputes it on the next save
+    #[serde(default)]
+    pub price_per_kg: CentsPerKg,
     pub url: String,
     pub material: FilamentMaterial,
     pub diameter: FilamentDiameter,
@@ -23,251 +39,3651 @@ pub struct Product {
     pub retailer: Retailer,
     pub retailer_product_id: String,
     pub color: String,
+    #[serde(default)]
+    pub flagged_updates: Vec<FlaggedUpdate>,
+    /// Last time a scrape reported this product
+    pub last_seen: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub availability: Availability,
+    /// Scraper or job that last wrote this product
+    pub source: Option<String>,
+    /// Source that last set each patched field, keyed by field name
+    #[serde(default)]
+    pub provenance: HashMap<String, String>,
+    /// Stored as milliseconds since the epoch so that queries compare and sort it correctly
+    #[serde(default, with = "chrono::serde::ts_milliseconds")]
+    pub created_at: DateTime<Utc>,
+    /// Set by the update hook on every write. Stored like `created_at`.
+    #[serde(default, with = "chrono::serde::ts_milliseconds")]
+    pub updated_at: DateTime<Utc>,
+    /// Weight of the empty spool, when the listing states it
+    pub spool_weight: Option<Grams>,
+    /// ISO 3166-1 alpha-2 country the listing is sold in, e.g. "GB"
+    pub region: Option<String>,
+    /// Number of spools sold together, when more than one
+    pub pack_quantity: Option<u16>,
+    /// Recommended nozzle temperature, when the listing states it
+    pub nozzle_temp: Option<TempRange>,
+    /// Recommended bed temperature, when the listing states it
+    pub bed_temp: Option<TempRange>,
+    /// Other URLs the product is reachable at (mobile, shortened), canonicalized, never
+    /// including `url`. See `Product::add_url`.
+    #[serde(default)]
+    pub urls: Vec<String>,
+    /// How sure we are of `material`: 1.0 when given explicitly, lower when inferred from the
+    /// name (see `FilamentMaterial::infer_with_confidence`), 0.0 while undetermined. `None` on
+    /// records predating the field; read it through `Product::material_confidence`.
+    #[serde(default)]
+    pub material_confidence: Option<f32>,
+    /// Units in stock, for retailers that expose it
+    pub stock_quantity: Option<u32>,
+    /// Regular price when `price` is discounted from it
+    pub list_price: Option<Cents>,
+    /// Lowercase labels such as "budget" or "pla-family", set manually or by
+    /// `Product::merge_derived_tags`
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Shipping cost in `currency`, when the retailer states it; zero for free shipping
+    pub shipping: Option<Cents>,
+    #[serde(default)]
+    pub spool_type: SpoolType,
 }
 
-#[async_trait]
-impl DBRecord for Product {
-    fn uuid(&self) -> SsUuid<Self> {
-        self.uuid.to_owned()
+impl Product {
+    pub fn calculate_price_per_kg(&self) -> CentsPerKg {
+        CentsPerKg::from((self.price, self.weight))
     }
 
-    const TABLE_NAME: &'static str = "products";
+    /// The recorded `material_confidence`, or for records predating it 0.0 while the material
+    /// is undetermined and 1.0 otherwise
+    pub fn material_confidence(&self) -> f32 {
+        self.material_confidence
+            .unwrap_or(if self.material.is_undetermined() {
+                0.0
+            } else {
+                1.0
+            })
+    }
 
-    async fn post_update_hook(&self) -> Result<(), SurrealSocketError> {
-        let price_per_kg = ((self.price.0 as f32 / self.weight.0 as f32) * 1000.0).round() as u32;
+    /// Whether this is a sample or trial spool: under `SAMPLE_MAX_WEIGHT`, or with one of
+    /// `SAMPLE_NAME_WORDS` as a whole word of its name (so "Industrial" doesn't count)
+    pub fn is_sample(&self) -> bool {
+        (self.weight.0 > 0 && self.weight < SAMPLE_MAX_WEIGHT)
+            || self
+                .name
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .any(|word| {
+                    SAMPLE_NAME_WORDS
+                        .iter()
+                        .any(|sample| word.eq_ignore_ascii_case(sample))
+                })
+    }
+
+    /// Freshness of `updated_at` as of `now`. An `updated_at` in the future counts as new.
+    pub fn freshness(&self, now: DateTime<Utc>) -> Freshness {
+        let age = now - self.updated_at;
+
+        if age < Duration::days(1) {
+            Freshness::New
+        } else if age < Duration::days(7) {
+            Freshness::Recent
+        } else if age < Duration::days(30) {
+            Freshness::Aging
+        } else {
+            Freshness::Stale
+        }
+    }
+
+    /// Records another URL for this product in canonical form, unless it is blank or
+    /// canonicalizes to `url` or one already in `urls`. Returns whether it was added.
+    pub fn add_url(&mut self, url: String) -> bool {
+        let canonical = canonicalize_url(&url);
+
+        if url.trim().is_empty()
+            || canonical == canonicalize_url(&self.url)
+            || self.urls.contains(&canonical)
+        {
+            return false;
+        }
+
+        self.urls.push(canonical);
+        true
+    }
+
+    /// Rewrites `urls` as `add_url` would have built them: canonical, without blanks,
+    /// duplicates or `url` itself, in first-seen order. Returns whether anything changed.
+    pub fn deduplicate_urls(&mut self) -> bool {
+        let original = std::mem::take(&mut self.urls);
+
+        for url in original.iter().cloned() {
+            self.add_url(url);
+        }
+
+        self.urls != original
+    }
+
+    /// Runs `deduplicate_urls` over every stored product with other URLs and saves those it
+    /// changed, e.g. to clean up URLs recorded before they were canonicalized. Returns how
+    /// many were changed.
+    pub async fn deduplicate_all_urls() -> Result<u64, SurrealSocketError> {
         let client = surrealdb_client().await?;
 
         let query = format!(
-            r#"
-            UPDATE {} SET price_per_kg = {} WHERE {} = {};
-            "#,
-            Self::table(),
-            price_per_kg,
-            Self::UUID_FIELD,
-            serde_json::to_string(&self.uuid())?
+            "SELECT * FROM {} WHERE array::len(urls ?? []) > 0",
+            Self::table()
         );
 
-        client.query(&query).await?;
-        Ok(())
+        let products: Vec<Product> = client.query(query).await?.take(0)?;
+        let mut changed = 0;
+
+        for mut product in products {
+            if product.deduplicate_urls() {
+                product.save().await?;
+                changed += 1;
+            }
+        }
+
+        Ok(changed)
     }
-}
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
-pub struct Cents(pub u32);
+    /// Copy of this product under a newly generated uuid, e.g. to use it as a template
+    pub fn clone_with_fresh_uuid(&self) -> Product {
+        Product {
+            uuid: SsUuid::new(),
+            ..self.clone()
+        }
+    }
 
-#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
-#[serde(try_from = "String", into = "String")]
-pub enum FilamentMaterial {
-    PLA,
-    PLAPlus,
-    ABS,
-    PETG,
-    TPU,
-    Nylon,
-    PC,
-    ASA,
-    PCTG,
-    Unspecified,
-    Other(String),
-}
+    /// Copy of this product with `price` reduced by `pct` percent (capped at 100) and the
+    /// price per kg recomputed, for previewing a site-wide sale without saving anything.
+    /// `list_price` is left as it was.
+    pub fn with_global_discount(&self, pct: u8) -> Product {
+        let pct = u64::from(pct.min(100));
+        let price = Cents((u64::from(self.price.0) * (100 - pct) / 100) as u32);
 
-impl FromStr for FilamentMaterial {
-    type Err = ();
+        let mut discounted = Product {
+            price,
+            ..self.clone()
+        };
+        discounted.price_per_kg = discounted.calculate_price_per_kg();
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(match s {
-            "PLA" => Self::PLA,
-            "PLAPlus" => Self::PLAPlus,
-            "ABS" => Self::ABS,
-            "PETG" => Self::PETG,
-            "TPU" => Self::TPU,
-            "Nylon" => Self::Nylon,
-            "PC" => Self::PC,
-            "ASA" => Self::ASA,
-            "PCTG" => Self::PCTG,
-            "Unspecified" => Self::Unspecified,
-            other => Self::Other(other.to_string()),
-        })
+        discounted
     }
-}
 
-impl std::fmt::Display for FilamentMaterial {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::PLA => write!(f, "PLA"),
-            Self::PLAPlus => write!(f, "PLAPlus"),
-            Self::ABS => write!(f, "ABS"),
-            Self::PETG => write!(f, "PETG"),
-            Self::TPU => write!(f, "TPU"),
-            Self::Nylon => write!(f, "Nylon"),
-            Self::PC => write!(f, "PC"),
-            Self::ASA => write!(f, "ASA"),
-            Self::PCTG => write!(f, "PCTG"),
-            Self::Unspecified => write!(f, "Unspecified"),
-            Self::Other(s) => write!(f, "{}", s),
+    /// Copy of this product with a different retailer
+    pub fn with_retailer(&self, retailer: Retailer) -> Product {
+        Product {
+            retailer,
+            ..self.clone()
         }
     }
-}
 
-impl From<String> for FilamentMaterial {
-    fn from(s: String) -> Self {
-        FilamentMaterial::from_str(&s).unwrap()
+    /// Diameter in millimeters for templates, e.g. "1.75"
+    pub fn diameter_mm_string(&self) -> String {
+        self.diameter.mm_string()
     }
-}
 
-impl From<FilamentMaterial> for String {
-    fn from(m: FilamentMaterial) -> String {
-        m.to_string()
+    /// Approximate filament length in meters, from weight, diameter and material density
+    pub fn estimated_length_m(&self) -> Option<f32> {
+        let density = self.material.density_g_per_cm3()?;
+        let radius_cm = self.diameter.mm() / 20.0;
+        let area_cm2 = std::f32::consts::PI * radius_cm * radius_cm;
+
+        if area_cm2 == 0.0 {
+            return None;
+        }
+
+        Some(self.weight.0 as f32 / (density * area_cm2) / 100.0)
     }
-}
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
-pub struct Celsius(pub u16);
+    /// Rough kg CO2e of producing the filament (not the spool or shipping), from
+    /// `FilamentMaterial::co2_kg_per_kg` and the net weight
+    pub fn estimated_co2(&self) -> Option<f32> {
+        Some(self.material.co2_kg_per_kg()? * self.weight.0 as f32 / 1000.0)
+    }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
-pub struct Grams(pub u16);
+    /// Gross shipping weight: filament, spool (`DEFAULT_SPOOL_WEIGHT` when unknown) and
+    /// `packaging_grams` of packaging
+    pub fn shipping_weight(&self, packaging_grams: u16) -> Grams {
+        let spool = self.spool_weight.unwrap_or(DEFAULT_SPOOL_WEIGHT);
 
-/// Filament diameter in hundredths of a millimeter (e.g. 175 = 1.75 mm)
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
-#[serde(into = "u16", try_from = "u16")]
-pub enum FilamentDiameter {
-    D175,
-    D285,
-    Other(u16),
-}
+        Grams(
+            self.weight
+                .0
+                .saturating_add(spool.0)
+                .saturating_add(packaging_grams),
+        )
+    }
 
-impl From<FilamentDiameter> for u16 {
-    fn from(d: FilamentDiameter) -> Self {
-        match d {
-            FilamentDiameter::D175 => 175,
-            FilamentDiameter::D285 => 285,
-            FilamentDiameter::Other(x) => x,
-        }
+    /// Price plus shipping, or just the price when shipping is unknown
+    pub fn effective_total(&self) -> Cents {
+        Cents(
+            self.price
+                .0
+                .saturating_add(self.shipping.map_or(0, |s| s.0)),
+        )
     }
-}
 
-impl TryFrom<u16> for FilamentDiameter {
-    type Error = &'static str;
-    fn try_from(v: u16) -> Result<Self, Self::Error> {
-        Ok(match v {
-            175 => FilamentDiameter::D175,
-            285 => FilamentDiameter::D285,
-            x => FilamentDiameter::Other(x),
-        })
+    /// Price per kg of `effective_total`, over the net filament weight
+    pub fn price_per_kg_with_shipping(&self) -> CentsPerKg {
+        CentsPerKg::from((self.effective_total(), self.weight))
     }
-}
 
-impl FilamentDiameter {
-    pub fn mm(&self) -> f32 {
-        match self {
-            FilamentDiameter::D175 => 1.75,
-            FilamentDiameter::D285 => 2.85,
-            FilamentDiameter::Other(hundredths) => *hundredths as f32 / 100.0,
+    /// Number of whole prints of `grams_per_print` a spool yields
+    pub fn estimated_prints(&self, grams_per_print: u16) -> Option<u32> {
+        if self.weight.0 == 0 || grams_per_print == 0 {
+            return None;
         }
+
+        Some((self.weight.0 / grams_per_print) as u32)
     }
-}
 
-/// Product Request
-#[derive(Deserialize, ToSchema)]
-pub struct ProductRequest {
-    pub name: String,
-    pub price: Cents,
-    pub url: String,
-    pub material: FilamentMaterial,
-    pub diameter: FilamentDiameter,
-    pub weight: Grams,
-    pub retailer: Retailer,
-    pub retailer_product_id: String,
-    pub color: String,
-}
+    /// Applies the fields set in `patch`, recording `source` as their provenance
+    pub fn apply_patch(&mut self, patch: ProductPatch, source: &str) {
+        let provenance = &mut self.provenance;
+        let mut set = |name: &str| {
+            provenance.insert(name.to_owned(), source.to_owned());
+        };
 
-impl From<ProductRequest> for Product {
-    fn from(request: ProductRequest) -> Self {
-        Self {
-            uuid: SsUuid::new(),
-            name: request.name,
-            price: request.price,
-            price_per_kg: Cents(0), // Calculated in update hook
-            url: request.url,
-            material: request.material,
-            diameter: request.diameter,
-            weight: request.weight,
-            retailer: request.retailer,
-            retailer_product_id: request.retailer_product_id,
-            color: request.color,
+        if let Some(name) = patch.name {
+            self.name = name;
+            set("name");
         }
+        if let Some(brand) = patch.brand {
+            self.brand = Some(brand);
+            set("brand");
+        }
+        if let Some(price) = patch.price {
+            self.price = price;
+            set("price");
+        }
+        if let Some(url) = patch.url {
+            self.url = url;
+            set("url");
+        }
+        if let Some(material) = patch.material {
+            self.material_confidence = Some(if material.is_undetermined() { 0.0 } else { 1.0 });
+            self.material = material;
+            set("material");
+        }
+        if let Some(diameter) = patch.diameter {
+            self.diameter = diameter;
+            set("diameter");
+        }
+        if let Some(weight) = patch.weight {
+            self.weight = weight;
+            set("weight");
+        }
+        if let Some(color) = patch.color {
+            self.color = color;
+            set("color");
+        }
+        if let Some(availability) = patch.availability {
+            self.availability = availability;
+            set("availability");
+        }
+
+        self.price_per_kg = self.calculate_price_per_kg();
+        self.source = Some(source.to_owned());
     }
-}
 
-/// Product Response
-#[derive(Serialize, ToSchema)]
-pub struct ProductResponse {
-    uuid: String,
-    name: String,
-    price: Cents,
-    price_per_kg: Cents,
-    url: String,
-    material: FilamentMaterial,
-    diameter: FilamentDiameter,
-    weight: Grams,
-    retailer: Retailer,
-    retailer_product_id: String,
-    color: String,
-}
+    /// Merges a partial record of the same product into this one. Fields empty here (blank
+    /// strings, `None`, zero price or weight, undetermined material, unknown availability)
+    /// are filled from `other`. Fields set in both take `other`'s value only when `other` was
+    /// updated more recently.
+    pub fn coalesce_from(&mut self, other: &Product) {
+        let other_newer = other.updated_at > self.updated_at;
 
-impl From<Product> for ProductResponse {
-    fn from(product: Product) -> Self {
-        Self {
-            uuid: product.uuid.to_uuid_string(),
-            name: product.name,
-            price: product.price,
-            price_per_kg: product.price_per_kg,
-            url: product.url,
-            material: product.material,
-            diameter: product.diameter,
-            weight: product.weight,
-            retailer: product.retailer,
-            retailer_product_id: product.retailer_product_id,
-            color: product.color,
+        fn merge<T: Clone>(mine: &mut T, theirs: &T, empty: impl Fn(&T) -> bool, newer: bool) {
+            if !empty(theirs) && (newer || empty(mine)) {
+                *mine = theirs.clone();
+            }
         }
-    }
-}
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
-#[serde(try_from = "String", into = "String")]
-pub enum Retailer {
-    Amazon,
-    Other(String),
-}
+        let blank = |s: &String| s.trim().is_empty();
 
-impl FromStr for Retailer {
-    type Err = ();
+        merge(&mut self.name, &other.name, blank, other_newer);
+        merge(&mut self.url, &other.url, blank, other_newer);
+        merge(&mut self.color, &other.color, blank, other_newer);
+        merge(
+            &mut self.retailer_product_id,
+            &other.retailer_product_id,
+            blank,
+            other_newer,
+        );
+        merge(&mut self.brand, &other.brand, Option::is_none, other_newer);
+        merge(&mut self.price, &other.price, |p| p.0 == 0, other_newer);
+        merge(&mut self.weight, &other.weight, |w| w.0 == 0, other_newer);
+        let material = self.material.clone();
+        merge(
+            &mut self.material,
+            &other.material,
+            FilamentMaterial::is_undetermined,
+            other_newer,
+        );
+        if self.material != material {
+            self.material_confidence = other.material_confidence;
+        }
+        merge(
+            &mut self.availability,
+            &other.availability,
+            |a| *a == Availability::Unknown,
+            other_newer,
+        );
+        merge(
+            &mut self.source,
+            &other.source,
+            Option::is_none,
+            other_newer,
+        );
+        merge(
+            &mut self.spool_weight,
+            &other.spool_weight,
+            Option::is_none,
+            other_newer,
+        );
+        merge(
+            &mut self.region,
+            &other.region,
+            Option::is_none,
+            other_newer,
+        );
+        merge(
+            &mut self.pack_quantity,
+            &other.pack_quantity,
+            Option::is_none,
+            other_newer,
+        );
+        merge(
+            &mut self.nozzle_temp,
+            &other.nozzle_temp,
+            Option::is_none,
+            other_newer,
+        );
+        merge(
+            &mut self.bed_temp,
+            &other.bed_temp,
+            Option::is_none,
+            other_newer,
+        );
+        merge(
+            &mut self.stock_quantity,
+            &other.stock_quantity,
+            Option::is_none,
+            other_newer,
+        );
+        merge(
+            &mut self.list_price,
+            &other.list_price,
+            Option::is_none,
+            other_newer,
+        );
+        merge(
+            &mut self.shipping,
+            &other.shipping,
+            Option::is_none,
+            other_newer,
+        );
+        merge(
+            &mut self.spool_type,
+            &other.spool_type,
+            |t| *t == SpoolType::Unknown,
+            other_newer,
+        );
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(match s {
-            "Amazon" => Self::Amazon,
-            other => Self::Other(other.to_string()),
-        })
+        self.merge_tags(&other.tags);
+
+        for url in std::iter::once(&other.url).chain(&other.urls) {
+            self.add_url(url.clone());
+        }
+
+        self.last_seen = self.last_seen.max(other.last_seen);
+        self.price_per_kg = self.calculate_price_per_kg();
     }
-}
 
-impl std::fmt::Display for Retailer {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Amazon => write!(f, "Amazon"),
-            Self::Other(s) => write!(f, "{}", s),
+    /// Applies a scraped price, unless it differs from the current price by more than the
+    /// guard allows, in which case it is recorded in `flagged_updates` for review instead.
+    pub fn apply_price_update(
+        &mut self,
+        new_price: Cents,
+        guard: &PriceChangeGuard,
+    ) -> PriceUpdate {
+        if new_price == self.price {
+            return PriceUpdate::Unchanged;
         }
+
+        if self.price.0 != 0 {
+            let change_percent = PriceChangeGuard::change_percent(self.price, new_price);
+
+            if change_percent > guard.max_change_percent as f32 {
+                self.flagged_updates.push(FlaggedUpdate {
+                    previous_price: self.price,
+                    proposed_price: new_price,
+                    change_percent,
+                });
+                return PriceUpdate::Flagged;
+            }
+        }
+
+        self.price = new_price;
+        self.price_per_kg = self.calculate_price_per_kg();
+        PriceUpdate::Applied
     }
-}
 
-impl From<String> for Retailer {
-    fn from(s: String) -> Self {
-        Retailer::from_str(&s).unwrap()
+    /// Where this product's price per kg sits among other products of the same material,
+    /// from 0.0 (cheapest) to 1.0 (most expensive). Returns 0.5 when there is nothing to compare against.
+    pub async fn price_percentile(&self) -> Result<f32, SurrealSocketError> {
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT VALUE price_per_kg FROM {} \
+             WHERE material = $material AND currency = $currency AND weight > 0 AND {} != $uuid",
+            Self::table(),
+            Self::UUID_FIELD
+        );
+
+        let mut response = client
+            .query(query)
+            .bind(("material", self.material.to_string()))
+            .bind(("currency", self.currency.to_string()))
+            .bind(("uuid", self.uuid()))
+            .await?;
+
+        let others: Vec<CentsPerKg> = response.take(0)?;
+        Ok(percentile_rank(&others, self.price_per_kg))
     }
-}
 
-impl From<Retailer> for String {
-    fn from(p: Retailer) -> String {
-        p.to_string()
+    /// Fraction by which this product's price per kg undercuts the average for its material
+    /// (negative when above average). `None` when there is no average to compare against.
+    pub async fn savings_vs_material_average(&self) -> Result<Option<f32>, SurrealSocketError> {
+        #[derive(Deserialize)]
+        struct Average {
+            average: Option<f64>,
+        }
+
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT math::mean(price_per_kg) AS average FROM {} \
+             WHERE material = $material AND currency = $currency AND weight > 0 GROUP ALL",
+            Self::table()
+        );
+
+        let mut response = client
+            .query(query)
+            .bind(("material", self.material.to_string()))
+            .bind(("currency", self.currency.to_string()))
+            .await?;
+
+        let average: Option<Average> = response.take(0)?;
+
+        Ok(savings_vs_average(
+            self.price_per_kg,
+            average.and_then(|a| a.average),
+        ))
+    }
+
+    /// Distinct normalized colors listed for `material`, with the number of products in
+    /// each, most common first
+    pub async fn colors_for_material(
+        material: &FilamentMaterial,
+    ) -> Result<Vec<(String, u64)>, SurrealSocketError> {
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT VALUE color FROM {} WHERE material = $material",
+            Self::table()
+        );
+
+        let mut response = client
+            .query(query)
+            .bind(("material", material.to_string()))
+            .await?;
+
+        let colors: Vec<String> = response.take(0)?;
+        Ok(color_counts(colors))
+    }
+
+    /// Cheapest product per material from one brand (matched case-insensitively)
+    pub async fn cheapest_by_material_for_brand(
+        brand: &str,
+    ) -> Result<HashMap<FilamentMaterial, Product>, SurrealSocketError> {
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT * FROM {} WHERE string::lowercase(brand ?? '') = string::lowercase($brand) ORDER BY price_per_kg ASC",
+            Self::table()
+        );
+
+        let mut response = client
+            .query(query)
+            .bind(("brand", brand.trim().to_owned()))
+            .await?;
+
+        let products: Vec<Product> = response.take(0)?;
+        Ok(cheapest_by(products, |p| Some(p.material.clone())))
+    }
+
+    /// Stored product with the same retailer and retailer product id (compared in its
+    /// normalized form), if any
+    pub async fn find_by_retailer_id(
+        retailer: &Retailer,
+        retailer_product_id: &str,
+    ) -> Result<Option<Product>, SurrealSocketError> {
+        let client = surrealdb_client().await?;
+        let (query, bindings) = Self::find_by_retailer_id_statement(retailer, retailer_product_id);
+
+        let mut request = client.query(query);
+        for binding in bindings {
+            request = request.bind(binding);
+        }
+
+        Ok(request.await?.take(0)?)
+    }
+
+    /// The lookup `find_by_retailer_id` runs, with the natural key it binds
+    fn find_by_retailer_id_statement(
+        retailer: &Retailer,
+        retailer_product_id: &str,
+    ) -> (String, [(&'static str, String); 2]) {
+        let query = format!(
+            "SELECT * FROM {} WHERE retailer = $retailer AND retailer_product_id = $retailer_product_id LIMIT 1",
+            Self::table()
+        );
+
+        (
+            query,
+            [
+                ("retailer", retailer.to_string()),
+                (
+                    "retailer_product_id",
+                    retailer.normalize_product_id(retailer_product_id),
+                ),
+            ],
+        )
+    }
+
+    /// Writes this product as a new record, runs the update hook and returns what was stored.
+    /// Fails with `IngestError::RetailerNotAllowed`, before anything is written, if
+    /// `IngestConfig::from_env` restricts retailers and this one isn't allowed.
+    pub async fn insert(&self) -> Result<Product, InsertError> {
+        self.insert_with(&IngestConfig::from_env(), &SystemClock)
+            .await
+    }
+
+    /// `insert` under `config`, running the update hook as of `clock`
+    pub async fn insert_with(
+        &self,
+        config: &IngestConfig,
+        clock: &dyn Clock,
+    ) -> Result<Product, InsertError> {
+        config.check(self)?;
+        Ok(self.create_with(clock).await?)
+    }
+
+    /// Writes this product as a new record and runs the update hook, without any
+    /// `IngestConfig` check
+    async fn create_with(&self, clock: &dyn Clock) -> Result<Product, SurrealSocketError> {
+        let mut product = self.clone();
+        product.price_per_kg = product.calculate_price_per_kg();
+
+        let client = surrealdb_client().await?;
+        client
+            .query(Self::insert_query())
+            .bind((INSERT_BINDING, product.clone()))
+            .await?
+            .check()?;
+
+        product.post_update_hook_with(clock).await?;
+        Ok(product)
+    }
+
+    /// The parameterized statement `insert` runs to create this product and its one binding,
+    /// the whole product, for inspecting writes without executing them
+    pub fn to_insert_statement(&self) -> (String, Vec<(String, serde_json::Value)>) {
+        let product = serde_json::to_value(self).unwrap_or_default();
+        (
+            Self::insert_query(),
+            vec![(INSERT_BINDING.to_owned(), product)],
+        )
+    }
+
+    fn insert_query() -> String {
+        format!("CREATE {} CONTENT ${}", Self::table(), INSERT_BINDING)
+    }
+
+    /// `insert` without the update hook round-trip, for bulk imports. `price_per_kg` and
+    /// `updated_at` are set inline instead; no availability history entry is recorded.
+    pub async fn insert_without_hook(&self) -> Result<Product, InsertError> {
+        let mut inserted = Self::insert_many_without_hook(std::slice::from_ref(self)).await?;
+        Ok(inserted.remove(0))
+    }
+
+    /// Writes all `products` as new records in a single statement, as `insert_without_hook`
+    /// does for one, and returns what was stored. Nothing is written if any product's
+    /// retailer isn't allowed, as for `insert`.
+    pub async fn insert_many_without_hook(
+        products: &[Product],
+    ) -> Result<Vec<Product>, InsertError> {
+        Self::insert_many_without_hook_with(products, &IngestConfig::from_env(), &SystemClock).await
+    }
+
+    /// `insert_many_without_hook` under `config`, setting `updated_at` from `clock`
+    pub async fn insert_many_without_hook_with(
+        products: &[Product],
+        config: &IngestConfig,
+        clock: &dyn Clock,
+    ) -> Result<Vec<Product>, InsertError> {
+        for product in products {
+            config.check(product)?;
+        }
+
+        Ok(Self::create_many_without_hook(products, clock.now()).await?)
+    }
+
+    /// Writes `products` with `with_hook_fields` as of `now`, without any `IngestConfig`
+    /// check
+    async fn create_many_without_hook(
+        products: &[Product],
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Product>, SurrealSocketError> {
+        let products: Vec<Product> = products.iter().map(|p| p.with_hook_fields(now)).collect();
+
+        if products.is_empty() {
+            return Ok(products);
+        }
+
+        let client = surrealdb_client().await?;
+        let query = format!("INSERT INTO {} $products", Self::table());
+        client
+            .query(query)
+            .bind(("products", products.clone()))
+            .await?
+            .check()?;
+
+        Ok(products)
+    }
+
+    /// This product with the fields the update hook would set, computed inline: its
+    /// `price_per_kg`, and `updated_at` as `now`
+    fn with_hook_fields(&self, now: DateTime<Utc>) -> Product {
+        Product {
+            price_per_kg: self.calculate_price_per_kg(),
+            updated_at: now,
+            ..self.clone()
+        }
+    }
+
+    /// Deletes the stored record and runs the delete hook
+    pub async fn delete(&self) -> Result<(), SurrealSocketError> {
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "DELETE {} WHERE {} = $uuid",
+            Self::table(),
+            Self::UUID_FIELD
+        );
+
+        client
+            .query(query)
+            .bind(("uuid", self.uuid()))
+            .await?
+            .check()?;
+
+        self.post_delete_hook().await
+    }
+
+    /// Removes the product's price events and availability history, then emits `Deleted`
+    /// so notification jobs can drop alerts for it
+    pub async fn post_delete_hook(&self) -> Result<(), SurrealSocketError> {
+        let client = surrealdb_client().await?;
+
+        client
+            .query(Self::delete_history_query())
+            .bind(("product_uuid", self.uuid()))
+            .await?
+            .check()?;
+
+        ProductEventRecord::emit(self.uuid(), ProductEvent::Deleted).await?;
+        Ok(())
+    }
+
+    fn delete_history_query() -> String {
+        format!(
+            "DELETE {} WHERE product_uuid = $product_uuid; \
+             DELETE {} WHERE product_uuid = $product_uuid;",
+            ProductEventRecord::table(),
+            AvailabilityHistory::table()
+        )
+    }
+
+    /// Returns the stored product with the same retailer and retailer product id, inserting
+    /// this one first if there is none. The flag is `true` when the product was created.
+    pub async fn get_or_insert(&self) -> Result<(Product, bool), SurrealSocketError> {
+        if let Some(existing) =
+            Self::find_by_retailer_id(&self.retailer, &self.retailer_product_id).await?
+        {
+            return Ok((existing, false));
+        }
+
+        Ok((self.insert().await?, true))
+    }
+
+    /// Overwrites the stored record with this product's fields, runs the update hook and
+    /// emits the events implied by the change from the record it replaced (see
+    /// `emit_update_events`)
+    pub async fn save(&self) -> Result<(), SurrealSocketError> {
+        self.save_with(&SystemClock).await
+    }
+
+    /// `save`, running the update hook and stamping events as of `clock`
+    pub async fn save_with(&self, clock: &dyn Clock) -> Result<(), SurrealSocketError> {
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT * FROM {table} WHERE {uuid} = $uuid; \
+             UPDATE {table} CONTENT $product WHERE {uuid} = $uuid;",
+            table = Self::table(),
+            uuid = Self::UUID_FIELD
+        );
+
+        let mut response = client
+            .query(query)
+            .bind(("product", self.clone()))
+            .bind(("uuid", self.uuid()))
+            .await?
+            .check()?;
+
+        let previous: Option<Product> = response.take(0)?;
+
+        self.post_update_hook_with(clock).await?;
+
+        if let Some(previous) = previous {
+            self.emit_update_events_with(&previous, &PriceHistoryConfig::from_env(), clock)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Records a scrape of this product: inserts it if no product with the same retailer
+    /// and retailer product id exists, otherwise saves the stored product with the scraped
+    /// price and source if either changed, and in every case marks the product as seen now.
+    /// Price changes beyond `PriceChangeGuard::from_env` are held back in `flagged_updates`
+    /// instead.
+    pub async fn touch_or_insert(&self) -> Result<TouchResult, SurrealSocketError> {
+        self.touch_or_insert_with(&SystemClock, &PriceChangeGuard::from_env())
+            .await
+    }
+
+    /// `touch_or_insert`, taking the time seen from `clock` and holding back price changes
+    /// beyond `guard`
+    pub async fn touch_or_insert_with(
+        &self,
+        clock: &dyn Clock,
+        guard: &PriceChangeGuard,
+    ) -> Result<TouchResult, SurrealSocketError> {
+        let now = clock.now();
+
+        let existing = Self::find_by_retailer_id(&self.retailer, &self.retailer_product_id).await?;
+        let (product, result) = self.plan_touch(existing, guard, now);
+
+        if result == TouchResult::Inserted {
+            product
+                .insert_with(&IngestConfig::from_env(), clock)
+                .await?;
+        } else if result == TouchResult::Touched {
+            let client = surrealdb_client().await?;
+
+            let query = format!(
+                "UPDATE {} SET last_seen = $last_seen, source = $source WHERE {} = $uuid",
+                Self::table(),
+                Self::UUID_FIELD
+            );
+
+            client
+                .query(query)
+                .bind(("last_seen", now))
+                .bind(("source", product.source.clone()))
+                .bind(("uuid", product.uuid()))
+                .await?
+                .check()?;
+        } else {
+            product.save_with(clock).await?;
+        }
+
+        Ok(result)
+    }
+
+    /// Works out what `touch_or_insert` writes for this scrape given the `existing` stored
+    /// record: the price goes through `guard`, the source is taken from the scrape when it
+    /// names one, and `last_seen` becomes `now`
+    fn plan_touch(
+        &self,
+        existing: Option<Product>,
+        guard: &PriceChangeGuard,
+        now: DateTime<Utc>,
+    ) -> (Product, TouchResult) {
+        let Some(mut product) = existing else {
+            let mut product = self.clone();
+            product.last_seen = Some(now);
+            return (product, TouchResult::Inserted);
+        };
+
+        let update = product.apply_price_update(self.price, guard);
+        let source_changed = self.source.is_some() && product.source != self.source;
+
+        product.last_seen = Some(now);
+
+        if source_changed {
+            product.source = self.source.clone();
+        }
+
+        let result = match update {
+            PriceUpdate::Flagged => TouchResult::Flagged,
+            PriceUpdate::Applied => TouchResult::Updated,
+            PriceUpdate::Unchanged if source_changed => TouchResult::Updated,
+            PriceUpdate::Unchanged => TouchResult::Touched,
+        };
+
+        (product, result)
+    }
+
+    /// Moves every product priced in `from` to `to`, returning how many changed. With
+    /// `convert`, amounts are multiplied by `rate` (units of `to` per unit of `from`);
+    /// otherwise only the label changes, for listings that were tagged with the wrong currency.
+    pub async fn reassign_currency(
+        from: &Currency,
+        to: &Currency,
+        convert: bool,
+        rate: Option<f32>,
+    ) -> Result<u64, SurrealSocketError> {
+        Self::reassign_currency_with(from, to, convert, rate, &SystemClock).await
+    }
+
+    /// `reassign_currency`, stamping `updated_at` from `clock`
+    pub async fn reassign_currency_with(
+        from: &Currency,
+        to: &Currency,
+        convert: bool,
+        rate: Option<f32>,
+        clock: &dyn Clock,
+    ) -> Result<u64, SurrealSocketError> {
+        let (query, rate) = Self::reassign_currency_statement(convert, rate)?;
+        let client = surrealdb_client().await?;
+
+        let mut response = client
+            .query(query)
+            .bind(("from", from.to_string()))
+            .bind(("to", to.to_string()))
+            .bind(("default", Currency::default().to_string()))
+            .bind(("rate", rate))
+            .bind(("updated_at", clock.now().timestamp_millis()))
+            .await?;
+
+        let updated: Vec<serde_json::Value> = response.take(1)?;
+        Ok(updated.len() as u64)
+    }
+
+    /// Query and `$rate` binding for `reassign_currency`, the rate being 1.0 when only
+    /// relabeling. Converting also rescales held-back `flagged_updates` and the recorded
+    /// price change events, so history stays comparable with the converted price.
+    fn reassign_currency_statement(
+        convert: bool,
+        rate: Option<f32>,
+    ) -> Result<(String, f32), SurrealSocketError> {
+        let rate = match (convert, rate) {
+            (true, Some(rate)) if rate.is_finite() && rate > 0.0 => Some(rate),
+            (true, _) => {
+                return Err(SurrealSocketError::from(
+                    "Converting currency requires a positive exchange rate".to_owned(),
+                ));
+            }
+            (false, _) => None,
+        };
+
+        let mut query = format!(
+            "LET $moved = (SELECT VALUE {uuid} FROM {table} WHERE (currency ?? $default) = $from); \
+             UPDATE {table} SET currency = $to, updated_at = $updated_at{amounts} \
+             WHERE {uuid} IN $moved RETURN {uuid};",
+            table = Self::table(),
+            uuid = Self::UUID_FIELD,
+            amounts = if rate.is_some() { CONVERT_AMOUNTS } else { "" },
+        );
+
+        if rate.is_some() {
+            query.push_str(&format!(
+                " UPDATE {} SET event.previous = <int> math::round(event.previous * $rate), \
+                 event.current = <int> math::round(event.current * $rate) \
+                 WHERE event.type = 'PriceChanged' AND product_uuid IN $moved;",
+                ProductEventRecord::table()
+            ));
+        }
+
+        Ok((query, rate.unwrap_or(1.0)))
+    }
+
+    /// JSON with keys sorted at every level, for reproducible diffs and backups
+    pub fn to_canonical_json(&self) -> String {
+        let value = serde_json::to_value(self).expect("Product serializes to JSON");
+        serde_json::to_string(&CanonicalJson::from(value)).expect("Product serializes to JSON")
+    }
+}
+
+/// Amounts `reassign_currency` multiplies by `$rate` when converting
+const CONVERT_AMOUNTS: &str = ", price = <int> math::round(price * $rate), \
+     price_per_kg = <int> math::round(price_per_kg * $rate), \
+     list_price = IF list_price != NONE THEN <int> math::round(list_price * $rate) END, \
+     shipping = IF shipping != NONE THEN <int> math::round(shipping * $rate) END, \
+     flagged_updates = array::map(flagged_updates, |$f| { \
+     previous_price: <int> math::round($f.previous_price * $rate), \
+     proposed_price: <int> math::round($f.proposed_price * $rate), \
+     change_percent: $f.change_percent })";
+
+/// Trims, collapses whitespace and capitalizes each word, so "galaxy  black" and
+/// "Galaxy Black" group together
+pub fn normalize_color(color: &str) -> String {
+    color
+        .split_whitespace()
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first
+                    .to_uppercase()
+                    .chain(chars.flat_map(char::to_lowercase))
+                    .collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Query parameters that only track where a visitor came from
+const TRACKING_PARAMS: [&str; 6] = ["fbclid", "gclid", "msclkid", "ref", "ref_", "tag"];
+
+/// Form of `url` used to tell whether two URLs lead to the same page: lowercased scheme and
+/// host without `www.`, no fragment, trailing slash, `utm_*` or other tracking parameters,
+/// nor Amazon `/ref=` path segments
+pub fn canonicalize_url(url: &str) -> String {
+    let url = url.trim();
+    let url = url.split_once('#').map_or(url, |(url, _)| url);
+
+    let (scheme, rest) = url.split_once("://").unwrap_or(("https", url));
+    let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let (host, path) = address.split_once('/').unwrap_or((address, ""));
+
+    let host = host.to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+
+    let path = path
+        .split('/')
+        .filter(|segment| !segment.is_empty() && !segment.starts_with("ref="))
+        .collect::<Vec<&str>>()
+        .join("/");
+
+    let params: Vec<&str> = query
+        .split('&')
+        .filter(|param| {
+            let name = param.split('=').next().unwrap_or_default();
+            !name.is_empty() && !name.starts_with("utm_") && !TRACKING_PARAMS.contains(&name)
+        })
+        .collect();
+
+    let mut canonical = format!("{}://{}", scheme.to_lowercase(), host);
+
+    if !path.is_empty() {
+        canonical += "/";
+        canonical += &path;
+    }
+
+    if !params.is_empty() {
+        canonical += "?";
+        canonical += &params.join("&");
+    }
+
+    canonical
+}
+
+/// Basic color words recognized by `color_family`, checked in order
+const COLOR_FAMILIES: [(&str, &[&str]); 14] = [
+    ("Transparent", &["transparent", "clear", "translucent"]),
+    ("Black", &["black"]),
+    ("White", &["white"]),
+    ("Gray", &["gray", "grey"]),
+    ("Silver", &["silver"]),
+    ("Gold", &["gold"]),
+    ("Red", &["red"]),
+    ("Orange", &["orange"]),
+    ("Yellow", &["yellow"]),
+    ("Green", &["green"]),
+    ("Blue", &["blue"]),
+    ("Purple", &["purple", "violet"]),
+    ("Pink", &["pink", "magenta"]),
+    ("Brown", &["brown"]),
+];
+
+/// Broad color family of a color name, e.g. "Galaxy Black" is "Black". Colors without a
+/// recognized color word are "Other".
+pub fn color_family(color: &str) -> &'static str {
+    let color = color.to_lowercase();
+
+    COLOR_FAMILIES
+        .iter()
+        .find(|(_, words)| {
+            color
+                .split(|c: char| !c.is_alphanumeric())
+                .any(|word| words.contains(&word))
+        })
+        .map_or("Other", |(family, _)| family)
+}
+
+/// JSON value with object keys held in a `BTreeMap`, so it serializes with sorted keys
+/// regardless of how `serde_json` orders maps
+#[derive(Serialize)]
+#[serde(untagged)]
+enum CanonicalJson {
+    Object(BTreeMap<String, CanonicalJson>),
+    Array(Vec<CanonicalJson>),
+    Scalar(serde_json::Value),
+}
+
+impl From<serde_json::Value> for CanonicalJson {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Object(map) => {
+                Self::Object(map.into_iter().map(|(k, v)| (k, v.into())).collect())
+            }
+            serde_json::Value::Array(items) => {
+                Self::Array(items.into_iter().map(Into::into).collect())
+            }
+            scalar => Self::Scalar(scalar),
+        }
+    }
+}
+
+/// Fraction of `others` below `value`, counting ties as half
+fn percentile_rank(others: &[CentsPerKg], value: CentsPerKg) -> f32 {
+    if others.is_empty() {
+        return 0.5;
+    }
+
+    let below = others.iter().filter(|p| **p < value).count() as f32;
+    let equal = others.iter().filter(|p| **p == value).count() as f32;
+
+    (below + equal / 2.0) / others.len() as f32
+}
+
+/// Distinct normalized colors with their number of occurrences, most common first, ties by
+/// name. Blank colors are skipped.
+fn color_counts(colors: impl IntoIterator<Item = String>) -> Vec<(String, u64)> {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+
+    for color in colors {
+        let color = normalize_color(&color);
+
+        if !color.is_empty() {
+            *counts.entry(color).or_default() += 1;
+        }
+    }
+
+    let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+/// Fraction by which `price_per_kg` undercuts `average`, negative when above it
+fn savings_vs_average(price_per_kg: CentsPerKg, average: Option<f64>) -> Option<f32> {
+    average
+        .filter(|average| *average > 0.0)
+        .map(|average| ((average - price_per_kg.0 as f64) / average) as f32)
+}
+
+/// Lowest `price_per_kg` product per key, skipping products `key` returns `None` for. The
+/// first of equally priced products wins, so callers can pass them in a meaningful order.
+pub(crate) fn cheapest_by<K: Eq + std::hash::Hash>(
+    products: impl IntoIterator<Item = Product>,
+    key: impl Fn(&Product) -> Option<K>,
+) -> HashMap<K, Product> {
+    let mut cheapest: HashMap<K, Product> = HashMap::new();
+
+    for product in products {
+        let Some(key) = key(&product) else {
+            continue;
+        };
+
+        match cheapest.entry(key) {
+            Entry::Occupied(mut entry) => {
+                if product.price_per_kg < entry.get().price_per_kg {
+                    entry.insert(product);
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(product);
+            }
+        }
+    }
+
+    cheapest
+}
+
+/// Parameter the product is bound to in `Product::to_insert_statement`
+const INSERT_BINDING: &str = "product";
+
+/// Typical weight of an empty plastic 1 kg spool, for listings that don't state it
+pub const DEFAULT_SPOOL_WEIGHT: Grams = Grams(200);
+
+/// Spools lighter than this are treated as samples
+pub const SAMPLE_MAX_WEIGHT: Grams = Grams(100);
+
+/// Words that mark a product name as a sample or trial spool
+pub const SAMPLE_NAME_WORDS: [&str; 2] = ["sample", "trial"];
+
+/// Threshold above which a price change is considered a likely scrape error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PriceChangeGuard {
+    /// Maximum change in percent, measured relative to the lower of the two prices so that
+    /// jumps and drops of the same factor are treated alike (a 10x change in either direction is 900%)
+    pub max_change_percent: u32,
+}
+
+impl Default for PriceChangeGuard {
+    fn default() -> Self {
+        Self {
+            max_change_percent: 200,
+        }
+    }
+}
+
+impl PriceChangeGuard {
+    /// Reads the maximum change from `FILAMENTSEEK_MAX_PRICE_CHANGE_PERCENT`, where unset or
+    /// unparseable means the default of 200%
+    pub fn from_env() -> Self {
+        std::env::var("FILAMENTSEEK_MAX_PRICE_CHANGE_PERCENT")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .map_or_else(Self::default, |max_change_percent| Self {
+                max_change_percent,
+            })
+    }
+
+    fn change_percent(old: Cents, new: Cents) -> f32 {
+        let (low, high) = if old < new { (old, new) } else { (new, old) };
+
+        if low.0 == 0 {
+            return f32::INFINITY;
+        }
+
+        (high.0 as f32 / low.0 as f32 - 1.0) * 100.0
+    }
+}
+
+/// Outcome of `Product::touch_or_insert`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TouchResult {
+    /// No matching product existed, so it was created
+    Inserted,
+    /// A matching product existed and its price or source changed
+    Updated,
+    /// A matching product existed with the same price and source; only `last_seen` changed
+    Touched,
+    /// A matching product existed, but its new price was held back by the price change
+    /// guard and recorded in `flagged_updates` for review
+    Flagged,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PriceUpdate {
+    Applied,
+    Flagged,
+    Unchanged,
+}
+
+/// Price change held back for review instead of being applied
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct FlaggedUpdate {
+    pub previous_price: Cents,
+    pub proposed_price: Cents,
+    pub change_percent: f32,
+}
+
+// `DBRecord` is declared with `#[async_trait]` in surreal_socket, so implementations have to
+// use it as well; switching to native `async fn` in traits has to happen upstream first.
+#[async_trait]
+impl DBRecord for Product {
+    fn uuid(&self) -> SsUuid<Self> {
+        self.uuid.to_owned()
+    }
+
+    const TABLE_NAME: &'static str = "products";
+
+    async fn post_update_hook(&self) -> Result<(), SurrealSocketError> {
+        self.post_update_hook_with(&SystemClock).await
+    }
+}
+
+impl Product {
+    /// The update hook run by `insert` and `save`: sets `price_per_kg` and `updated_at` (from
+    /// `clock`) on the stored record and records any availability transition
+    pub async fn post_update_hook_with(&self, clock: &dyn Clock) -> Result<(), SurrealSocketError> {
+        let client = surrealdb_client().await?;
+
+        client
+            .query(self.post_update_hook_query()?)
+            .bind(("updated_at", clock.now().timestamp_millis()))
+            .await?;
+
+        AvailabilityHistory::record_transition_with(self, clock).await
+    }
+
+    /// The update the hook runs on the stored record, binding `$updated_at`
+    fn post_update_hook_query(&self) -> Result<String, serde_json::Error> {
+        Ok(format!(
+            r#"
+            UPDATE {} SET price_per_kg = {}, updated_at = $updated_at WHERE {} = {};
+            "#,
+            Self::table(),
+            self.calculate_price_per_kg().0,
+            Self::UUID_FIELD,
+            serde_json::to_string(&self.uuid())?
+        ))
+    }
+}
+
+/// Amount in the minor unit of the product's currency: cents for most, whole yen for JPY
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+pub struct Cents(pub u32);
+
+impl Cents {
+    /// Nudge (in cents) applied before rounding so that values like 1.005, stored as
+    /// 100.49999.. cents, round to the intended cent instead of the one below
+    pub const ROUNDING_EPSILON: f64 = 1e-6;
+
+    /// Converts a dollar amount to the nearest cent. Returns `None` for negative,
+    /// non-finite or out-of-range amounts.
+    pub fn from_dollars(dollars: f64) -> Option<Self> {
+        Self::from_dollars_with_epsilon(dollars, Self::ROUNDING_EPSILON)
+    }
+
+    pub fn from_dollars_with_epsilon(dollars: f64, epsilon: f64) -> Option<Self> {
+        if !dollars.is_finite() || dollars < 0.0 {
+            return None;
+        }
+
+        let cents = (dollars * 100.0 + epsilon).round();
+
+        if cents > u32::MAX as f64 {
+            return None;
+        }
+
+        Some(Cents(cents as u32))
+    }
+
+    /// Parses a dollar amount such as "1,299.00", "$19.95" or "1299" exactly, without going
+    /// through a float. `decimal` names the locale's decimal separator, the other of `.` and `,`
+    /// being the grouping separator. Without it the separator is inferred, and inputs that read
+    /// differently in different locales, like "1,299", are rejected.
+    pub fn from_dollars_str(s: &str, decimal: Option<DecimalSeparator>) -> Result<Self, String> {
+        let trimmed = s.trim();
+        let amount = trimmed.strip_prefix('$').unwrap_or(trimmed).trim_start();
+
+        let decimal = match decimal {
+            Some(decimal) => decimal,
+            None => DecimalSeparator::infer(amount).ok_or_else(|| {
+                format!("Ambiguous price {:?}: decimal separator must be given", s)
+            })?,
+        };
+
+        let (whole, fraction) = match amount.rsplit_once(decimal.as_char()) {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (amount, None),
+        };
+
+        let groups: Vec<&str> = whole.split(decimal.grouping_char()).collect();
+        let is_digits = |g: &str| !g.is_empty() && g.bytes().all(|b| b.is_ascii_digit());
+
+        let groups_valid = groups.iter().enumerate().all(|(i, group)| {
+            is_digits(group)
+                && (groups.len() == 1 || group.len() == 3 || (i == 0 && group.len() < 3))
+        });
+
+        let fraction_valid = fraction.is_none_or(|f| is_digits(f) && f.len() <= 2);
+
+        if !groups_valid || !fraction_valid {
+            return Err(format!("Invalid price {:?}", s));
+        }
+
+        let whole: u64 = groups
+            .concat()
+            .parse()
+            .map_err(|_| format!("Price {:?} is out of range", s))?;
+
+        let fraction = match fraction {
+            Some(f) if f.len() == 1 => f.parse::<u64>().unwrap_or_default() * 10,
+            Some(f) => f.parse().unwrap_or_default(),
+            None => 0,
+        };
+
+        whole
+            .checked_mul(100)
+            .and_then(|cents| u32::try_from(cents + fraction).ok())
+            .map(Cents)
+            .ok_or_else(|| format!("Price {:?} is out of range", s))
+    }
+
+    pub fn dollars(&self) -> f64 {
+        self.0 as f64 / 100.0
+    }
+
+    /// Dollars with two decimals and no symbol, e.g. "19.95"
+    pub fn dollars_string(&self) -> String {
+        format!("{}.{:02}", self.0 / 100, self.0 % 100)
+    }
+
+    /// Converts an amount in `currency`'s major unit to its minor unit, e.g. 19.95 USD to
+    /// 1995 and 1995 JPY to 1995. `None` as for `from_dollars`.
+    pub fn from_amount(amount: f64, currency: &Currency) -> Option<Self> {
+        match currency.minor_units() {
+            0 => Self::from_dollars(amount / 100.0),
+            _ => Self::from_dollars(amount),
+        }
+    }
+
+    /// Parses an amount in `currency`'s major unit as `from_dollars_str` does, e.g. "19.95"
+    /// USD to 1995 and "¥1,995" JPY to 1995. For currencies without a minor unit, `.` and `,`
+    /// are grouping separators unless `decimal` says otherwise, and fractions are rejected.
+    pub fn from_amount_str(
+        s: &str,
+        currency: &Currency,
+        decimal: Option<DecimalSeparator>,
+    ) -> Result<Self, String> {
+        if currency.minor_units() > 0 {
+            return Self::from_dollars_str(s, decimal);
+        }
+
+        let amount = s.trim().trim_start_matches(['¥', '￥']);
+        let hundredths =
+            Self::from_dollars_str(amount, Some(decimal.unwrap_or(DecimalSeparator::Point)))?;
+
+        if hundredths.0 % 100 != 0 {
+            return Err(format!("{} has no minor unit, got {:?}", currency, s));
+        }
+
+        Ok(Cents(hundredths.0 / 100))
+    }
+
+    /// Amount in `currency`'s major unit with its number of decimals and no symbol, e.g.
+    /// "19.95" for USD and "1995" for JPY
+    pub fn amount_string(&self, currency: &Currency) -> String {
+        match currency.minor_units() {
+            0 => self.0.to_string(),
+            _ => self.dollars_string(),
+        }
+    }
+
+    /// `amount_string` labelled with the currency, e.g. "$19.95" or "1995 JPY"
+    pub fn price_string(&self, currency: &Currency) -> String {
+        currency.label(&self.amount_string(currency))
+    }
+}
+
+/// Decimal separator of a locale, for reading prices with `Cents::from_dollars_str`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecimalSeparator {
+    /// "1,299.00"
+    Point,
+    /// "1.299,00"
+    Comma,
+}
+
+impl DecimalSeparator {
+    fn as_char(self) -> char {
+        match self {
+            Self::Point => '.',
+            Self::Comma => ',',
+        }
+    }
+
+    fn grouping_char(self) -> char {
+        match self {
+            Self::Point => ',',
+            Self::Comma => '.',
+        }
+    }
+
+    /// Separator `amount` can only have been written with, or `None` if it reads as a
+    /// valid price either way (a single separator followed by exactly three digits)
+    fn infer(amount: &str) -> Option<Self> {
+        let from_char = |c| if c == ',' { Self::Comma } else { Self::Point };
+
+        let mut separators = amount
+            .char_indices()
+            .filter(|(_, c)| *c == '.' || *c == ',');
+        let Some((last_index, last)) = separators.next_back() else {
+            return Some(Self::Point);
+        };
+
+        if let Some((_, earlier)) = separators.next_back() {
+            // Several of the same separator can only be grouping; otherwise the last is decimal
+            return Some(match from_char(last) {
+                Self::Point if earlier == last => Self::Comma,
+                Self::Comma if earlier == last => Self::Point,
+                decimal => decimal,
+            });
+        }
+
+        if amount.len() - last_index - 1 == 3 {
+            None
+        } else {
+            Some(from_char(last))
+        }
+    }
+}
+
+/// Price per kilogram in cents, a rate kept distinct from absolute `Cents` prices
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema,
+)]
+pub struct CentsPerKg(pub u32);
+
+impl CentsPerKg {
+    /// Per-kg amount in `currency`'s major unit and no symbol, as `Cents::amount_string`
+    pub fn amount_string(&self, currency: &Currency) -> String {
+        Cents(self.0).amount_string(currency)
+    }
+
+    /// Per-kg price labelled with the currency, e.g. "$19.95/kg" or "1995 JPY/kg"
+    pub fn price_string(&self, currency: &Currency) -> String {
+        format!("{}/kg", Cents(self.0).price_string(currency))
+    }
+}
+
+/// Price per kg of `price` for `weight`, saturating when the weight is zero
+impl From<(Cents, Grams)> for CentsPerKg {
+    fn from((price, weight): (Cents, Grams)) -> Self {
+        CentsPerKg(((price.0 as f32 / weight.0 as f32) * 1000.0).round() as u32)
+    }
+}
+
+/// Serde helper for `Cents` fields exchanged as dollar floats, for use with
+/// `#[serde(with = "dollars")]`
+pub mod dollars {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    use super::Cents;
+
+    pub fn serialize<S: Serializer>(cents: &Cents, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(cents.dollars())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Cents, D::Error> {
+        let dollars = f64::deserialize(deserializer)?;
+        Cents::from_dollars(dollars)
+            .ok_or_else(|| D::Error::custom(format!("invalid dollar amount: {}", dollars)))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, ToSchema, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum FilamentMaterial {
+    PLA,
+    PLAPlus,
+    ABS,
+    PETG,
+    TPU,
+    Nylon,
+    PC,
+    ASA,
+    PCTG,
+    /// The listing was checked and does not state a material.
+    /// Records written before `Unknown` existed keep this value.
+    Unspecified,
+    /// The material has not been determined yet
+    Unknown,
+    /// Any other material, kept exactly as given (no trimming or case changes) so it
+    /// serializes back to the same string. A string naming a known variant, such as "PLA",
+    /// deserializes as that variant rather than as `Other`.
+    Other(String),
+}
+
+impl FromStr for FilamentMaterial {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "PLA" => Self::PLA,
+            "PLAPlus" => Self::PLAPlus,
+            "ABS" => Self::ABS,
+            "PETG" => Self::PETG,
+            "TPU" => Self::TPU,
+            "Nylon" => Self::Nylon,
+            "PC" => Self::PC,
+            "ASA" => Self::ASA,
+            "PCTG" => Self::PCTG,
+            "Unspecified" => Self::Unspecified,
+            "Unknown" => Self::Unknown,
+            other => Self::Other(other.to_string()),
+        })
+    }
+}
+
+impl std::fmt::Display for FilamentMaterial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PLA => write!(f, "PLA"),
+            Self::PLAPlus => write!(f, "PLAPlus"),
+            Self::ABS => write!(f, "ABS"),
+            Self::PETG => write!(f, "PETG"),
+            Self::TPU => write!(f, "TPU"),
+            Self::Nylon => write!(f, "Nylon"),
+            Self::PC => write!(f, "PC"),
+            Self::ASA => write!(f, "ASA"),
+            Self::PCTG => write!(f, "PCTG"),
+            Self::Unspecified => write!(f, "Unspecified"),
+            Self::Unknown => write!(f, "Unknown"),
+            Self::Other(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl FilamentMaterial {
+    /// Typical density, or `None` when the material is not known well enough to say
+    pub fn density_g_per_cm3(&self) -> Option<f32> {
+        Some(match self {
+            Self::PLA | Self::PLAPlus => 1.24,
+            Self::ABS => 1.04,
+            Self::PETG => 1.27,
+            Self::TPU => 1.21,
+            Self::Nylon => 1.14,
+            Self::PC => 1.20,
+            Self::ASA => 1.07,
+            Self::PCTG => 1.23,
+            Self::Unspecified | Self::Unknown | Self::Other(_) => return None,
+        })
+    }
+
+    /// Typical maximum print speed for slicer profile suggestions, in mm/s, or `None` when
+    /// the material is not known well enough to say. Flexible TPU needs much slower speeds.
+    pub fn max_print_speed_mm_s(&self) -> Option<u16> {
+        Some(match self {
+            Self::PLA | Self::PLAPlus => 200,
+            Self::PETG | Self::PCTG => 150,
+            Self::ABS | Self::ASA => 120,
+            Self::Nylon => 100,
+            Self::PC => 80,
+            Self::TPU => 40,
+            Self::Unspecified | Self::Unknown | Self::Other(_) => return None,
+        })
+    }
+
+    /// Rough cradle-to-gate emissions of producing the polymer, in kg CO2e per kg, from
+    /// published averages. `None` when the material is not known well enough to say.
+    pub fn co2_kg_per_kg(&self) -> Option<f32> {
+        Some(match self {
+            Self::PLA | Self::PLAPlus => 1.8,
+            Self::ABS => 3.5,
+            Self::PETG | Self::PCTG => 3.0,
+            Self::TPU => 4.5,
+            Self::Nylon => 7.0,
+            Self::PC => 7.6,
+            Self::ASA => 3.7,
+            Self::Unspecified | Self::Unknown | Self::Other(_) => return None,
+        })
+    }
+
+    /// Whether the base polymer can be food safe at all. Conservative: `true` only for
+    /// materials commonly certified for food contact, and never a guarantee for a given
+    /// spool or print (see `food_safety_note`).
+    pub fn food_safe_capable(&self) -> bool {
+        matches!(self, Self::PLA | Self::PETG | Self::PCTG)
+    }
+
+    /// Caveat to show alongside `food_safe_capable`
+    pub fn food_safety_note(&self) -> &'static str {
+        match self {
+            Self::PLA | Self::PETG | Self::PCTG => {
+                "Only with a food-contact certified spool and unpigmented or certified colorant, \
+                 a stainless steel nozzle, and a food-safe coating to seal layer lines"
+            }
+            Self::PLAPlus => "Additives vary by brand and are rarely certified for food contact",
+            Self::ABS | Self::ASA => "Contains styrene and is not suitable for food contact",
+            Self::PC => "May release BPA and is not suitable for food contact",
+            Self::TPU | Self::Nylon => "Absorbs moisture and harbours bacteria; not recommended",
+            Self::Unspecified | Self::Unknown | Self::Other(_) => {
+                "Unknown material; check the manufacturer's food contact certification"
+            }
+        }
+    }
+}
+
+impl From<String> for FilamentMaterial {
+    fn from(s: String) -> Self {
+        FilamentMaterial::from_str(&s).unwrap()
+    }
+}
+
+impl From<FilamentMaterial> for String {
+    fn from(m: FilamentMaterial) -> String {
+        m.to_string()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub enum Availability {
+    InStock,
+    LowStock,
+    OutOfStock,
+    #[default]
+    Unknown,
+}
+
+impl std::fmt::Display for Availability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InStock => write!(f, "InStock"),
+            Self::LowStock => write!(f, "LowStock"),
+            Self::OutOfStock => write!(f, "OutOfStock"),
+            Self::Unknown => write!(f, "Unknown"),
+        }
+    }
+}
+
+impl Availability {
+    pub fn is_purchasable(&self) -> bool {
+        matches!(self, Self::InStock | Self::LowStock)
+    }
+}
+
+/// What the filament is wound on, for buyers avoiding single-use plastic spools
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub enum SpoolType {
+    Plastic,
+    Cardboard,
+    /// Spool kept and refilled, e.g. a masterspool
+    Reusable,
+    /// Coil without a spool, for a reusable one
+    Refill,
+    #[default]
+    Unknown,
+}
+
+impl std::fmt::Display for SpoolType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Plastic => write!(f, "Plastic"),
+            Self::Cardboard => write!(f, "Cardboard"),
+            Self::Reusable => write!(f, "Reusable"),
+            Self::Refill => write!(f, "Refill"),
+            Self::Unknown => write!(f, "Unknown"),
+        }
+    }
+}
+
+impl FromStr for SpoolType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "Plastic" => Self::Plastic,
+            "Cardboard" => Self::Cardboard,
+            "Reusable" => Self::Reusable,
+            "Refill" => Self::Refill,
+            _ => Self::Unknown,
+        })
+    }
+}
+
+/// How good a product's price per kg is within its material, for listings priced below the
+/// median
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum DealBadge {
+    /// Cheapest quarter
+    Great,
+    /// Below the 40th percentile
+    Good,
+    /// Below the median
+    Fair,
+}
+
+impl DealBadge {
+    /// Badge for a `Product::price_percentile`, `None` at or above the median
+    pub fn from_percentile(percentile: f32) -> Option<Self> {
+        if percentile < 0.25 {
+            Some(Self::Great)
+        } else if percentile < 0.4 {
+            Some(Self::Good)
+        } else if percentile < 0.5 {
+            Some(Self::Fair)
+        } else {
+            None
+        }
+    }
+}
+
+/// How recently a product was updated, for "New" and "Stale" badges
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum Freshness {
+    /// Under a day
+    New,
+    /// Under a week
+    Recent,
+    /// Under 30 days
+    Aging,
+    /// 30 days or more
+    Stale,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+pub struct Celsius(pub u16);
+
+/// Inclusive temperature range, e.g. a recommended nozzle temperature. Always has
+/// `min <= max`, which deserialization also enforces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(try_from = "RawTempRange")]
+pub struct TempRange {
+    min: Celsius,
+    max: Celsius,
+}
+
+#[derive(Deserialize)]
+struct RawTempRange {
+    min: Celsius,
+    max: Celsius,
+}
+
+impl TempRange {
+    pub fn new(min: Celsius, max: Celsius) -> Result<Self, String> {
+        if min > max {
+            return Err(format!(
+                "Temperature range minimum {}°C is above maximum {}°C",
+                min.0, max.0
+            ));
+        }
+
+        Ok(Self { min, max })
+    }
+
+    pub fn min(&self) -> Celsius {
+        self.min
+    }
+
+    pub fn max(&self) -> Celsius {
+        self.max
+    }
+
+    pub fn contains(&self, t: Celsius) -> bool {
+        (self.min..=self.max).contains(&t)
+    }
+}
+
+impl TryFrom<RawTempRange> for TempRange {
+    type Error = String;
+
+    fn try_from(raw: RawTempRange) -> Result<Self, Self::Error> {
+        Self::new(raw.min, raw.max)
+    }
+}
+
+/// "190–220°C", or "200°C" when both ends are equal
+impl std::fmt::Display for TempRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}°C", self.min.0)
+        } else {
+            write!(f, "{}–{}°C", self.min.0, self.max.0)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+pub struct Grams(pub u16);
+
+impl Grams {
+    pub fn checked_sub(self, rhs: Grams) -> Option<Grams> {
+        self.0.checked_sub(rhs.0).map(Grams)
+    }
+}
+
+/// Saturates at zero, e.g. for net weight when a spool weight exceeds the listed weight
+impl std::ops::Sub for Grams {
+    type Output = Grams;
+
+    fn sub(self, rhs: Grams) -> Grams {
+        Grams(self.0.saturating_sub(rhs.0))
+    }
+}
+
+/// Deserializes a weight given either as integer grams (`1000`) or as float kilograms
+/// (`1.0`). Use with `#[serde(deserialize_with = "grams_or_kg")]`.
+pub fn grams_or_kg<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Grams, D::Error> {
+    use serde::de::Error;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Weight {
+        Grams(u64),
+        Kilograms(f64),
+    }
+
+    let grams = match Weight::deserialize(deserializer)? {
+        Weight::Grams(grams) => grams as f64,
+        Weight::Kilograms(kg) => (kg * 1000.0).round(),
+    };
+
+    if !(0.0..=u16::MAX as f64).contains(&grams) {
+        return Err(D::Error::custom(format!(
+            "weight out of range: {} g",
+            grams
+        )));
+    }
+
+    Ok(Grams(grams as u16))
+}
+
+/// ISO 4217 currency of a product's prices. Records written before currencies were tracked
+/// are USD.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(try_from = "String", into = "String")]
+pub enum Currency {
+    #[default]
+    USD,
+    EUR,
+    GBP,
+    CAD,
+    AUD,
+    JPY,
+    Other(String),
+}
+
+impl FromStr for Currency {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "USD" => Self::USD,
+            "EUR" => Self::EUR,
+            "GBP" => Self::GBP,
+            "CAD" => Self::CAD,
+            "AUD" => Self::AUD,
+            "JPY" => Self::JPY,
+            other => Self::Other(other.to_string()),
+        })
+    }
+}
+
+impl Currency {
+    /// Number of decimals of the currency's minor unit. Unknown currencies are taken to have
+    /// two, like most.
+    pub fn minor_units(&self) -> u32 {
+        match self {
+            Self::JPY => 0,
+            Self::USD | Self::EUR | Self::GBP | Self::CAD | Self::AUD | Self::Other(_) => 2,
+        }
+    }
+
+    /// `amount` (in this currency, without a symbol) labelled for display: "$19.95" for
+    /// dollars, "19.95 EUR" for everything else
+    pub fn label(&self, amount: &str) -> String {
+        match self {
+            Self::USD => format!("${}", amount),
+            _ => format!("{} {}", amount, self),
+        }
+    }
+}
+
+impl std::fmt::Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::USD => write!(f, "USD"),
+            Self::EUR => write!(f, "EUR"),
+            Self::GBP => write!(f, "GBP"),
+            Self::CAD => write!(f, "CAD"),
+            Self::AUD => write!(f, "AUD"),
+            Self::JPY => write!(f, "JPY"),
+            Self::Other(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<String> for Currency {
+    fn from(s: String) -> Self {
+        Currency::from_str(&s).unwrap()
+    }
+}
+
+impl From<Currency> for String {
+    fn from(c: Currency) -> String {
+        c.to_string()
+    }
+}
+
+/// Filament diameter in hundredths of a millimeter (e.g. 175 = 1.75 mm)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(into = "u16", try_from = "u16")]
+pub enum FilamentDiameter {
+    D175,
+    D285,
+    Other(u16),
+}
+
+impl From<FilamentDiameter> for u16 {
+    fn from(d: FilamentDiameter) -> Self {
+        match d {
+            FilamentDiameter::D175 => 175,
+            FilamentDiameter::D285 => 285,
+            FilamentDiameter::Other(x) => x,
+        }
+    }
+}
+
+impl TryFrom<u16> for FilamentDiameter {
+    type Error = &'static str;
+    fn try_from(v: u16) -> Result<Self, Self::Error> {
+        Ok(match v {
+            175 => FilamentDiameter::D175,
+            285 => FilamentDiameter::D285,
+            x => FilamentDiameter::Other(x),
+        })
+    }
+}
+
+impl FilamentDiameter {
+    pub fn mm(&self) -> f32 {
+        match self {
+            FilamentDiameter::D175 => 1.75,
+            FilamentDiameter::D285 => 2.85,
+            FilamentDiameter::Other(hundredths) => *hundredths as f32 / 100.0,
+        }
+    }
+
+    /// Snaps `Other` values within `tolerance_hundredths` of a standard diameter to it,
+    /// e.g. a measured 1.76 mm to `D175`
+    pub fn snap_to_standard(&self, tolerance_hundredths: u16) -> FilamentDiameter {
+        let FilamentDiameter::Other(hundredths) = *self else {
+            return *self;
+        };
+
+        [FilamentDiameter::D175, FilamentDiameter::D285]
+            .into_iter()
+            .find(|standard| u16::from(*standard).abs_diff(hundredths) <= tolerance_hundredths)
+            .unwrap_or(*self)
+    }
+
+    /// Nearest diameter for a value in millimeters, or `None` if it is negative or out of range
+    pub fn from_mm(mm: f32) -> Option<Self> {
+        let hundredths = (mm * 100.0).round();
+
+        if !(0.0..=u16::MAX as f32).contains(&hundredths) {
+            return None;
+        }
+
+        FilamentDiameter::try_from(hundredths as u16).ok()
+    }
+
+    /// Millimeters with two decimals, e.g. "1.75"
+    pub fn mm_string(&self) -> String {
+        let hundredths = u16::from(*self);
+        format!("{}.{:02}", hundredths / 100, hundredths % 100)
+    }
+
+    /// Label for display, e.g. "1.75 mm". Values above `MAX_PLAUSIBLE_DIAMETER_MM`, typically
+    /// scrape typos like `Other(1759)`, are marked as "invalid (17.59 mm)".
+    pub fn label_safe(&self) -> String {
+        if self.mm() > MAX_PLAUSIBLE_DIAMETER_MM {
+            format!("invalid ({} mm)", self.mm_string())
+        } else {
+            format!("{} mm", self.mm_string())
+        }
+    }
+}
+
+/// Largest diameter `FilamentDiameter::label_safe` treats as a real filament
+pub const MAX_PLAUSIBLE_DIAMETER_MM: f32 = 10.0;
+
+/// Serde helper for flat exports, representing a diameter as a decimal millimeter string
+/// ("1.75") instead of hundredths. Use with `#[serde(with = "diameter_as_mm")]`.
+pub mod diameter_as_mm {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    use super::FilamentDiameter;
+
+    pub fn serialize<S: Serializer>(
+        diameter: &FilamentDiameter,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&diameter.mm_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<FilamentDiameter, D::Error> {
+        let s = String::deserialize(deserializer)?;
+
+        s.trim()
+            .parse::<f32>()
+            .ok()
+            .and_then(FilamentDiameter::from_mm)
+            .ok_or_else(|| D::Error::custom(format!("invalid diameter in mm: {}", s)))
+    }
+}
+
+/// Product Request
+#[derive(Serialize, Deserialize, ToSchema)]
+#[schema(example = ProductRequest::example_json)]
+pub struct ProductRequest {
+    pub name: String,
+    pub brand: Option<String>,
+    pub price: Cents,
+    #[serde(default)]
+    pub currency: Currency,
+    pub url: String,
+    pub material: FilamentMaterial,
+    pub diameter: FilamentDiameter,
+    /// Integer grams or float kilograms
+    #[serde(deserialize_with = "grams_or_kg")]
+    pub weight: Grams,
+    pub retailer: Retailer,
+    pub retailer_product_id: String,
+    pub color: String,
+    #[serde(default)]
+    pub availability: Availability,
+    pub source: Option<String>,
+    pub spool_weight: Option<Grams>,
+    pub region: Option<String>,
+    pub pack_quantity: Option<u16>,
+    pub nozzle_temp: Option<TempRange>,
+    pub bed_temp: Option<TempRange>,
+    pub stock_quantity: Option<u32>,
+    pub list_price: Option<Cents>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub shipping: Option<Cents>,
+    #[serde(default)]
+    pub spool_type: SpoolType,
+}
+
+impl From<ProductRequest> for Product {
+    fn from(request: ProductRequest) -> Self {
+        Self::from_request(request, &SystemClock)
+    }
+}
+
+impl ProductRequest {
+    /// Realistic, fully populated request, for API documentation and tests
+    pub fn example() -> Self {
+        Self {
+            name: "Hatchbox PLA 3D Printer Filament, 1.75 mm, 1 kg Spool, Black".to_owned(),
+            brand: Some("Hatchbox".to_owned()),
+            price: Cents(2199),
+            currency: Currency::USD,
+            url: "https://www.amazon.com/dp/B00J0GMMP6".to_owned(),
+            material: FilamentMaterial::PLA,
+            diameter: FilamentDiameter::D175,
+            weight: Grams(1000),
+            retailer: Retailer::Amazon,
+            retailer_product_id: "B00J0GMMP6".to_owned(),
+            color: "Black".to_owned(),
+            availability: Availability::InStock,
+            source: Some("amazon-scraper".to_owned()),
+            spool_weight: Some(Grams(245)),
+            region: Some("US".to_owned()),
+            pack_quantity: None,
+            nozzle_temp: TempRange::new(Celsius(180), Celsius(210)).ok(),
+            bed_temp: TempRange::new(Celsius(50), Celsius(60)).ok(),
+            stock_quantity: Some(37),
+            list_price: Some(Cents(2499)),
+            tags: Vec::new(),
+            shipping: Some(Cents(0)),
+            spool_type: SpoolType::Plastic,
+        }
+    }
+
+    fn example_json() -> serde_json::Value {
+        serde_json::to_value(Self::example()).unwrap_or_default()
+    }
+}
+
+impl Product {
+    /// `ProductRequest::example` as stored, with its price per kg filled in
+    pub fn example() -> Self {
+        let created = DateTime::from_timestamp(1_760_000_000, 0).unwrap_or_default();
+        let mut product = Self::from_request(ProductRequest::example(), &FixedClock(created));
+        product.price_per_kg = product.calculate_price_per_kg();
+        product.last_seen = Some(created);
+        product
+    }
+
+    /// New product from a request, timestamped by `clock`
+    pub fn from_request(request: ProductRequest, clock: &dyn Clock) -> Self {
+        let now = clock.now();
+        let retailer_product_id = request
+            .retailer
+            .normalize_product_id(&request.retailer_product_id);
+        let material_confidence = Some(if request.material.is_undetermined() {
+            0.0
+        } else {
+            1.0
+        });
+
+        Self {
+            uuid: SsUuid::new(),
+            name: request.name,
+            brand: request.brand,
+            price: request.price,
+            currency: request.currency,
+            price_per_kg: CentsPerKg(0), // Calculated in update hook
+            url: request.url,
+            material: request.material,
+            diameter: request.diameter,
+            weight: request.weight,
+            retailer: request.retailer,
+            retailer_product_id,
+            color: request.color,
+            flagged_updates: Vec::new(),
+            last_seen: None,
+            availability: request.availability,
+            source: request.source,
+            provenance: HashMap::new(),
+            created_at: now,
+            updated_at: now,
+            spool_weight: request.spool_weight,
+            region: request.region.map(|r| r.trim().to_uppercase()),
+            pack_quantity: request.pack_quantity,
+            nozzle_temp: request.nozzle_temp,
+            bed_temp: request.bed_temp,
+            urls: Vec::new(),
+            material_confidence,
+            stock_quantity: request.stock_quantity,
+            list_price: request.list_price,
+            tags: request.tags,
+            shipping: request.shipping,
+            spool_type: request.spool_type,
+        }
+    }
+}
+
+/// Partial update to a product; unset fields are left unchanged
+#[derive(Clone, Default, Deserialize, ToSchema)]
+pub struct ProductPatch {
+    pub name: Option<String>,
+    pub brand: Option<String>,
+    pub price: Option<Cents>,
+    pub url: Option<String>,
+    pub material: Option<FilamentMaterial>,
+    pub diameter: Option<FilamentDiameter>,
+    pub weight: Option<Grams>,
+    pub color: Option<String>,
+    pub availability: Option<Availability>,
+}
+
+/// Product Response
+#[derive(Serialize, ToSchema)]
+#[schema(example = ProductResponse::example_json)]
+pub struct ProductResponse {
+    uuid: String,
+    name: String,
+    brand: Option<String>,
+    price: Cents,
+    currency: Currency,
+    price_per_kg: CentsPerKg,
+    url: String,
+    material: FilamentMaterial,
+    diameter: FilamentDiameter,
+    weight: Grams,
+    retailer: Retailer,
+    /// Structured form of `retailer`, telling known retailers from custom ones
+    retailer_info: RetailerInfo,
+    retailer_product_id: String,
+    color: String,
+    availability: Availability,
+    source: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    spool_weight: Option<Grams>,
+    region: Option<String>,
+    pack_quantity: Option<u16>,
+    freshness: Freshness,
+    nozzle_temp: Option<TempRange>,
+    bed_temp: Option<TempRange>,
+    /// Only computed by `ProductResponse::with_deal_badge`, as it needs a percentile lookup
+    deal_badge: Option<DealBadge>,
+    urls: Vec<String>,
+    material_confidence: f32,
+    stock_quantity: Option<u32>,
+    list_price: Option<Cents>,
+    tags: Vec<String>,
+    shipping: Option<Cents>,
+    spool_type: SpoolType,
+    /// `weight` in kilograms, for display
+    weight_kg: f32,
+    /// Price per kg including shipping, when shipping is known
+    price_per_kg_with_shipping: Option<CentsPerKg>,
+}
+
+impl From<Product> for ProductResponse {
+    fn from(product: Product) -> Self {
+        Self::from_product(product, &SystemClock)
+    }
+}
+
+impl ProductResponse {
+    /// Response for `product` including its deal badge
+    pub async fn with_deal_badge(product: Product) -> Result<Self, SurrealSocketError> {
+        let percentile = product.price_percentile().await?;
+        Ok(Self::with_percentile(product, percentile))
+    }
+
+    /// Response for `product` with the deal badge for its `price_percentile`
+    fn with_percentile(product: Product, percentile: f32) -> Self {
+        Self {
+            deal_badge: DealBadge::from_percentile(percentile),
+            ..Self::from(product)
+        }
+    }
+
+    fn example_json() -> serde_json::Value {
+        let example = Product::example();
+        let clock = FixedClock(example.updated_at);
+        serde_json::to_value(Self::from_product(example, &clock)).unwrap_or_default()
+    }
+
+    /// Response for `product`, with its freshness judged at the time given by `clock`
+    pub fn from_product(product: Product, clock: &dyn Clock) -> Self {
+        let material_confidence = product.material_confidence();
+        let price_per_kg_with_shipping = product
+            .shipping
+            .is_some()
+            .then(|| product.price_per_kg_with_shipping());
+
+        Self {
+            freshness: product.freshness(clock.now()),
+            price_per_kg_with_shipping,
+            weight_kg: product.weight.0 as f32 / 1000.0,
+            deal_badge: None,
+            uuid: product.uuid.to_uuid_string(),
+            name: product.name,
+            brand: product.brand,
+            price: product.price,
+            currency: product.currency,
+            price_per_kg: product.price_per_kg,
+            url: product.url,
+            material: product.material,
+            diameter: product.diameter,
+            weight: product.weight,
+            retailer_info: product.retailer.info(),
+            retailer: product.retailer,
+            retailer_product_id: product.retailer_product_id,
+            color: product.color,
+            availability: product.availability,
+            source: product.source,
+            created_at: product.created_at,
+            updated_at: product.updated_at,
+            spool_weight: product.spool_weight,
+            region: product.region,
+            pack_quantity: product.pack_quantity,
+            nozzle_temp: product.nozzle_temp,
+            bed_temp: product.bed_temp,
+            urls: product.urls,
+            material_confidence,
+            stock_quantity: product.stock_quantity,
+            list_price: product.list_price,
+            tags: product.tags,
+            shipping: product.shipping,
+            spool_type: product.spool_type,
+        }
+    }
+}
+
+/// Product Summary
+#[derive(Serialize, ToSchema)]
+pub struct ProductSummary {
+    uuid: String,
+    name: String,
+    price: Cents,
+    price_per_kg: CentsPerKg,
+    material: FilamentMaterial,
+    diameter: FilamentDiameter,
+    retailer: Retailer,
+}
+
+impl From<Product> for ProductSummary {
+    fn from(product: Product) -> Self {
+        Self {
+            uuid: product.uuid.to_uuid_string(),
+            name: product.name,
+            price: product.price,
+            price_per_kg: product.price_per_kg,
+            material: product.material,
+            diameter: product.diameter,
+            retailer: product.retailer,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProductResponseKind {
+    Full,
+    Summary,
+}
+
+/// Serializes `product` as either a `ProductResponse` or a `ProductSummary`
+pub fn build_response(product: &Product, kind: ProductResponseKind) -> serde_json::Value {
+    let value = match kind {
+        ProductResponseKind::Full => serde_json::to_value(ProductResponse::from(product.clone())),
+        ProductResponseKind::Summary => serde_json::to_value(ProductSummary::from(product.clone())),
+    };
+
+    value.expect("Product responses serialize to JSON")
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(try_from = "String", into = "String")]
+pub enum Retailer {
+    Amazon,
+    Other(String),
+}
+
+impl FromStr for Retailer {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "Amazon" => Self::Amazon,
+            other => Self::Other(other.to_string()),
+        })
+    }
+}
+
+impl std::fmt::Display for Retailer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Amazon => write!(f, "Amazon"),
+            Self::Other(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// Retailer as `{ "known": false, "name": "SomeShop" }`, so clients can style custom
+/// retailers differently
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RetailerInfo {
+    pub known: bool,
+    pub name: String,
+}
+
+impl Retailer {
+    pub fn info(&self) -> RetailerInfo {
+        RetailerInfo {
+            known: !matches!(self, Self::Other(_)),
+            name: self.to_string(),
+        }
+    }
+
+    /// Name for display with the region code, e.g. "Amazon (GB)", or just the name without
+    /// a region
+    pub fn display_with_region(&self, region: Option<&str>) -> String {
+        match region.map(str::trim).filter(|r| !r.is_empty()) {
+            Some(region) => format!("{} ({})", self, region.to_uppercase()),
+            None => self.to_string(),
+        }
+    }
+
+    /// Homepage of known retailers, for building search links. Amazon is the US storefront.
+    pub fn base_url(&self) -> Option<&str> {
+        match self {
+            Self::Amazon => Some("https://www.amazon.com"),
+            Self::Other(_) => None,
+        }
+    }
+
+    /// Canonical form of a product id for this retailer: trimmed, and uppercased for Amazon
+    /// ASINs, so natural key lookups don't miss on casing or stray whitespace
+    pub fn normalize_product_id(&self, id: &str) -> String {
+        match self {
+            Self::Amazon => id.trim().to_uppercase(),
+            Self::Other(_) => id.trim().to_owned(),
+        }
+    }
+
+    /// Query parameter carrying the affiliate tag, for retailers with a known scheme
+    fn affiliate_param(&self) -> Option<&'static str> {
+        match self {
+            Self::Amazon => Some("tag"),
+            Self::Other(_) => None,
+        }
+    }
+
+    /// Adds the affiliate tag to `product_url`, keeping existing query parameters (an existing
+    /// tag is replaced). URLs for retailers without a known scheme are returned unchanged.
+    pub fn affiliate_url(&self, product_url: &str, affiliate_tag: &str) -> String {
+        let Some(param) = self.affiliate_param() else {
+            return product_url.to_owned();
+        };
+
+        let (without_fragment, fragment) = match product_url.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment)),
+            None => (product_url, None),
+        };
+
+        let (base, query) = without_fragment
+            .split_once('?')
+            .unwrap_or((without_fragment, ""));
+
+        let mut params: Vec<String> = query
+            .split('&')
+            .filter(|p| !p.is_empty() && p.split('=').next() != Some(param))
+            .map(str::to_owned)
+            .collect();
+        params.push(format!("{}={}", param, affiliate_tag));
+
+        let mut url = format!("{}?{}", base, params.join("&"));
+
+        if let Some(fragment) = fragment {
+            url.push('#');
+            url.push_str(fragment);
+        }
+
+        url
+    }
+}
+
+impl From<String> for Retailer {
+    fn from(s: String) -> Self {
+        Retailer::from_str(&s).unwrap()
+    }
+}
+
+impl From<Retailer> for String {
+    fn from(p: Retailer) -> String {
+        p.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn price_jump_beyond_guard_is_flagged_not_applied() {
+        let mut product = Product::example();
+        let original = product.price;
+        let jumped = Cents(original.0 * 6);
+
+        let update = product.apply_price_update(jumped, &PriceChangeGuard::default());
+
+        assert_eq!(update, PriceUpdate::Flagged);
+        assert_eq!(product.price, original);
+        assert_eq!(product.flagged_updates.len(), 1);
+        let flagged = &product.flagged_updates[0];
+        assert_eq!(flagged.previous_price, original);
+        assert_eq!(flagged.proposed_price, jumped);
+        assert!((flagged.change_percent - 500.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn price_change_within_guard_is_applied() {
+        let mut product = Product::example();
+        let update = product.apply_price_update(Cents(1999), &PriceChangeGuard::default());
+
+        assert_eq!(update, PriceUpdate::Applied);
+        assert_eq!(product.price, Cents(1999));
+        assert_eq!(product.price_per_kg, CentsPerKg(1999));
+        assert!(product.flagged_updates.is_empty());
+    }
+
+    #[test]
+    fn samples_by_weight_or_whole_word() {
+        let spool = Product::example();
+        assert!(!spool.is_sample());
+
+        let light = Product {
+            weight: Grams(50),
+            ..Product::example()
+        };
+        assert!(light.is_sample());
+
+        let named = Product {
+            name: "PLA Sample Pack (5 colours)".to_owned(),
+            ..Product::example()
+        };
+        assert!(named.is_sample());
+
+        let trial = Product {
+            name: "PETG trial-size spool".to_owned(),
+            ..Product::example()
+        };
+        assert!(trial.is_sample());
+
+        let industrial = Product {
+            name: "Industrial PETG 1kg".to_owned(),
+            ..Product::example()
+        };
+        assert!(!industrial.is_sample());
+    }
+
+    #[test]
+    fn price_drop_guarded_like_jump() {
+        let mut product = Product::example();
+        let update =
+            product.apply_price_update(Cents(product.price.0 / 10), &PriceChangeGuard::default());
+
+        assert_eq!(update, PriceUpdate::Flagged);
+    }
+
+    #[test]
+    fn percentile_rank_spans_cheapest_to_priciest() {
+        let prices = [
+            CentsPerKg(1500),
+            CentsPerKg(2000),
+            CentsPerKg(2500),
+            CentsPerKg(3000),
+        ];
+
+        assert!(percentile_rank(&prices, CentsPerKg(1000)) < 0.05);
+        assert!(percentile_rank(&prices, CentsPerKg(4000)) > 0.95);
+        assert_eq!(percentile_rank(&prices, CentsPerKg(2000)), 0.375);
+        assert_eq!(percentile_rank(&[], CentsPerKg(2000)), 0.5);
+    }
+
+    #[test]
+    fn unspecified_and_unknown_round_trip() {
+        for (material, text) in [
+            (FilamentMaterial::Unspecified, "Unspecified"),
+            (FilamentMaterial::Unknown, "Unknown"),
+        ] {
+            assert_eq!(material.to_string(), text);
+            assert_eq!(FilamentMaterial::from_str(text), Ok(material.clone()));
+
+            let json = serde_json::to_value(&material).unwrap();
+            assert_eq!(json, serde_json::json!(text));
+            assert_eq!(
+                serde_json::from_value::<FilamentMaterial>(json).unwrap(),
+                material
+            );
+        }
+    }
+
+    #[test]
+    fn estimated_prints_from_net_weight() {
+        let spool = Product::example();
+        assert_eq!(spool.estimated_prints(25), Some(40));
+        assert_eq!(spool.estimated_prints(0), None);
+
+        let empty = Product {
+            weight: Grams(0),
+            ..Product::example()
+        };
+        assert_eq!(empty.estimated_prints(25), None);
+    }
+
+    #[test]
+    fn canonical_json_sorts_keys() {
+        let json = Product::example().to_canonical_json();
+
+        assert!(json.starts_with(r#"{"availability":"#));
+        assert!(json.contains(r#""bed_temp":{"max":60,"min":50}"#));
+
+        let positions: Vec<usize> = [
+            "\"brand\":",
+            "\"color\":",
+            "\"name\":",
+            "\"uuid\":",
+            "\"weight\":",
+        ]
+        .iter()
+        .map(|key| json.find(key).unwrap())
+        .collect();
+        assert!(positions.is_sorted());
+    }
+
+    fn priced(material: FilamentMaterial, cents_per_kg: u32) -> Product {
+        Product {
+            material,
+            price_per_kg: CentsPerKg(cents_per_kg),
+            ..Product::example()
+        }
+    }
+
+    #[test]
+    fn cheapest_per_material_of_a_brand() {
+        let products = vec![
+            priced(FilamentMaterial::PLA, 2199),
+            priced(FilamentMaterial::PETG, 2599),
+            priced(FilamentMaterial::PLA, 1899),
+            priced(FilamentMaterial::ABS, 2099),
+            priced(FilamentMaterial::PETG, 2799),
+        ];
+
+        let cheapest = cheapest_by(products, |p| Some(p.material.clone()));
+
+        assert_eq!(cheapest.len(), 3);
+        assert_eq!(
+            cheapest[&FilamentMaterial::PLA].price_per_kg,
+            CentsPerKg(1899)
+        );
+        assert_eq!(
+            cheapest[&FilamentMaterial::PETG].price_per_kg,
+            CentsPerKg(2599)
+        );
+        assert_eq!(
+            cheapest[&FilamentMaterial::ABS].price_per_kg,
+            CentsPerKg(2099)
+        );
+    }
+
+    #[test]
+    fn dollars_round_to_nearest_cent() {
+        assert_eq!(Cents::from_dollars(19.95), Some(Cents(1995)));
+        assert_eq!(Cents::from_dollars(0.1 + 0.2), Some(Cents(30)));
+        assert_eq!(Cents::from_dollars(9999.99), Some(Cents(999999)));
+        assert_eq!(Cents::from_dollars(1.005), Some(Cents(101)));
+        assert_eq!(Cents::from_dollars(-1.0), None);
+        assert_eq!(Cents::from_dollars(f64::NAN), None);
+    }
+
+    #[test]
+    fn update_hook_recomputes_price_per_kg_for_this_record() {
+        let product = Product {
+            price: Cents(2500),
+            weight: Grams(500),
+            price_per_kg: CentsPerKg(0),
+            ..Product::example()
+        };
+
+        let query = product.post_update_hook_query().unwrap();
+
+        assert!(
+            query.contains("UPDATE products SET price_per_kg = 5000, updated_at = $updated_at")
+        );
+        assert!(query.contains(&format!(
+            "WHERE {} = {};",
+            Product::UUID_FIELD,
+            serde_json::to_string(&product.uuid()).unwrap()
+        )));
+    }
+
+    #[test]
+    fn affiliate_urls_by_retailer() {
+        let amazon = Retailer::Amazon;
+        assert_eq!(
+            amazon.affiliate_url("https://www.amazon.com/dp/B00J0GMMP6", "fs-20"),
+            "https://www.amazon.com/dp/B00J0GMMP6?tag=fs-20"
+        );
+        assert_eq!(
+            amazon.affiliate_url(
+                "https://www.amazon.com/dp/B00J0GMMP6?th=1&tag=old-20#reviews",
+                "fs-20"
+            ),
+            "https://www.amazon.com/dp/B00J0GMMP6?th=1&tag=fs-20#reviews"
+        );
+
+        let unknown = Retailer::Other("SomeShop".to_owned());
+        assert_eq!(
+            unknown.affiliate_url("https://someshop.example/pla?ref=x", "fs-20"),
+            "https://someshop.example/pla?ref=x"
+        );
+    }
+
+    #[test]
+    fn savings_against_material_average() {
+        let below = savings_vs_average(CentsPerKg(1800), Some(2400.0)).unwrap();
+        assert!((below - 0.25).abs() < 1e-6);
+
+        let above = savings_vs_average(CentsPerKg(3000), Some(2400.0)).unwrap();
+        assert!(above < 0.0);
+
+        assert_eq!(savings_vs_average(CentsPerKg(1800), None), None);
+        assert_eq!(savings_vs_average(CentsPerKg(1800), Some(0.0)), None);
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct FlatDiameter {
+        #[serde(with = "diameter_as_mm")]
+        diameter: FilamentDiameter,
+    }
+
+    #[test]
+    fn diameter_as_mm_round_trips() {
+        let flat = FlatDiameter {
+            diameter: FilamentDiameter::D175,
+        };
+
+        let json = serde_json::to_string(&flat).unwrap();
+        assert_eq!(json, r#"{"diameter":"1.75"}"#);
+        assert_eq!(serde_json::from_str::<FlatDiameter>(&json).unwrap(), flat);
+
+        assert!(serde_json::from_str::<FlatDiameter>(r#"{"diameter":"thick"}"#).is_err());
+    }
+
+    #[test]
+    fn amounts_parse_in_their_currency() {
+        assert_eq!(
+            Cents::from_amount_str("$19.95", &Currency::USD, None),
+            Ok(Cents(1995))
+        );
+        assert_eq!(
+            Cents::from_amount_str("1995", &Currency::USD, None),
+            Ok(Cents(199500))
+        );
+        assert_eq!(
+            Cents::from_amount_str("1995", &Currency::JPY, None),
+            Ok(Cents(1995))
+        );
+        assert_eq!(
+            Cents::from_amount_str("¥1,995", &Currency::JPY, None),
+            Ok(Cents(1995))
+        );
+        assert!(Cents::from_amount_str("19.95", &Currency::JPY, None).is_err());
+    }
+
+    #[test]
+    fn amounts_format_in_their_currency() {
+        assert_eq!(Cents(1995).price_string(&Currency::USD), "$19.95");
+        assert_eq!(Cents(1995).price_string(&Currency::JPY), "1995 JPY");
+        assert_eq!(CentsPerKg(2199).price_string(&Currency::USD), "$21.99/kg");
+        assert_eq!(CentsPerKg(2199).price_string(&Currency::JPY), "2199 JPY/kg");
+        assert_eq!(
+            Cents::from_amount(1995.0, &Currency::JPY),
+            Some(Cents(1995))
+        );
+    }
+
+    #[test]
+    fn created_at_comes_from_clock() {
+        let frozen = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let request: ProductRequest =
+            serde_json::from_value(ProductRequest::example_json()).unwrap();
+        let product = Product::from_request(request, &crate::clock::FixedClock(frozen));
+
+        assert_eq!(product.created_at, frozen);
+        assert_eq!(product.updated_at, frozen);
+    }
+
+    #[test]
+    fn touch_outcomes() {
+        let guard = PriceChangeGuard::default();
+        let now = DateTime::from_timestamp(1_760_100_000, 0).unwrap();
+        let stored = Product::example();
+
+        let (inserted, result) = stored.plan_touch(None, &guard, now);
+        assert_eq!(result, TouchResult::Inserted);
+        assert_eq!(inserted.last_seen, Some(now));
+
+        let (touched, result) = stored.plan_touch(Some(stored.clone()), &guard, now);
+        assert_eq!(result, TouchResult::Touched);
+        assert_eq!(touched.last_seen, Some(now));
+
+        let mut scraped = stored.clone();
+        scraped.price = Cents(1899);
+        let (repriced, result) = scraped.plan_touch(Some(stored.clone()), &guard, now);
+        assert_eq!(result, TouchResult::Updated);
+        assert_eq!(repriced.price, Cents(1899));
+
+        let mut scraped = stored.clone();
+        scraped.source = Some("keepa".to_owned());
+        let (resourced, result) = scraped.plan_touch(Some(stored.clone()), &guard, now);
+        assert_eq!(result, TouchResult::Updated);
+        assert_eq!(resourced.source.as_deref(), Some("keepa"));
+
+        let mut scraped = stored.clone();
+        scraped.source = None;
+        let (touched, result) = scraped.plan_touch(Some(stored.clone()), &guard, now);
+        assert_eq!(result, TouchResult::Touched);
+        assert_eq!(touched.source, stored.source);
+
+        let mut scraped = stored.clone();
+        scraped.price = Cents(stored.price.0 * 6);
+        let (jumped, result) = scraped.plan_touch(Some(stored.clone()), &guard, now);
+        assert_eq!(result, TouchResult::Flagged);
+        assert_eq!(jumped.price, stored.price);
+        assert_eq!(jumped.flagged_updates.len(), 1);
+    }
+
+    #[test]
+    fn color_facet_counts_normalized_colors() {
+        let colors = [
+            "Black",
+            "galaxy  black",
+            "black ",
+            "Galaxy Black",
+            "Red",
+            "",
+            "  ",
+        ];
+
+        let counts = color_counts(colors.map(str::to_owned));
+
+        assert_eq!(
+            counts,
+            vec![
+                ("Black".to_owned(), 2),
+                ("Galaxy Black".to_owned(), 2),
+                ("Red".to_owned(), 1),
+            ]
+        );
+    }
+
+    fn field_names(value: &serde_json::Value) -> Vec<String> {
+        let mut names: Vec<String> = value.as_object().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn response_fields_differ_by_kind() {
+        let product = Product::example();
+
+        let summary = field_names(&build_response(&product, ProductResponseKind::Summary));
+        let full = field_names(&build_response(&product, ProductResponseKind::Full));
+
+        assert_eq!(
+            summary,
+            [
+                "diameter",
+                "material",
+                "name",
+                "price",
+                "price_per_kg",
+                "retailer",
+                "uuid"
+            ]
+        );
+        assert!(summary.iter().all(|name| full.contains(name)));
+        assert!(full.len() > summary.len());
+        assert!(full.contains(&"url".to_owned()));
+    }
+
+    #[test]
+    fn scrape_from_another_source_takes_over() {
+        let guard = PriceChangeGuard::default();
+        let now = DateTime::from_timestamp(1_760_100_000, 0).unwrap();
+        let stored = Product::example();
+
+        let scraped = Product {
+            source: Some("price-api".to_owned()),
+            ..stored.clone()
+        };
+        let (touched, result) = scraped.plan_touch(Some(stored.clone()), &guard, now);
+        assert_eq!(result, TouchResult::Updated);
+        assert_eq!(touched.source.as_deref(), Some("price-api"));
+
+        let anonymous = Product {
+            source: None,
+            ..stored.clone()
+        };
+        let (touched, _) = anonymous.plan_touch(Some(stored), &guard, now);
+        assert_eq!(touched.source.as_deref(), Some("amazon-scraper"));
+    }
+
+    #[test]
+    fn patch_records_provenance_per_field() {
+        let mut product = Product::example();
+        product.apply_patch(
+            ProductPatch {
+                name: Some("Hatchbox PLA Black".to_owned()),
+                color: Some("Black".to_owned()),
+                ..Default::default()
+            },
+            "catalog-import",
+        );
+
+        product.apply_patch(
+            ProductPatch {
+                price: Some(Cents(1999)),
+                ..Default::default()
+            },
+            "scraperA",
+        );
+
+        assert_eq!(product.price, Cents(1999));
+        assert_eq!(product.price_per_kg, CentsPerKg(1999));
+        assert_eq!(product.provenance["price"], "scraperA");
+        assert_eq!(product.provenance["name"], "catalog-import");
+        assert_eq!(product.provenance["color"], "catalog-import");
+        assert!(!product.provenance.contains_key("weight"));
+        assert_eq!(product.source.as_deref(), Some("scraperA"));
+    }
+
+    #[test]
+    fn get_or_insert_looks_up_the_natural_key() {
+        let product = Product::example();
+        let lookup =
+            |retailer: &Retailer, id: &str| Product::find_by_retailer_id_statement(retailer, id);
+
+        let (query, bindings) = lookup(&product.retailer, &product.retailer_product_id);
+        assert!(query.contains("retailer = $retailer"));
+        assert!(query.contains("retailer_product_id = $retailer_product_id"));
+        assert_eq!(
+            bindings,
+            [
+                ("retailer", "Amazon".to_owned()),
+                ("retailer_product_id", product.retailer_product_id.clone()),
+            ]
+        );
+
+        let rescraped = format!(" {} ", product.retailer_product_id.to_lowercase());
+        assert_eq!(lookup(&Retailer::Amazon, &rescraped).1, bindings);
+
+        assert_ne!(lookup(&Retailer::Amazon, "B000000000").1, bindings);
+        assert_ne!(
+            lookup(
+                &Retailer::Other("SomeShop".to_owned()),
+                &product.retailer_product_id
+            )
+            .1,
+            bindings
+        );
+    }
+
+    #[test]
+    fn other_material_round_trips_losslessly() {
+        for name in ["PLA Wood", "  carbon fibre PETG ", "pla"] {
+            let material = FilamentMaterial::Other(name.to_owned());
+
+            let stored = String::from(material.clone());
+            assert_eq!(stored, name);
+            assert_eq!(FilamentMaterial::from(stored), material);
+
+            let json = serde_json::to_value(&material).unwrap();
+            assert_eq!(
+                serde_json::from_value::<FilamentMaterial>(json).unwrap(),
+                material
+            );
+        }
+
+        assert_eq!(
+            FilamentMaterial::from("PLA".to_owned()),
+            FilamentMaterial::PLA
+        );
+    }
+
+    #[test]
+    fn currency_relabel_and_convert_statements() {
+        let (relabel, rate) = Product::reassign_currency_statement(false, Some(150.0)).unwrap();
+        assert!(
+            relabel.contains("UPDATE products SET currency = $to, updated_at = $updated_at WHERE")
+        );
+        assert!(!relabel.contains("price"));
+        assert!(!relabel.contains("product_events"));
+        assert_eq!(rate, 1.0);
+
+        let (convert, rate) = Product::reassign_currency_statement(true, Some(150.0)).unwrap();
+        assert!(convert.contains("price = <int> math::round(price * $rate)"));
+        assert!(convert.contains("price_per_kg = <int> math::round(price_per_kg * $rate)"));
+        assert!(convert.contains("THEN <int> math::round(list_price * $rate) END"));
+        assert!(convert.contains("THEN <int> math::round(shipping * $rate) END"));
+        assert!(convert.contains("previous_price: <int> math::round($f.previous_price * $rate)"));
+        assert!(convert.contains("proposed_price: <int> math::round($f.proposed_price * $rate)"));
+        assert!(convert.contains(
+            "UPDATE product_events SET event.previous = <int> math::round(event.previous * $rate)"
+        ));
+        assert!(convert.contains("product_uuid IN $moved"));
+        assert_eq!(rate, 150.0);
+
+        assert!(Product::reassign_currency_statement(true, None).is_err());
+        assert!(Product::reassign_currency_statement(true, Some(0.0)).is_err());
+        assert!(Product::reassign_currency_statement(true, Some(f32::NAN)).is_err());
+    }
+
+    #[test]
+    fn request_weight_in_grams_or_kilograms() {
+        let with_weight = |weight: serde_json::Value| {
+            let mut json = ProductRequest::example_json();
+            json["weight"] = weight;
+            serde_json::from_value::<ProductRequest>(json).map(|r| r.weight)
+        };
+
+        assert_eq!(with_weight(serde_json::json!(1000)).unwrap(), Grams(1000));
+        assert_eq!(with_weight(serde_json::json!(1.0)).unwrap(), Grams(1000));
+        assert_eq!(with_weight(serde_json::json!(0.75)).unwrap(), Grams(750));
+        assert!(with_weight(serde_json::json!(100_000)).is_err());
+        assert!(with_weight(serde_json::json!(-1.0)).is_err());
+    }
+
+    #[test]
+    fn timestamps_stored_as_comparable_millis() {
+        let cutoff = DateTime::from_timestamp(1_760_000_500, 0).unwrap();
+        let stamped = |secs: i64| {
+            let at = DateTime::from_timestamp(secs, 0).unwrap();
+            serde_json::to_value(Product {
+                updated_at: at,
+                ..Product::example()
+            })
+            .unwrap()["updated_at"]
+                .as_i64()
+                .unwrap()
+        };
+
+        let (before, after) = (stamped(1_760_000_000), stamped(1_760_001_000));
+        assert_eq!(before, 1_760_000_000_000);
+        assert!(before < cutoff.timestamp_millis());
+        assert!(after > cutoff.timestamp_millis());
+
+        let mut legacy = serde_json::to_value(Product::example()).unwrap();
+        legacy.as_object_mut().unwrap().remove("updated_at");
+        let legacy: Product = serde_json::from_value(legacy).unwrap();
+        assert_eq!(legacy.updated_at, DateTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn grams_subtraction_saturates_or_checks() {
+        assert_eq!(Grams(1000) - Grams(245), Grams(755));
+        assert_eq!(Grams(200) - Grams(245), Grams(0));
+
+        assert_eq!(Grams(1000).checked_sub(Grams(245)), Some(Grams(755)));
+        assert_eq!(Grams(200).checked_sub(Grams(245)), None);
+    }
+
+    #[test]
+    fn price_per_kg_from_price_and_weight() {
+        assert_eq!(
+            CentsPerKg::from((Cents(2199), Grams(1000))),
+            CentsPerKg(2199)
+        );
+        assert_eq!(
+            CentsPerKg::from((Cents(1299), Grams(500))),
+            CentsPerKg(2598)
+        );
+        assert_eq!(
+            CentsPerKg::from((Cents(5999), Grams(3000))),
+            CentsPerKg(2000)
+        );
+        assert_eq!(
+            CentsPerKg::from((Cents(999), Grams(0))),
+            CentsPerKg(u32::MAX)
+        );
+
+        assert_eq!(CentsPerKg(2598).price_string(&Currency::USD), "$25.98/kg");
+        assert_eq!(CentsPerKg(2598).amount_string(&Currency::USD), "25.98");
+    }
+
+    #[test]
+    fn with_retailer_changes_only_the_retailer() {
+        let product = Product::example();
+        let moved = product.with_retailer(Retailer::Other("SomeShop".to_owned()));
+
+        assert_eq!(moved.retailer, Retailer::Other("SomeShop".to_owned()));
+        assert!(
+            Product {
+                retailer: Retailer::Amazon,
+                ..moved
+            } == product
+        );
+    }
+
+    #[test]
+    fn near_standard_diameters_snap() {
+        assert_eq!(
+            FilamentDiameter::Other(176).snap_to_standard(3),
+            FilamentDiameter::D175
+        );
+        assert_eq!(
+            FilamentDiameter::Other(282).snap_to_standard(3),
+            FilamentDiameter::D285
+        );
+        assert_eq!(
+            FilamentDiameter::Other(300).snap_to_standard(3),
+            FilamentDiameter::Other(300)
+        );
+        assert_eq!(
+            FilamentDiameter::Other(179).snap_to_standard(3),
+            FilamentDiameter::Other(179)
+        );
+        assert_eq!(
+            FilamentDiameter::D285.snap_to_standard(200),
+            FilamentDiameter::D285
+        );
+    }
+
+    #[test]
+    fn retailer_info_tells_known_from_custom() {
+        assert_eq!(
+            serde_json::to_value(Retailer::Amazon.info()).unwrap(),
+            serde_json::json!({ "known": true, "name": "Amazon" })
+        );
+        assert_eq!(
+            serde_json::to_value(Retailer::Other("SomeShop".to_owned()).info()).unwrap(),
+            serde_json::json!({ "known": false, "name": "SomeShop" })
+        );
+
+        let response = build_response(&Product::example(), ProductResponseKind::Full);
+        assert_eq!(response["retailer"], "Amazon");
+        assert_eq!(response["retailer_info"]["known"], true);
+    }
+
+    #[test]
+    fn shipping_weight_adds_spool_and_packaging() {
+        let product = Product::example();
+        assert_eq!(product.shipping_weight(150), Grams(1395));
+
+        let unknown_spool = Product {
+            spool_weight: None,
+            ..Product::example()
+        };
+        assert_eq!(
+            unknown_spool.shipping_weight(150),
+            Grams(1000 + DEFAULT_SPOOL_WEIGHT.0 + 150)
+        );
+    }
+
+    #[test]
+    fn dollar_strings_with_grouping_separators() {
+        assert_eq!(Cents::from_dollars_str("1,299.00", None), Ok(Cents(129900)));
+        assert_eq!(
+            Cents::from_dollars_str("1.299,00", Some(DecimalSeparator::Comma)),
+            Ok(Cents(129900))
+        );
+        assert_eq!(Cents::from_dollars_str("1.299,00", None), Ok(Cents(129900)));
+        assert_eq!(Cents::from_dollars_str("1299", None), Ok(Cents(129900)));
+        assert_eq!(Cents::from_dollars_str("$19.9", None), Ok(Cents(1990)));
+        assert_eq!(
+            Cents::from_dollars_str("1,299", Some(DecimalSeparator::Point)),
+            Ok(Cents(129900))
+        );
+
+        assert!(Cents::from_dollars_str("1,299", None).is_err());
+        assert!(Cents::from_dollars_str("12,99,000", None).is_err());
+        assert!(Cents::from_dollars_str("19.999", Some(DecimalSeparator::Point)).is_err());
+    }
+
+    #[test]
+    fn missing_price_per_kg_defaults_then_recomputes() {
+        let mut json = serde_json::to_value(Product::example()).unwrap();
+        json.as_object_mut().unwrap().remove("price_per_kg");
+
+        let mut product: Product = serde_json::from_value(json).unwrap();
+        assert_eq!(product.price_per_kg, CentsPerKg(0));
+
+        product.price_per_kg = product.calculate_price_per_kg();
+        assert_eq!(product.price_per_kg, CentsPerKg(2199));
+    }
+
+    #[test]
+    fn food_safe_classification() {
+        use FilamentMaterial::*;
+
+        for material in [PLA, PETG, PCTG] {
+            assert!(material.food_safe_capable(), "{material}");
+        }
+        for material in [
+            PLAPlus,
+            ABS,
+            ASA,
+            PC,
+            TPU,
+            Nylon,
+            Unknown,
+            Other("PP".to_owned()),
+        ] {
+            assert!(!material.food_safe_capable(), "{material}");
+            assert!(!material.food_safety_note().is_empty());
+        }
+    }
+
+    #[test]
+    fn freshness_boundaries() {
+        let product = Product::example();
+        let after = |age: Duration| product.freshness(product.updated_at + age);
+        let just_under = |days: i64| Duration::days(days) - Duration::milliseconds(1);
+
+        assert_eq!(after(-Duration::hours(1)), Freshness::New);
+        assert_eq!(after(Duration::zero()), Freshness::New);
+        assert_eq!(after(just_under(1)), Freshness::New);
+        assert_eq!(after(Duration::days(1)), Freshness::Recent);
+        assert_eq!(after(just_under(7)), Freshness::Recent);
+        assert_eq!(after(Duration::days(7)), Freshness::Aging);
+        assert_eq!(after(just_under(30)), Freshness::Aging);
+        assert_eq!(after(Duration::days(30)), Freshness::Stale);
+    }
+
+    #[test]
+    fn temp_range_validation_display_and_containment() {
+        let range = TempRange::new(Celsius(190), Celsius(220)).unwrap();
+
+        assert_eq!(range.to_string(), "190–220°C");
+        assert!(range.contains(Celsius(190)));
+        assert!(range.contains(Celsius(205)));
+        assert!(range.contains(Celsius(220)));
+        assert!(!range.contains(Celsius(189)));
+        assert!(!range.contains(Celsius(221)));
+
+        assert_eq!(
+            TempRange::new(Celsius(200), Celsius(200))
+                .unwrap()
+                .to_string(),
+            "200°C"
+        );
+        assert!(TempRange::new(Celsius(230), Celsius(200)).is_err());
+        assert!(serde_json::from_str::<TempRange>(r#"{"min":230,"max":200}"#).is_err());
+        assert_eq!(
+            serde_json::from_str::<TempRange>(r#"{"min":190,"max":220}"#).unwrap(),
+            range
+        );
+    }
+
+    #[test]
+    fn example_passes_consistency_checks() {
+        let example = Product::example();
+
+        assert!(example.review_issues().is_empty());
+        assert!(example.price_per_kg_plausible());
+        assert_eq!(example.price_per_kg, example.calculate_price_per_kg());
+        assert_eq!(example.diameter_matches_name(), Some(true));
+        assert_ne!(example.brand_material_plausible(), Some(false));
+        assert!(example.is_public_visible_with(&crate::quality::VisibilityConfig::default()));
+
+        let request: ProductRequest =
+            serde_json::from_value(ProductRequest::example_json()).unwrap();
+        assert_eq!(request.name, example.name);
+    }
+
+    #[test]
+    fn coalesce_fills_empty_fields_without_overwriting() {
+        let nozzle = TempRange::new(Celsius(190), Celsius(220)).ok();
+        let bed = TempRange::new(Celsius(50), Celsius(60)).ok();
+
+        let mut priced = Product {
+            price: Cents(1999),
+            nozzle_temp: None,
+            bed_temp: None,
+            ..Product::example()
+        };
+        let with_temps = Product {
+            price: Cents(0),
+            nozzle_temp: nozzle,
+            bed_temp: bed,
+            updated_at: priced.updated_at + Duration::hours(1),
+            ..Product::example()
+        };
+
+        priced.coalesce_from(&with_temps);
+
+        assert_eq!(priced.price, Cents(1999));
+        assert_eq!(priced.price_per_kg, CentsPerKg(1999));
+        assert_eq!(priced.nozzle_temp, nozzle);
+        assert_eq!(priced.bed_temp, bed);
+    }
+
+    #[test]
+    fn coalesce_conflicts_go_to_the_newer_record() {
+        let mut product = Product::example();
+        let older = Product {
+            color: "Red".to_owned(),
+            updated_at: product.updated_at - Duration::hours(1),
+            ..Product::example()
+        };
+        let newer = Product {
+            color: "Blue".to_owned(),
+            updated_at: product.updated_at + Duration::hours(1),
+            ..Product::example()
+        };
+
+        product.coalesce_from(&older);
+        assert_eq!(product.color, "Black");
+
+        product.coalesce_from(&newer);
+        assert_eq!(product.color, "Blue");
+    }
+
+    #[test]
+    fn label_safe_flags_implausible_diameters() {
+        assert_eq!(FilamentDiameter::D175.label_safe(), "1.75 mm");
+        assert_eq!(FilamentDiameter::Other(300).label_safe(), "3.00 mm");
+        assert_eq!(FilamentDiameter::Other(1000).label_safe(), "10.00 mm");
+        assert_eq!(
+            FilamentDiameter::Other(1759).label_safe(),
+            "invalid (17.59 mm)"
+        );
+        assert_eq!(
+            FilamentDiameter::Other(1001).label_safe(),
+            "invalid (10.01 mm)"
+        );
+    }
+
+    #[test]
+    fn amazon_product_ids_normalize_consistently() {
+        let amazon = Retailer::Amazon;
+
+        assert_eq!(amazon.normalize_product_id(" b0abc123xy "), "B0ABC123XY");
+        assert_eq!(
+            amazon.normalize_product_id(" b0abc123xy "),
+            amazon.normalize_product_id("B0ABC123XY")
+        );
+        assert_eq!(
+            Retailer::Other("Shop".to_owned()).normalize_product_id(" hb-pla "),
+            "hb-pla"
+        );
+
+        let request = ProductRequest {
+            retailer_product_id: " b0abc123xy ".to_owned(),
+            ..ProductRequest::example()
+        };
+        let product = Product::from_request(request, &FixedClock(Utc::now()));
+        assert_eq!(product.retailer_product_id, "B0ABC123XY");
+    }
+
+    #[test]
+    fn deal_badge_for_bottom_and_top_quartile() {
+        let others: Vec<CentsPerKg> = (15..=30).map(|p| CentsPerKg(p * 100)).collect();
+        let response = |cents: u32| {
+            let product = Product {
+                price: Cents(cents),
+                price_per_kg: CentsPerKg(cents),
+                ..Product::example()
+            };
+            let percentile = percentile_rank(&others, product.price_per_kg);
+            ProductResponse::with_percentile(product, percentile)
+        };
+
+        assert_eq!(response(1450).deal_badge, Some(DealBadge::Great));
+        assert_eq!(response(2950).deal_badge, None);
+        assert_eq!(DealBadge::from_percentile(0.3), Some(DealBadge::Good));
+        assert_eq!(DealBadge::from_percentile(0.45), Some(DealBadge::Fair));
+        assert_eq!(ProductResponse::from(Product::example()).deal_badge, None);
+    }
+
+    #[test]
+    fn add_url_skips_tracking_variants() {
+        let mut product = Product {
+            urls: Vec::new(),
+            ..Product::example()
+        };
+
+        assert!(
+            !product.add_url("https://amazon.com/dp/B00J0GMMP6/?tag=deals-20#reviews".to_owned())
+        );
+        assert!(!product.add_url("   ".to_owned()));
+
+        assert!(
+            product.add_url("https://www.amazon.com/gp/product/B00J0GMMP6?utm_source=x".to_owned())
+        );
+        assert!(
+            !product.add_url("https://amazon.com/gp/product/B00J0GMMP6/?fbclid=abc".to_owned())
+        );
+
+        assert_eq!(
+            product.urls,
+            vec!["https://amazon.com/gp/product/B00J0GMMP6".to_owned()]
+        );
+    }
+
+    #[test]
+    fn legacy_records_get_confidence_from_material() {
+        let mut legacy = serde_json::to_value(Product::example()).unwrap();
+        legacy
+            .as_object_mut()
+            .unwrap()
+            .remove("material_confidence");
+
+        let determined: Product = serde_json::from_value(legacy.clone()).unwrap();
+        assert_eq!(determined.material_confidence, None);
+        assert_eq!(determined.material_confidence(), 1.0);
+
+        legacy["material"] = "Unspecified".into();
+        let undetermined: Product = serde_json::from_value(legacy).unwrap();
+        assert_eq!(undetermined.material_confidence(), 0.0);
+    }
+
+    #[test]
+    fn recorded_confidence_wins() {
+        let inferred = Product {
+            material_confidence: Some(0.6),
+            ..Product::example()
+        };
+
+        assert_eq!(inferred.material_confidence(), 0.6);
+        assert_eq!(inferred.resolve_material(), (FilamentMaterial::PLA, 0.6));
+    }
+
+    #[test]
+    fn insert_statement_binds_whole_product() {
+        let product = Product::example();
+        let (query, bindings) = product.to_insert_statement();
+
+        assert_eq!(query, "CREATE products CONTENT $product");
+        assert_eq!(
+            bindings,
+            vec![(
+                "product".to_owned(),
+                serde_json::to_value(&product).unwrap()
+            )]
+        );
+
+        let serde_json::Value::Object(fields) = &bindings[0].1 else {
+            panic!("product binding should be an object");
+        };
+        assert!(fields.contains_key("uuid"));
+        assert!(fields.contains_key("price_per_kg"));
+        assert!(fields.contains_key("flagged_updates"));
+    }
+
+    #[test]
+    fn legacy_records_default_stock_quantity_to_none() {
+        let mut json = serde_json::to_value(Product::example()).unwrap();
+        json.as_object_mut().unwrap().remove("stock_quantity");
+
+        let legacy: Product = serde_json::from_value(json).unwrap();
+        assert_eq!(legacy.stock_quantity, None);
+
+        let mut json = ProductRequest::example_json();
+        json.as_object_mut().unwrap().remove("stock_quantity");
+
+        let request: ProductRequest = serde_json::from_value(json).unwrap();
+        assert_eq!(request.stock_quantity, None);
+    }
+
+    #[test]
+    fn hookless_insert_computes_price_per_kg_inline() {
+        let now = DateTime::from_timestamp(1_770_000_000, 0).unwrap();
+        let stale = Product {
+            price: Cents(2999),
+            weight: Grams(750),
+            price_per_kg: CentsPerKg(0),
+            ..Product::example()
+        };
+
+        let stored = stale.with_hook_fields(now);
+
+        assert_eq!(stored.price_per_kg, stale.calculate_price_per_kg());
+        assert_eq!(stored.price_per_kg, CentsPerKg(3999));
+        assert_eq!(stored.updated_at, now);
+        assert!(stored.uuid == stale.uuid);
+    }
+
+    #[test]
+    fn known_retailers_have_base_urls() {
+        assert_eq!(Retailer::Amazon.base_url(), Some("https://www.amazon.com"));
+        assert_eq!(Retailer::Other("AliExpress".to_owned()).base_url(), None);
+        assert_eq!(Retailer::Other(String::new()).base_url(), None);
+    }
+
+    #[test]
+    fn diameter_mm_string_for_each_variant() {
+        let with = |diameter: FilamentDiameter| Product {
+            diameter,
+            ..Product::example()
+        };
+
+        assert_eq!(with(FilamentDiameter::D175).diameter_mm_string(), "1.75");
+        assert_eq!(with(FilamentDiameter::D285).diameter_mm_string(), "2.85");
+        assert_eq!(
+            with(FilamentDiameter::Other(300)).diameter_mm_string(),
+            "3.00"
+        );
+        assert_eq!(
+            with(FilamentDiameter::Other(5)).diameter_mm_string(),
+            "0.05"
+        );
+    }
+
+    #[test]
+    fn delete_hook_removes_both_histories_of_the_product() {
+        let product = Product::example();
+        let event =
+            ProductEventRecord::new(product.uuid(), ProductEvent::BackInStock, &SystemClock);
+        let entry = AvailabilityHistory {
+            uuid: SsUuid::new(),
+            product_uuid: product.uuid(),
+            availability: Availability::InStock,
+            recorded_at: product.created_at,
+        };
+
+        let query = Product::delete_history_query();
+        assert_eq!(
+            query,
+            "DELETE product_events WHERE product_uuid = $product_uuid; \
+             DELETE availability_history WHERE product_uuid = $product_uuid;"
+        );
+
+        let uuid = serde_json::to_value(product.uuid()).unwrap();
+        assert_eq!(serde_json::to_value(&event).unwrap()["product_uuid"], uuid);
+        assert_eq!(serde_json::to_value(&entry).unwrap()["product_uuid"], uuid);
+    }
+
+    #[test]
+    fn price_per_kg_with_shipping_adds_shipping_over_net_weight() {
+        let shipped = Product {
+            price: Cents(1999),
+            shipping: Some(Cents(499)),
+            weight: Grams(750),
+            ..Product::example()
+        };
+
+        assert_eq!(shipped.effective_total(), Cents(2498));
+        assert_eq!(shipped.calculate_price_per_kg(), CentsPerKg(2665));
+        assert_eq!(shipped.price_per_kg_with_shipping(), CentsPerKg(3331));
+        assert!(shipped.price_per_kg_with_shipping() > shipped.calculate_price_per_kg());
+
+        let free = Product::example();
+        assert_eq!(
+            free.price_per_kg_with_shipping(),
+            free.calculate_price_per_kg()
+        );
+
+        let unknown = Product {
+            shipping: None,
+            ..Product::example()
+        };
+        assert_eq!(
+            unknown.price_per_kg_with_shipping(),
+            unknown.calculate_price_per_kg()
+        );
+        assert_eq!(
+            ProductResponse::from(unknown).price_per_kg_with_shipping,
+            None
+        );
+        assert_eq!(
+            ProductResponse::from(shipped).price_per_kg_with_shipping,
+            Some(CentsPerKg(3331))
+        );
+    }
+
+    #[test]
+    fn response_weight_in_kg() {
+        let weighing = |grams: u16| {
+            ProductResponse::from(Product {
+                weight: Grams(grams),
+                ..Product::example()
+            })
+            .weight_kg
+        };
+
+        assert_eq!(weighing(1000), 1.0);
+        assert_eq!(weighing(250), 0.25);
+        assert_eq!(
+            serde_json::to_value(ProductResponse::from(Product::example())).unwrap()["weight_kg"],
+            1.0
+        );
+    }
+
+    #[test]
+    fn fresh_uuid_clone_matches_on_every_other_field() {
+        let original = Product::example();
+        let copy = original.clone_with_fresh_uuid();
+
+        assert!(copy.uuid != original.uuid);
+        assert!(
+            Product {
+                uuid: original.uuid.clone(),
+                ..copy
+            } == original
+        );
+    }
+
+    #[test]
+    fn retailer_display_with_region() {
+        assert_eq!(
+            Retailer::Amazon.display_with_region(Some("uk")),
+            "Amazon (UK)"
+        );
+        assert_eq!(Retailer::Amazon.display_with_region(None), "Amazon");
+        assert_eq!(Retailer::Amazon.display_with_region(Some("  ")), "Amazon");
+    }
+
+    #[test]
+    fn co2_estimate_for_pla_and_unknown_material() {
+        assert_eq!(FilamentMaterial::PLA.co2_kg_per_kg(), Some(1.8));
+
+        let half_kg = Product {
+            weight: Grams(500),
+            ..Product::example()
+        };
+        assert!((half_kg.estimated_co2().unwrap() - 0.9).abs() < 1e-6);
+
+        let unknown = Product {
+            material: FilamentMaterial::Other("PEEK".to_owned()),
+            ..Product::example()
+        };
+        assert_eq!(unknown.material.co2_kg_per_kg(), None);
+        assert_eq!(unknown.estimated_co2(), None);
+    }
+
+    #[test]
+    fn tpu_prints_slower_than_pla() {
+        let pla = FilamentMaterial::PLA.max_print_speed_mm_s().unwrap();
+        let tpu = FilamentMaterial::TPU.max_print_speed_mm_s().unwrap();
+
+        assert!(tpu < pla);
+        assert!(FilamentMaterial::ABS.max_print_speed_mm_s().unwrap() < pla);
+        assert_eq!(FilamentMaterial::Unknown.max_print_speed_mm_s(), None);
+    }
+
+    #[test]
+    fn global_discount_lowers_price_and_price_per_kg() {
+        let product = Product {
+            price: Cents(3000),
+            weight: Grams(500),
+            price_per_kg: CentsPerKg(6000),
+            ..Product::example()
+        };
+
+        let discounted = product.with_global_discount(10);
+
+        assert_eq!(discounted.price, Cents(2700));
+        assert_eq!(discounted.price_per_kg, CentsPerKg(5400));
+        assert!(discounted.list_price == product.list_price);
+        assert_eq!(product.price, Cents(3000));
+        assert_eq!(product.with_global_discount(150).price, Cents(0));
+    }
+
+    #[test]
+    fn deduplicate_urls_collapses_tracking_duplicates() {
+        let mut product = Product {
+            urls: vec![
+                "https://www.amazon.com/gp/product/B00J0GMMP6?utm_source=x".to_owned(),
+                "https://amazon.com/gp/product/B00J0GMMP6/?fbclid=abc".to_owned(),
+                "https://amazon.com/dp/B00J0GMMP6/?tag=deals-20#reviews".to_owned(),
+                " ".to_owned(),
+            ],
+            ..Product::example()
+        };
+
+        assert!(product.deduplicate_urls());
+        assert_eq!(
+            product.urls,
+            vec!["https://amazon.com/gp/product/B00J0GMMP6".to_owned()]
+        );
+
+        assert!(!product.deduplicate_urls());
     }
 }
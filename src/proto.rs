@@ -0,0 +1,272 @@
+use chrono::{DateTime, Utc};
+use surreal_socket::dbrecord::SsUuid;
+
+use crate::product::{
+    Availability, Celsius, Cents, CentsPerKg, Currency, FilamentDiameter, FilamentMaterial, Grams,
+    Product, Retailer, SpoolType, TempRange,
+};
+
+/// Protobuf form of `ProductResponse`, for the gRPC service. Enums holding an `Other` value
+/// travel as their string form; newtypes as their inner integer.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProductMessage {
+    #[prost(string, tag = "1")]
+    pub uuid: String,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(string, optional, tag = "3")]
+    pub brand: Option<String>,
+    /// Cents
+    #[prost(uint32, tag = "4")]
+    pub price: u32,
+    #[prost(string, tag = "5")]
+    pub currency: String,
+    /// Cents per kg
+    #[prost(uint32, tag = "6")]
+    pub price_per_kg: u32,
+    #[prost(string, tag = "7")]
+    pub url: String,
+    #[prost(string, tag = "8")]
+    pub material: String,
+    /// Hundredths of a millimeter
+    #[prost(uint32, tag = "9")]
+    pub diameter: u32,
+    /// Grams
+    #[prost(uint32, tag = "10")]
+    pub weight: u32,
+    #[prost(string, tag = "11")]
+    pub retailer: String,
+    #[prost(string, tag = "12")]
+    pub retailer_product_id: String,
+    #[prost(string, tag = "13")]
+    pub color: String,
+    #[prost(enumeration = "AvailabilityMessage", tag = "14")]
+    pub availability: i32,
+    #[prost(string, optional, tag = "15")]
+    pub source: Option<String>,
+    /// Milliseconds since the epoch
+    #[prost(int64, tag = "16")]
+    pub created_at: i64,
+    /// Milliseconds since the epoch
+    #[prost(int64, tag = "17")]
+    pub updated_at: i64,
+    /// Grams
+    #[prost(uint32, optional, tag = "18")]
+    pub spool_weight: Option<u32>,
+    #[prost(string, optional, tag = "19")]
+    pub region: Option<String>,
+    #[prost(uint32, optional, tag = "20")]
+    pub pack_quantity: Option<u32>,
+    #[prost(message, optional, tag = "21")]
+    pub nozzle_temp: Option<TempRangeMessage>,
+    #[prost(message, optional, tag = "22")]
+    pub bed_temp: Option<TempRangeMessage>,
+    #[prost(string, repeated, tag = "23")]
+    pub urls: Vec<String>,
+    #[prost(float, tag = "24")]
+    pub material_confidence: f32,
+    #[prost(uint32, optional, tag = "25")]
+    pub stock_quantity: Option<u32>,
+    /// Cents
+    #[prost(uint32, optional, tag = "26")]
+    pub list_price: Option<u32>,
+    #[prost(string, repeated, tag = "27")]
+    pub tags: Vec<String>,
+    /// Cents
+    #[prost(uint32, optional, tag = "28")]
+    pub shipping: Option<u32>,
+    #[prost(string, tag = "29")]
+    pub spool_type: String,
+}
+
+/// Degrees Celsius
+#[derive(Clone, Copy, PartialEq, prost::Message)]
+pub struct TempRangeMessage {
+    #[prost(uint32, tag = "1")]
+    pub min: u32,
+    #[prost(uint32, tag = "2")]
+    pub max: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum AvailabilityMessage {
+    Unknown = 0,
+    InStock = 1,
+    LowStock = 2,
+    OutOfStock = 3,
+}
+
+impl From<Availability> for AvailabilityMessage {
+    fn from(availability: Availability) -> Self {
+        match availability {
+            Availability::Unknown => Self::Unknown,
+            Availability::InStock => Self::InStock,
+            Availability::LowStock => Self::LowStock,
+            Availability::OutOfStock => Self::OutOfStock,
+        }
+    }
+}
+
+impl From<AvailabilityMessage> for Availability {
+    fn from(availability: AvailabilityMessage) -> Self {
+        match availability {
+            AvailabilityMessage::Unknown => Self::Unknown,
+            AvailabilityMessage::InStock => Self::InStock,
+            AvailabilityMessage::LowStock => Self::LowStock,
+            AvailabilityMessage::OutOfStock => Self::OutOfStock,
+        }
+    }
+}
+
+impl From<TempRange> for TempRangeMessage {
+    fn from(range: TempRange) -> Self {
+        Self {
+            min: range.min().0.into(),
+            max: range.max().0.into(),
+        }
+    }
+}
+
+impl Product {
+    pub fn to_proto(&self) -> ProductMessage {
+        ProductMessage {
+            uuid: self.uuid.to_uuid_string(),
+            name: self.name.clone(),
+            brand: self.brand.clone(),
+            price: self.price.0,
+            currency: self.currency.to_string(),
+            price_per_kg: self.price_per_kg.0,
+            url: self.url.clone(),
+            material: self.material.to_string(),
+            diameter: u16::from(self.diameter).into(),
+            weight: self.weight.0.into(),
+            retailer: self.retailer.to_string(),
+            retailer_product_id: self.retailer_product_id.clone(),
+            color: self.color.clone(),
+            availability: AvailabilityMessage::from(self.availability).into(),
+            source: self.source.clone(),
+            created_at: self.created_at.timestamp_millis(),
+            updated_at: self.updated_at.timestamp_millis(),
+            spool_weight: self.spool_weight.map(|g| g.0.into()),
+            region: self.region.clone(),
+            pack_quantity: self.pack_quantity.map(u32::from),
+            nozzle_temp: self.nozzle_temp.map(TempRangeMessage::from),
+            bed_temp: self.bed_temp.map(TempRangeMessage::from),
+            urls: self.urls.clone(),
+            material_confidence: self.material_confidence(),
+            stock_quantity: self.stock_quantity,
+            list_price: self.list_price.map(|c| c.0),
+            tags: self.tags.clone(),
+            shipping: self.shipping.map(|c| c.0),
+            spool_type: self.spool_type.to_string(),
+        }
+    }
+
+    /// Product from a message produced by `to_proto`. Fields the message doesn't carry
+    /// (flagged updates, provenance, last seen) are left empty.
+    pub fn from_proto(message: ProductMessage) -> Result<Product, String> {
+        let narrow = |field: &str, value: u32| {
+            u16::try_from(value).map_err(|_| format!("{} {} out of range", field, value))
+        };
+
+        let timestamp = |field: &str, millis: i64| {
+            DateTime::<Utc>::from_timestamp_millis(millis)
+                .ok_or_else(|| format!("{} {} out of range", field, millis))
+        };
+
+        let temp_range = |field: &str, range: TempRangeMessage| {
+            TempRange::new(
+                Celsius(narrow(field, range.min)?),
+                Celsius(narrow(field, range.max)?),
+            )
+        };
+
+        let uuid: SsUuid<Product> = serde_json::from_value(serde_json::Value::String(message.uuid))
+            .map_err(|e| format!("Invalid uuid: {}", e))?;
+
+        let availability = AvailabilityMessage::try_from(message.availability)
+            .map_err(|_| format!("Unknown availability {}", message.availability))?;
+
+        Ok(Product {
+            uuid,
+            name: message.name,
+            brand: message.brand,
+            price: Cents(message.price),
+            currency: Currency::from(message.currency),
+            price_per_kg: CentsPerKg(message.price_per_kg),
+            url: message.url,
+            material: FilamentMaterial::from(message.material),
+            diameter: FilamentDiameter::try_from(narrow("diameter", message.diameter)?)?,
+            weight: Grams(narrow("weight", message.weight)?),
+            retailer: Retailer::from(message.retailer),
+            retailer_product_id: message.retailer_product_id,
+            color: message.color,
+            flagged_updates: Vec::new(),
+            last_seen: None,
+            availability: availability.into(),
+            source: message.source,
+            provenance: Default::default(),
+            created_at: timestamp("created_at", message.created_at)?,
+            updated_at: timestamp("updated_at", message.updated_at)?,
+            spool_weight: message
+                .spool_weight
+                .map(|g| narrow("spool_weight", g).map(Grams))
+                .transpose()?,
+            region: message.region,
+            pack_quantity: message
+                .pack_quantity
+                .map(|n| narrow("pack_quantity", n))
+                .transpose()?,
+            nozzle_temp: message
+                .nozzle_temp
+                .map(|t| temp_range("nozzle_temp", t))
+                .transpose()?,
+            bed_temp: message
+                .bed_temp
+                .map(|t| temp_range("bed_temp", t))
+                .transpose()?,
+            urls: message.urls,
+            material_confidence: Some(message.material_confidence),
+            stock_quantity: message.stock_quantity,
+            list_price: message.list_price.map(Cents),
+            tags: message.tags,
+            shipping: message.shipping.map(Cents),
+            spool_type: message.spool_type.parse().unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    #[test]
+    fn product_round_trips_through_protobuf() {
+        let product = Product::example();
+        let message = product.to_proto();
+
+        let bytes = message.encode_to_vec();
+        let decoded =
+            Product::from_proto(ProductMessage::decode(bytes.as_slice()).unwrap()).unwrap();
+
+        assert_eq!(decoded.to_proto(), message);
+        assert!(decoded.uuid == product.uuid);
+        assert_eq!(decoded.price, product.price);
+        assert_eq!(decoded.material, product.material);
+        assert_eq!(decoded.diameter, product.diameter);
+        assert_eq!(decoded.availability, product.availability);
+        assert_eq!(decoded.created_at, product.created_at);
+    }
+
+    #[test]
+    fn from_proto_rejects_out_of_range_values() {
+        let message = ProductMessage {
+            weight: u32::MAX,
+            ..Product::example().to_proto()
+        };
+
+        assert!(Product::from_proto(message).is_err());
+    }
+}
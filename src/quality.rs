@@ -0,0 +1,577 @@
+use std::ops::RangeInclusive;
+
+use serde::{Deserialize, Serialize};
+use surreal_socket::error::SurrealSocketError;
+use utoipa::ToSchema;
+
+use crate::{
+    product::{
+        Availability, CentsPerKg, Currency, FilamentDiameter, FilamentMaterial, Grams, Product,
+        ProductResponse,
+    },
+    query::ProductQuery,
+};
+
+/// Plausible price per kg for materials without their own bounds
+pub const GLOBAL_PRICE_PER_KG_BOUNDS: RangeInclusive<CentsPerKg> =
+    CentsPerKg(500)..=CentsPerKg(30000);
+
+/// Spools below this weight cost more per kg, so their plausible band is stretched upwards
+const SMALL_SPOOL_WEIGHT: Grams = Grams(500);
+
+/// Spools from this weight up cost less per kg, so their plausible band is stretched downwards
+const BULK_SPOOL_WEIGHT: Grams = Grams(3000);
+
+/// Settings for `Product::is_public_visible_with`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VisibilityConfig {
+    /// Leave out-of-stock products off the public site
+    pub hide_out_of_stock: bool,
+}
+
+impl Default for VisibilityConfig {
+    fn default() -> Self {
+        Self {
+            hide_out_of_stock: true,
+        }
+    }
+}
+
+impl VisibilityConfig {
+    /// Shows out-of-stock products when `FILAMENTSEEK_SHOW_OUT_OF_STOCK` is "true" or "1";
+    /// unset or anything else hides them
+    pub fn from_env() -> Self {
+        let show = std::env::var("FILAMENTSEEK_SHOW_OUT_OF_STOCK")
+            .is_ok_and(|v| matches!(v.trim().to_lowercase().as_str(), "true" | "1"));
+
+        Self {
+            hide_out_of_stock: !show,
+        }
+    }
+}
+
+/// Data-quality problem found by `Product::review_issues`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub enum ReviewIssue {
+    /// Price updates were held back by the price change guard
+    FlaggedUpdates,
+    ZeroWeight,
+    /// Price per kg outside the material's plausible band for the spool weight
+    ImplausiblePrice,
+    UnspecifiedMaterial,
+    /// Color is blank or not a name, e.g. a scraped SKU
+    InvalidColor,
+    /// Non-standard diameter that the product name doesn't state
+    UnexplainedDiameter,
+    /// Price implausible for a spool in its currency, e.g. yen stored as if in cents
+    CurrencyMismatch,
+}
+
+impl ReviewIssue {
+    /// Weight of the issue in `Product::review_severity`. Problems that make a listing
+    /// misleading to buyers weigh most.
+    pub fn severity(&self) -> u32 {
+        match self {
+            Self::ImplausiblePrice | Self::ZeroWeight | Self::CurrencyMismatch => 3,
+            Self::FlaggedUpdates | Self::UnspecifiedMaterial => 2,
+            Self::InvalidColor | Self::UnexplainedDiameter => 1,
+        }
+    }
+}
+
+/// Product with its review issues, for the operator review queue
+#[derive(Serialize, ToSchema)]
+pub struct ReviewResponse {
+    #[serde(flatten)]
+    product: ProductResponse,
+    needs_review: bool,
+    severity: u32,
+    issues: Vec<ReviewIssue>,
+}
+
+impl From<Product> for ReviewResponse {
+    fn from(product: Product) -> Self {
+        let issues = product.review_issues();
+
+        Self {
+            needs_review: !issues.is_empty(),
+            severity: issues.iter().map(ReviewIssue::severity).sum(),
+            issues,
+            product: ProductResponse::from(product),
+        }
+    }
+}
+
+/// Spool prices, in cents, outside which a cent-based price was likely stored in the wrong
+/// unit: below a dollar, or above a thousand
+const PLAUSIBLE_SPOOL_PRICE_CENTS: RangeInclusive<u32> = 100..=100_000;
+
+/// Spool prices, in whole yen, outside which a yen price was likely stored in the wrong unit
+const PLAUSIBLE_SPOOL_PRICE_JPY: RangeInclusive<u32> = 300..=150_000;
+
+/// Materials each well-known brand sells, by lowercase brand name. Deliberately
+/// conservative: a material missing here is one the brand isn't known to make.
+const KNOWN_BRAND_MATERIALS: &[(&str, &[FilamentMaterial])] = &[
+    (
+        "polymaker",
+        &[
+            FilamentMaterial::PLA,
+            FilamentMaterial::PLAPlus,
+            FilamentMaterial::PETG,
+            FilamentMaterial::ABS,
+            FilamentMaterial::ASA,
+            FilamentMaterial::TPU,
+            FilamentMaterial::Nylon,
+            FilamentMaterial::PC,
+        ],
+    ),
+    (
+        "prusament",
+        &[
+            FilamentMaterial::PLA,
+            FilamentMaterial::PETG,
+            FilamentMaterial::ASA,
+            FilamentMaterial::PC,
+            FilamentMaterial::Nylon,
+        ],
+    ),
+    (
+        "hatchbox",
+        &[
+            FilamentMaterial::PLA,
+            FilamentMaterial::ABS,
+            FilamentMaterial::PETG,
+            FilamentMaterial::TPU,
+        ],
+    ),
+    (
+        "esun",
+        &[
+            FilamentMaterial::PLA,
+            FilamentMaterial::PLAPlus,
+            FilamentMaterial::ABS,
+            FilamentMaterial::PETG,
+            FilamentMaterial::TPU,
+            FilamentMaterial::ASA,
+            FilamentMaterial::Nylon,
+            FilamentMaterial::PC,
+        ],
+    ),
+    (
+        "overture",
+        &[
+            FilamentMaterial::PLA,
+            FilamentMaterial::PLAPlus,
+            FilamentMaterial::PETG,
+            FilamentMaterial::ABS,
+            FilamentMaterial::ASA,
+            FilamentMaterial::TPU,
+        ],
+    ),
+    (
+        "sunlu",
+        &[
+            FilamentMaterial::PLA,
+            FilamentMaterial::PLAPlus,
+            FilamentMaterial::PETG,
+            FilamentMaterial::ABS,
+            FilamentMaterial::ASA,
+            FilamentMaterial::TPU,
+        ],
+    ),
+];
+
+impl FilamentMaterial {
+    /// Range of price per kg outside which a listing is probably a scrape or unit error
+    pub fn plausible_price_per_kg(&self) -> RangeInclusive<CentsPerKg> {
+        let (min, max) = match self {
+            Self::PLA => (800, 8000),
+            Self::PLAPlus => (1000, 8000),
+            Self::PETG => (1000, 8000),
+            Self::ABS => (1200, 8000),
+            Self::ASA => (1500, 10000),
+            Self::PCTG => (1500, 10000),
+            Self::TPU => (1800, 15000),
+            Self::Nylon => (2500, 20000),
+            Self::PC => (2500, 20000),
+            Self::Unspecified | Self::Unknown | Self::Other(_) => {
+                return GLOBAL_PRICE_PER_KG_BOUNDS;
+            }
+        };
+
+        CentsPerKg(min)..=CentsPerKg(max)
+    }
+}
+
+impl Product {
+    pub fn price_per_kg_plausible(&self) -> bool {
+        self.material
+            .plausible_price_per_kg()
+            .contains(&self.calculate_price_per_kg())
+    }
+
+    /// Whether the price per kg fits the weight: within the material's plausible band, which
+    /// is raised for small spools and lowered for bulk ones. A bulk spool priced like a small
+    /// one (or the reverse) usually means the weight or price was scraped wrong. Products
+    /// without a weight are taken as consistent.
+    pub fn weight_price_consistent(&self) -> bool {
+        if self.weight.0 == 0 {
+            return true;
+        }
+
+        let band = self.material.plausible_price_per_kg();
+        let (min, max) = (band.start().0, band.end().0);
+
+        let (min, max) = if self.weight < SMALL_SPOOL_WEIGHT {
+            (min, max.saturating_mul(5) / 2)
+        } else if self.weight >= BULK_SPOOL_WEIGHT {
+            (min * 3 / 5, max)
+        } else {
+            (min, max)
+        };
+
+        (min..=max).contains(&self.calculate_price_per_kg().0)
+    }
+
+    /// Whether the price is plausible for a spool in its currency's major unit, catching
+    /// prices stored in the wrong minor unit (a yen price multiplied by 100 like cents, or
+    /// a dollar price entered as whole dollars). Prices of unknown currencies aren't judged.
+    pub fn price_currency_consistent(&self) -> bool {
+        let plausible = match self.currency {
+            Currency::JPY => PLAUSIBLE_SPOOL_PRICE_JPY,
+            Currency::USD | Currency::EUR | Currency::GBP | Currency::CAD | Currency::AUD => {
+                PLAUSIBLE_SPOOL_PRICE_CENTS
+            }
+            Currency::Other(_) => return true,
+        };
+
+        self.price.0 == 0 || plausible.contains(&self.price.0)
+    }
+
+    /// Whether the brand is known to make the material, from `KNOWN_BRAND_MATERIALS`. A
+    /// soft signal: `false` only suggests a mis-scraped brand or material. `None` when the
+    /// brand isn't known or the material isn't determined.
+    pub fn brand_material_plausible(&self) -> Option<bool> {
+        if matches!(
+            self.material,
+            FilamentMaterial::Unspecified | FilamentMaterial::Unknown
+        ) {
+            return None;
+        }
+
+        let brand = self.brand.as_deref()?.trim().to_lowercase();
+
+        KNOWN_BRAND_MATERIALS
+            .iter()
+            .find(|(known, _)| *known == brand)
+            .map(|(_, materials)| materials.contains(&self.material))
+    }
+
+    /// Whether `color` looks like a color name: not blank and not only digits and
+    /// punctuation (a scraped SKU or index, e.g. "12345")
+    pub fn color_is_valid(&self) -> bool {
+        self.color.chars().any(char::is_alphabetic)
+    }
+
+    /// Data-quality problems an operator should look at, in `ReviewIssue` order
+    pub fn review_issues(&self) -> Vec<ReviewIssue> {
+        let unexplained_diameter = matches!(self.diameter, FilamentDiameter::Other(_))
+            && self.diameter_matches_name() != Some(true);
+
+        [
+            (
+                ReviewIssue::FlaggedUpdates,
+                !self.flagged_updates.is_empty(),
+            ),
+            (ReviewIssue::ZeroWeight, self.weight.0 == 0),
+            (
+                ReviewIssue::ImplausiblePrice,
+                !self.weight_price_consistent(),
+            ),
+            (
+                ReviewIssue::UnspecifiedMaterial,
+                self.material.is_undetermined(),
+            ),
+            (ReviewIssue::InvalidColor, !self.color_is_valid()),
+            (ReviewIssue::UnexplainedDiameter, unexplained_diameter),
+            (
+                ReviewIssue::CurrencyMismatch,
+                !self.price_currency_consistent(),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(issue, present)| present.then_some(issue))
+        .collect()
+    }
+
+    /// Whether an operator should look at this product, i.e. it has any `review_issues`
+    pub fn needs_review(&self) -> bool {
+        !self.review_issues().is_empty()
+    }
+
+    /// `is_public_visible_with` the config from the environment
+    pub fn is_public_visible(&self) -> bool {
+        self.is_public_visible_with(&VisibilityConfig::from_env())
+    }
+
+    /// Whether the product should be listed on the public site: it doesn't `needs_review`
+    /// and, when `config.hide_out_of_stock` is set, isn't `OutOfStock`
+    pub fn is_public_visible_with(&self, config: &VisibilityConfig) -> bool {
+        !(config.hide_out_of_stock && self.availability == Availability::OutOfStock)
+            && !self.needs_review()
+    }
+
+    /// Sum of the severities of `review_issues`, zero when there are none
+    pub fn review_severity(&self) -> u32 {
+        self.review_issues().iter().map(ReviewIssue::severity).sum()
+    }
+
+    /// Up to `limit` products that `needs_review`, most severe first and cheapest per kg
+    /// among equally severe ones
+    pub async fn review_queue(limit: usize) -> Result<Vec<Product>, SurrealSocketError> {
+        let products = Self::search(&ProductQuery {
+            exclude_samples: false,
+            ..Default::default()
+        })
+        .await?;
+
+        Ok(review_order(products, limit))
+    }
+}
+
+/// Up to `limit` of `products` that need review, most severe first. Ties keep the order
+/// given, which for `review_queue` is cheapest per kg first.
+fn review_order(products: Vec<Product>, limit: usize) -> Vec<Product> {
+    let mut queue: Vec<(u32, Product)> = products
+        .into_iter()
+        .map(|p| (p.review_severity(), p))
+        .filter(|(severity, _)| *severity > 0)
+        .collect();
+
+    // Stable, so the given order breaks ties
+    queue.sort_by_key(|(severity, _)| std::cmp::Reverse(*severity));
+    queue.truncate(limit);
+
+    queue.into_iter().map(|(_, p)| p).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::product::Cents;
+
+    fn priced(material: FilamentMaterial, cents: u32) -> Product {
+        let mut product = Product {
+            material,
+            price: Cents(cents),
+            ..Product::example()
+        };
+        product.price_per_kg = product.calculate_price_per_kg();
+        product
+    }
+
+    #[test]
+    fn price_per_kg_plausibility_by_material() {
+        assert!(!priced(FilamentMaterial::ABS, 499).price_per_kg_plausible());
+        assert!(priced(FilamentMaterial::PLA, 2199).price_per_kg_plausible());
+        assert!(!priced(FilamentMaterial::PLA, 9999).price_per_kg_plausible());
+
+        let unknown = priced(FilamentMaterial::Other("PEEK".to_owned()), 25000);
+        assert!(unknown.price_per_kg_plausible());
+        assert_eq!(
+            unknown.material.plausible_price_per_kg(),
+            GLOBAL_PRICE_PER_KG_BOUNDS
+        );
+    }
+
+    #[test]
+    fn heavy_cheap_spool_is_flagged() {
+        let mislabeled = Product {
+            weight: Grams(5000),
+            ..priced(FilamentMaterial::PLA, 1500)
+        };
+
+        assert!(!mislabeled.weight_price_consistent());
+        assert!(
+            mislabeled
+                .review_issues()
+                .contains(&ReviewIssue::ImplausiblePrice)
+        );
+
+        let bulk = Product {
+            weight: Grams(5000),
+            ..priced(FilamentMaterial::PLA, 4999)
+        };
+        assert!(bulk.weight_price_consistent());
+
+        let small = Product {
+            weight: Grams(250),
+            ..priced(FilamentMaterial::PLA, 1500)
+        };
+        assert!(small.weight_price_consistent());
+        assert!(
+            Product {
+                weight: Grams(0),
+                ..Product::example()
+            }
+            .weight_price_consistent()
+        );
+    }
+
+    #[test]
+    fn color_validity() {
+        let colored = |color: &str| Product {
+            color: color.to_owned(),
+            ..Product::example()
+        };
+
+        assert!(colored("Galaxy Black").color_is_valid());
+        assert!(colored("Grün").color_is_valid());
+
+        for invalid in ["", "   ", "12345", "#123-45"] {
+            let product = colored(invalid);
+            assert!(!product.color_is_valid(), "{invalid:?}");
+            assert!(product.review_issues().contains(&ReviewIssue::InvalidColor));
+        }
+    }
+
+    #[test]
+    fn each_condition_alone_needs_review() {
+        use crate::product::{FlaggedUpdate, Grams};
+
+        let clean = Product::example();
+        assert!(!clean.needs_review());
+
+        let cases = [
+            (
+                ReviewIssue::FlaggedUpdates,
+                Product {
+                    flagged_updates: vec![FlaggedUpdate {
+                        previous_price: Cents(2199),
+                        proposed_price: Cents(199),
+                        change_percent: 90.9,
+                    }],
+                    ..Product::example()
+                },
+            ),
+            (
+                ReviewIssue::ZeroWeight,
+                Product {
+                    weight: Grams(0),
+                    ..Product::example()
+                },
+            ),
+            (
+                ReviewIssue::ImplausiblePrice,
+                priced(FilamentMaterial::PLA, 9999),
+            ),
+            (
+                ReviewIssue::UnspecifiedMaterial,
+                Product {
+                    material: FilamentMaterial::Unspecified,
+                    ..Product::example()
+                },
+            ),
+            (
+                ReviewIssue::InvalidColor,
+                Product {
+                    color: "12345".to_owned(),
+                    ..Product::example()
+                },
+            ),
+            (
+                ReviewIssue::UnexplainedDiameter,
+                Product {
+                    diameter: FilamentDiameter::Other(200),
+                    ..Product::example()
+                },
+            ),
+            (
+                ReviewIssue::CurrencyMismatch,
+                Product {
+                    currency: Currency::JPY,
+                    price: Cents(219_900),
+                    ..Product::example()
+                },
+            ),
+        ];
+
+        for (issue, product) in cases {
+            assert!(product.needs_review(), "{issue:?}");
+            assert!(product.review_issues().contains(&issue), "{issue:?}");
+            assert!(product.review_severity() >= issue.severity());
+        }
+    }
+
+    #[test]
+    fn review_queue_puts_the_worst_records_first() {
+        let clean = Product::example();
+        let bad_color = Product {
+            color: String::new(),
+            ..Product::example()
+        };
+        let also_bad_color = Product {
+            color: "0000".to_owned(),
+            ..Product::example()
+        };
+        let zero_weight = Product {
+            weight: crate::product::Grams(0),
+            ..Product::example()
+        };
+        let worst = Product {
+            material: FilamentMaterial::Unspecified,
+            color: String::new(),
+            ..zero_weight.clone()
+        };
+
+        let queue = review_order(
+            vec![
+                bad_color.clone(),
+                clean,
+                zero_weight.clone(),
+                also_bad_color.clone(),
+                worst.clone(),
+            ],
+            10,
+        );
+
+        assert!(
+            queue
+                == vec![
+                    worst,
+                    zero_weight.clone(),
+                    bad_color.clone(),
+                    also_bad_color
+                ]
+        );
+
+        let top = review_order(vec![bad_color, zero_weight.clone()], 1);
+        assert!(top == vec![zero_weight]);
+    }
+
+    #[test]
+    fn brand_material_plausibility() {
+        let made_by = |brand: &str, material| Product {
+            brand: Some(brand.to_owned()),
+            material,
+            ..Product::example()
+        };
+
+        assert_eq!(
+            made_by(" Polymaker ", FilamentMaterial::TPU).brand_material_plausible(),
+            Some(true)
+        );
+        assert_eq!(
+            made_by("Prusament", FilamentMaterial::TPU).brand_material_plausible(),
+            Some(false)
+        );
+        assert_eq!(
+            made_by("NoNameCo", FilamentMaterial::TPU).brand_material_plausible(),
+            None
+        );
+        assert_eq!(
+            made_by("Polymaker", FilamentMaterial::Unknown).brand_material_plausible(),
+            None
+        );
+    }
+}
@@ -0,0 +1,1610 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use chrono::{DateTime, Utc};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use surreal_socket::{
+    dbrecord::{DBRecord, SsUuid},
+    error::SurrealSocketError,
+};
+use surrealdb::{Connection, Surreal};
+use utoipa::ToSchema;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    product::{
+        CentsPerKg, FilamentDiameter, FilamentMaterial, Product, ProductResponse, Retailer,
+        SAMPLE_MAX_WEIGHT, cheapest_by, color_family, normalize_color,
+    },
+    quality::VisibilityConfig,
+    surrealdb_client,
+    tags::normalize_tag,
+};
+
+/// Filters for `Product::search`. Unset filters match every product, and so do `material`
+/// and `retailer` filters holding an empty (or blank) `Other` value, so that a form
+/// submitted with an empty field doesn't narrow results to records with an empty string.
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct ProductQuery {
+    pub material: Option<FilamentMaterial>,
+    /// Match any of these materials, for multi-select filters. Empty matches every material;
+    /// combined with `material`, both must match.
+    pub materials: Vec<FilamentMaterial>,
+    pub diameter: Option<FilamentDiameter>,
+    pub retailer: Option<Retailer>,
+    /// Matched case-insensitively
+    pub brand: Option<String>,
+    pub max_price_per_kg: Option<CentsPerKg>,
+    /// ISO country code, matched case-insensitively. Products without a region never match.
+    pub region: Option<String>,
+    /// Leave out products known to have fewer units in stock. Products whose retailer
+    /// doesn't report a stock quantity are kept.
+    pub min_stock: Option<u32>,
+    pub limit: Option<u32>,
+    /// Leave out sample and trial spools (see `Product::is_sample`). Defaults to `true`.
+    pub exclude_samples: bool,
+    /// Only products fit for the public site (see `Product::is_public_visible`). Review
+    /// issues can only be checked in memory, so `bulk_add_tag` rejects queries with this set.
+    pub public_only: bool,
+}
+
+impl Default for ProductQuery {
+    fn default() -> Self {
+        Self {
+            material: None,
+            materials: Vec::new(),
+            diameter: None,
+            retailer: None,
+            brand: None,
+            max_price_per_kg: None,
+            region: None,
+            min_stock: None,
+            limit: None,
+            exclude_samples: true,
+            public_only: false,
+        }
+    }
+}
+
+impl ProductQuery {
+    /// `material`, unless it is a blank `Other`
+    fn material_filter(&self) -> Option<&FilamentMaterial> {
+        self.material
+            .as_ref()
+            .filter(|m| !matches!(m, FilamentMaterial::Other(s) if s.trim().is_empty()))
+    }
+
+    /// `materials` without blank `Other` values, or `None` when that leaves none
+    fn materials_filter(&self) -> Option<Vec<&FilamentMaterial>> {
+        let materials: Vec<&FilamentMaterial> = self
+            .materials
+            .iter()
+            .filter(|m| !matches!(m, FilamentMaterial::Other(s) if s.trim().is_empty()))
+            .collect();
+
+        (!materials.is_empty()).then_some(materials)
+    }
+
+    /// Whether `material` passes both the `material` and `materials` filters
+    fn material_matches(&self, material: &FilamentMaterial) -> bool {
+        self.material_filter().is_none_or(|m| material == m)
+            && self
+                .materials_filter()
+                .is_none_or(|materials| materials.contains(&material))
+    }
+
+    /// `retailer`, unless it is a blank `Other`
+    fn retailer_filter(&self) -> Option<&Retailer> {
+        self.retailer
+            .as_ref()
+            .filter(|r| !matches!(r, Retailer::Other(s) if s.trim().is_empty()))
+    }
+
+    /// Conditions for these filters, hiding out-of-stock products from `public_only` queries
+    /// as `visibility` says. Review issues aren't expressible as conditions.
+    fn builder(&self, visibility: &VisibilityConfig) -> QueryBuilder {
+        let mut builder = QueryBuilder::default();
+
+        if let Some(material) = self.material_filter() {
+            builder.and(
+                "material = $material",
+                [("material", material.to_string().into())],
+            );
+        }
+
+        if let Some(materials) = self.materials_filter() {
+            builder.and(
+                "material IN $materials",
+                [(
+                    "materials",
+                    materials
+                        .iter()
+                        .map(|m| serde_json::Value::from(m.to_string()))
+                        .collect::<Vec<serde_json::Value>>()
+                        .into(),
+                )],
+            );
+        }
+
+        if let Some(diameter) = self.diameter {
+            builder.and(
+                "diameter = $diameter",
+                [("diameter", u16::from(diameter).into())],
+            );
+        }
+
+        if let Some(retailer) = self.retailer_filter() {
+            builder.and(
+                "retailer = $retailer",
+                [("retailer", retailer.to_string().into())],
+            );
+        }
+
+        if let Some(brand) = &self.brand {
+            builder.and(BRAND_CONDITION, [("brand", brand.trim().into())]);
+        }
+
+        if let Some(max_price_per_kg) = self.max_price_per_kg {
+            builder.and(
+                "price_per_kg <= $max_price_per_kg",
+                [("max_price_per_kg", max_price_per_kg.0.into())],
+            );
+        }
+
+        if let Some(region) = &self.region {
+            builder.and(
+                "region = $region",
+                [("region", region.trim().to_uppercase().into())],
+            );
+        }
+
+        if let Some(min_stock) = self.min_stock {
+            builder.and(
+                "(stock_quantity = NONE OR stock_quantity >= $min_stock)",
+                [("min_stock", min_stock.into())],
+            );
+        }
+
+        if self.exclude_samples {
+            builder.and(
+                "(weight = 0 OR weight >= $sample_max_weight) \
+                 AND string::lowercase(name) != /(^|[^a-z0-9])(sample|trial)([^a-z0-9]|$)/",
+                [("sample_max_weight", SAMPLE_MAX_WEIGHT.0.into())],
+            );
+        }
+
+        if self.public_only && visibility.hide_out_of_stock {
+            builder.and("availability != 'OutOfStock'", []);
+        }
+
+        builder
+    }
+
+    /// Runs `search_statement` on `client`. `public_only` queries are read in batches of
+    /// `PUBLIC_BATCH_SIZE`, dropping products that `needs_review`, until `limit` products are
+    /// found or the matches run out.
+    async fn fetch_on<C: Connection>(
+        &self,
+        client: &Surreal<C>,
+    ) -> Result<Vec<Product>, SurrealSocketError> {
+        let visibility = VisibilityConfig::from_env();
+
+        if !self.public_only {
+            let limit = self.limit.map(|l| l as usize);
+            return Ok(run_on(client, self.search_statement(&visibility, limit, 0))
+                .await?
+                .take(0)?);
+        }
+
+        let limit = self.limit.map_or(usize::MAX, |l| l as usize);
+        let mut products = Vec::new();
+        let mut start = 0;
+
+        while products.len() < limit {
+            let statement = self.search_statement(&visibility, Some(PUBLIC_BATCH_SIZE), start);
+            let batch: Vec<Product> = run_on(client, statement).await?.take(0)?;
+            let exhausted = batch.len() < PUBLIC_BATCH_SIZE;
+
+            products.extend(
+                batch
+                    .into_iter()
+                    .filter(|p| p.is_public_visible_with(&visibility)),
+            );
+
+            if exhausted {
+                break;
+            }
+
+            start += PUBLIC_BATCH_SIZE;
+        }
+
+        products.truncate(limit);
+        Ok(products)
+    }
+
+    /// `SELECT *` with these filters, cheapest per kg first, reading at most `limit` rows
+    /// from `start`. Ties are broken by uuid so the order, and which rows fall within a
+    /// limit, is stable between calls.
+    fn search_statement(
+        &self,
+        visibility: &VisibilityConfig,
+        limit: Option<usize>,
+        start: usize,
+    ) -> Statement {
+        let mut tail = format!(" ORDER BY price_per_kg ASC, {} ASC", Product::UUID_FIELD);
+
+        if let Some(limit) = limit {
+            tail += &format!(" LIMIT {}", limit);
+        }
+        if start > 0 {
+            tail += &format!(" START {}", start);
+        }
+
+        self.builder(visibility).build(&select_products(), &tail)
+    }
+
+    /// Number of products on `client` matching these filters, ignoring `limit`
+    async fn count_on<C: Connection>(
+        &self,
+        client: &Surreal<C>,
+    ) -> Result<u64, SurrealSocketError> {
+        if self.public_only {
+            let unlimited = ProductQuery {
+                limit: None,
+                ..self.clone()
+            };
+
+            return Ok(unlimited.fetch_on(client).await?.len() as u64);
+        }
+
+        #[derive(Deserialize)]
+        struct Count {
+            count: u64,
+        }
+
+        let statement = self.count_statement(&VisibilityConfig::from_env());
+        let count: Option<Count> = run_on(client, statement).await?.take(0)?;
+
+        Ok(count.map_or(0, |c| c.count))
+    }
+
+    /// `count()` over the rows `search_statement` selects, without its limit
+    fn count_statement(&self, visibility: &VisibilityConfig) -> Statement {
+        let head = format!("SELECT count() AS count FROM {}", Product::table());
+        self.builder(visibility).build(&head, " GROUP ALL")
+    }
+
+    /// Products on `client` matching these filters per distinct value of `field`, as
+    /// `(value, count)`
+    async fn group_counts_on<T: DeserializeOwned + ToString, C: Connection>(
+        &self,
+        client: &Surreal<C>,
+        field: &'static str,
+    ) -> Result<Vec<(String, u64)>, SurrealSocketError> {
+        let statement = self.group_count_statement(&VisibilityConfig::from_env(), field);
+        let rows: Vec<serde_json::Value> = run_on(client, statement).await?.take(0)?;
+
+        rows.into_iter()
+            .map(|mut row| {
+                let count = row["count"].as_u64().unwrap_or_default();
+                let value: T = serde_json::from_value(row[field].take())?;
+                Ok((value.to_string(), count))
+            })
+            .collect()
+    }
+
+    fn group_count_statement(&self, visibility: &VisibilityConfig, field: &str) -> Statement {
+        let head = format!(
+            "SELECT {}, count() AS count FROM {}",
+            field,
+            Product::table()
+        );
+        self.builder(visibility)
+            .build(&head, &format!(" GROUP BY {}", field))
+    }
+
+    /// The grouped counts behind each facet of `search_with_facets`: materials and
+    /// retailers each without their own filter, color families with every filter
+    fn facet_queries(&self) -> [ProductQuery; 3] {
+        let materials = ProductQuery {
+            material: None,
+            materials: Vec::new(),
+            ..self.clone()
+        };
+        let retailers = ProductQuery {
+            retailer: None,
+            ..self.clone()
+        };
+
+        [materials, retailers, self.clone()]
+    }
+}
+
+/// Rows read per round trip by `public_only` searches
+const PUBLIC_BATCH_SIZE: usize = 200;
+
+/// Case-insensitive match of `brand` against the `$brand` parameter
+const BRAND_CONDITION: &str = "string::lowercase(brand ?? '') = string::lowercase($brand)";
+
+/// SurrealQL `WHERE` conditions and their bound parameters, joined with `AND`. Conditions are
+/// fixed SQL, built only from field and table names, and values are always bound, never
+/// interpolated, since `Other` variants can hold arbitrary text.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct QueryBuilder {
+    conditions: Vec<Cow<'static, str>>,
+    bindings: Vec<(&'static str, serde_json::Value)>,
+}
+
+/// A statement built by `QueryBuilder::build` and its bindings
+type Statement = (String, Vec<(&'static str, serde_json::Value)>);
+
+impl QueryBuilder {
+    /// Adds `condition`, along with the parameters it references
+    fn and(
+        &mut self,
+        condition: impl Into<Cow<'static, str>>,
+        bindings: impl IntoIterator<Item = (&'static str, serde_json::Value)>,
+    ) -> &mut Self {
+        self.conditions.push(condition.into());
+        self.bindings.extend(bindings);
+        self
+    }
+
+    /// `head`, the `WHERE` clause (left out when there are no conditions) and `tail`, e.g.
+    /// `ORDER BY` or `GROUP` clauses, with the bindings for the statement
+    fn build(self, head: &str, tail: &str) -> Statement {
+        let where_clause = if self.conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", self.conditions.join(" AND "))
+        };
+
+        (format!("{}{}{}", head, where_clause, tail), self.bindings)
+    }
+}
+
+/// Cheapest per kg of `products`, ties broken by uuid as in `search`
+fn cheapest_offer(products: Vec<Product>) -> Option<Product> {
+    products
+        .into_iter()
+        .min_by_key(|p| (p.price_per_kg, p.uuid.to_uuid_string()))
+}
+
+/// `SELECT *` from the products table, the head of most statements
+fn select_products() -> String {
+    format!("SELECT * FROM {}", Product::table())
+}
+
+/// Runs `statement` with its bindings
+async fn run(statement: Statement) -> Result<surrealdb::Response, SurrealSocketError> {
+    run_on(&surrealdb_client().await?, statement).await
+}
+
+/// Runs `statement` with its bindings on `client`
+async fn run_on<C: Connection>(
+    client: &Surreal<C>,
+    (query, bindings): Statement,
+) -> Result<surrealdb::Response, SurrealSocketError> {
+    let mut request = client.query(query);
+
+    for binding in bindings {
+        request = request.bind(binding);
+    }
+
+    Ok(request.await?)
+}
+
+/// Position in the change feed: the last `(updated_at, uuid)` returned. Rows are read in
+/// that order, so equal timestamps are paged through without gaps or repeats.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub updated_at: DateTime<Utc>,
+    /// `None` before the first row
+    pub uuid: Option<SsUuid<Product>>,
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Self {
+            updated_at: DateTime::UNIX_EPOCH,
+            uuid: None,
+        }
+    }
+}
+
+impl Cursor {
+    /// Cursor following `batch`, a page read from this cursor: the last row's position, or
+    /// this cursor again when the batch is empty
+    fn after(self, batch: &[Product]) -> Cursor {
+        match batch.last() {
+            Some(last) => Cursor {
+                updated_at: last.updated_at,
+                uuid: Some(last.uuid.clone()),
+            },
+            None => self,
+        }
+    }
+}
+
+/// Number of products per facet value
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, ToSchema)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: u64,
+}
+
+/// Facet counts for a search. Each facet is counted with every filter applied except its
+/// own, so the UI can show what selecting another value of that facet would yield.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Facets {
+    pub materials: Vec<FacetCount>,
+    pub retailers: Vec<FacetCount>,
+    pub color_families: Vec<FacetCount>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SearchResults {
+    pub results: Vec<ProductResponse>,
+    /// Matching products before `limit` is applied
+    pub total: u64,
+    pub facets: Facets,
+}
+
+impl Product {
+    /// Products matching `query`, cheapest per kg first. A query with no filters set returns
+    /// every product, apart from samples unless `exclude_samples` is turned off.
+    pub async fn search(query: &ProductQuery) -> Result<Vec<Product>, SurrealSocketError> {
+        query.fetch_on(&surrealdb_client().await?).await
+    }
+
+    /// Whether this product passes the filters of `query`, evaluated in memory with the same
+    /// rules as `search`. `limit` is not a filter and is ignored.
+    pub fn matches(&self, query: &ProductQuery) -> bool {
+        self.matches_with(query, &VisibilityConfig::from_env())
+    }
+
+    /// `matches`, judging `public_only` by `visibility`, for callers checking many products
+    /// against one query
+    pub fn matches_with(&self, query: &ProductQuery, visibility: &VisibilityConfig) -> bool {
+        query.material_matches(&self.material)
+            && query.diameter.is_none_or(|d| self.diameter == d)
+            && query.retailer_filter().is_none_or(|r| &self.retailer == r)
+            && query.brand.as_ref().is_none_or(|b| {
+                self.brand.as_deref().unwrap_or_default().to_lowercase() == b.trim().to_lowercase()
+            })
+            && query
+                .max_price_per_kg
+                .is_none_or(|max| self.price_per_kg <= max)
+            && query
+                .region
+                .as_ref()
+                .is_none_or(|r| self.region.as_ref() == Some(&r.trim().to_uppercase()))
+            && query
+                .min_stock
+                .is_none_or(|min| self.stock_quantity.is_none_or(|stock| stock >= min))
+            && !(query.exclude_samples && self.is_sample())
+            && (!query.public_only || self.is_public_visible_with(visibility))
+    }
+
+    /// Products updated after `ts`, oldest update first
+    pub async fn updated_since(ts: DateTime<Utc>) -> Result<Vec<Product>, SurrealSocketError> {
+        Ok(run(Self::updated_since_statement(ts)).await?.take(0)?)
+    }
+
+    fn updated_since_statement(ts: DateTime<Utc>) -> Statement {
+        let mut builder = QueryBuilder::default();
+        builder.and("updated_at > $ts", [("ts", ts.timestamp_millis().into())]);
+        builder.build(&select_products(), " ORDER BY updated_at ASC")
+    }
+
+    /// Next batch of at most `limit` products changed after `cursor` (from the start when
+    /// `None`), and the cursor to pass for the following batch
+    pub async fn changes_since(
+        cursor: Option<Cursor>,
+        limit: usize,
+    ) -> Result<(Vec<Product>, Cursor), SurrealSocketError> {
+        let cursor = cursor.unwrap_or_default();
+
+        let statement = Self::changes_since_statement(&cursor, limit)?;
+        let products: Vec<Product> = run(statement).await?.take(0)?;
+
+        let next = cursor.after(&products);
+
+        Ok((products, next))
+    }
+
+    fn changes_since_statement(
+        cursor: &Cursor,
+        limit: usize,
+    ) -> Result<Statement, SurrealSocketError> {
+        let mut builder = QueryBuilder::default();
+        let ts = ("ts", cursor.updated_at.timestamp_millis().into());
+
+        match &cursor.uuid {
+            Some(uuid) => builder.and(
+                format!(
+                    "(updated_at > $ts OR (updated_at = $ts AND {} > $uuid))",
+                    Self::UUID_FIELD
+                ),
+                [ts, ("uuid", serde_json::to_value(uuid)?)],
+            ),
+            None => builder.and("updated_at >= $ts", [ts]),
+        };
+
+        let tail = format!(
+            " ORDER BY updated_at ASC, {} ASC LIMIT {}",
+            Self::UUID_FIELD,
+            limit
+        );
+
+        Ok(builder.build(&select_products(), &tail))
+    }
+
+    /// Cheapest listing per normalized color for `material`
+    pub async fn cheapest_by_color(
+        material: &FilamentMaterial,
+    ) -> Result<HashMap<String, Product>, SurrealSocketError> {
+        let products = Self::search(&ProductQuery {
+            material: Some(material.clone()),
+            ..Default::default()
+        })
+        .await?;
+
+        Ok(cheapest_per_color(products))
+    }
+
+    /// Number of products `search` would return for `query` without its `limit`
+    pub async fn count_for_query(query: &ProductQuery) -> Result<u64, SurrealSocketError> {
+        query.count_on(&surrealdb_client().await?).await
+    }
+
+    /// Adds `tag`, normalized as by `normalize_tag`, to every product matching `query`
+    /// (ignoring `limit`) that doesn't have it yet. Returns the number of products tagged.
+    /// Fails for `public_only` queries, whose review check can't run inside the update.
+    pub async fn bulk_add_tag(query: &ProductQuery, tag: &str) -> Result<u64, SurrealSocketError> {
+        Self::bulk_add_tag_with(query, tag, &SystemClock).await
+    }
+
+    /// `bulk_add_tag`, stamping `updated_at` on tagged products from `clock`
+    pub async fn bulk_add_tag_with(
+        query: &ProductQuery,
+        tag: &str,
+        clock: &dyn Clock,
+    ) -> Result<u64, SurrealSocketError> {
+        if query.public_only {
+            return Err(SurrealSocketError::from(
+                "bulk_add_tag doesn't support public_only queries".to_owned(),
+            ));
+        }
+
+        let Some(statement) = Self::bulk_add_tag_statement(query, tag, clock.now()) else {
+            return Ok(0);
+        };
+
+        let updated: Vec<serde_json::Value> = run(statement).await?.take(0)?;
+        Ok(updated.len() as u64)
+    }
+
+    /// The update `bulk_add_tag` runs, setting `updated_at` to `now`, or `None` when `tag`
+    /// normalizes to nothing
+    fn bulk_add_tag_statement(
+        query: &ProductQuery,
+        tag: &str,
+        now: DateTime<Utc>,
+    ) -> Option<Statement> {
+        let tag = normalize_tag(tag);
+
+        if tag.is_empty() {
+            return None;
+        }
+
+        let mut builder = query.builder(&VisibilityConfig::default());
+        builder.and("$tag NOTINSIDE (tags ?? [])", [("tag", tag.into())]);
+
+        let head = format!(
+            "UPDATE {} SET tags = array::append(tags ?? [], $tag), updated_at = $updated_at",
+            Self::table()
+        );
+        let tail = format!(" RETURN {}", Self::UUID_FIELD);
+
+        let (sql, mut bindings) = builder.build(&head, &tail);
+        bindings.push(("updated_at", now.timestamp_millis().into()));
+        Some((sql, bindings))
+    }
+
+    /// Search results together with material, retailer and color family facet counts. The
+    /// counts are grouped in the database, so only the page of results is read, except for
+    /// `public_only` queries: their review check runs in memory, so facets and total count the
+    /// products `search` would return.
+    pub async fn search_with_facets(
+        query: &ProductQuery,
+    ) -> Result<SearchResults, SurrealSocketError> {
+        Self::search_with_facets_on(&surrealdb_client().await?, query).await
+    }
+
+    /// `search_with_facets` on `client`
+    async fn search_with_facets_on<C: Connection>(
+        client: &Surreal<C>,
+        query: &ProductQuery,
+    ) -> Result<SearchResults, SurrealSocketError> {
+        if query.public_only {
+            return Self::public_search_with_facets_on(client, query).await;
+        }
+
+        let [materials, retailers, colors] = query.facet_queries();
+
+        let facets = Facets {
+            materials: facet_counts(
+                materials
+                    .group_counts_on::<FilamentMaterial, _>(client, "material")
+                    .await?,
+            ),
+            retailers: facet_counts(
+                retailers
+                    .group_counts_on::<Retailer, _>(client, "retailer")
+                    .await?,
+            ),
+            color_families: facet_counts(
+                colors
+                    .group_counts_on::<String, _>(client, "color")
+                    .await?
+                    .into_iter()
+                    .map(|(color, count)| (color_family(&color).to_owned(), count)),
+            ),
+        };
+
+        Ok(SearchResults {
+            results: query
+                .fetch_on(client)
+                .await?
+                .into_iter()
+                .map(ProductResponse::from)
+                .collect(),
+            total: query.count_on(client).await?,
+            facets,
+        })
+    }
+
+    /// `search_with_facets_on` for `public_only` queries: reads the products matching every
+    /// filter but the faceted ones and counts the facets over them in memory
+    async fn public_search_with_facets_on<C: Connection>(
+        client: &Surreal<C>,
+        query: &ProductQuery,
+    ) -> Result<SearchResults, SurrealSocketError> {
+        let unfaceted = ProductQuery {
+            material: None,
+            materials: Vec::new(),
+            retailer: None,
+            limit: None,
+            ..query.clone()
+        };
+        let candidates = unfaceted.fetch_on(client).await?;
+
+        let material_matches = |p: &Product| query.material_matches(&p.material);
+        let retailer_matches =
+            |p: &Product| query.retailer_filter().is_none_or(|r| &p.retailer == r);
+
+        let facets = Facets {
+            materials: facet_counts(
+                candidates
+                    .iter()
+                    .filter(|p| retailer_matches(p))
+                    .map(|p| (p.material.to_string(), 1)),
+            ),
+            retailers: facet_counts(
+                candidates
+                    .iter()
+                    .filter(|p| material_matches(p))
+                    .map(|p| (p.retailer.to_string(), 1)),
+            ),
+            color_families: facet_counts(
+                candidates
+                    .iter()
+                    .filter(|p| material_matches(p) && retailer_matches(p))
+                    .map(|p| (color_family(&p.color).to_owned(), 1)),
+            ),
+        };
+
+        let matching: Vec<Product> = candidates
+            .into_iter()
+            .filter(|p| material_matches(p) && retailer_matches(p))
+            .collect();
+        let total = matching.len() as u64;
+        let limit = query.limit.map_or(usize::MAX, |l| l as usize);
+
+        Ok(SearchResults {
+            results: matching
+                .into_iter()
+                .take(limit)
+                .map(ProductResponse::from)
+                .collect(),
+            total,
+            facets,
+        })
+    }
+
+    /// The same brand, material and color in another diameter (e.g. the 2.85 mm version of
+    /// a 1.75 mm spool), preferring this product's retailer and then the lowest price per
+    /// kg. `None` for products without a brand, which can't be matched reliably.
+    pub async fn equivalent_other_diameter(&self) -> Result<Option<Product>, SurrealSocketError> {
+        let Some(brand) = self
+            .brand
+            .as_deref()
+            .map(str::trim)
+            .filter(|b| !b.is_empty())
+        else {
+            return Ok(None);
+        };
+
+        let candidates: Vec<Product> = run(self.equivalent_other_diameter_statement(brand))
+            .await?
+            .take(0)?;
+
+        Ok(self.closest_equivalent(candidates))
+    }
+
+    /// Among `candidates` of the same brand and material in another diameter, the one of the
+    /// same color `equivalent_other_diameter` prefers
+    fn closest_equivalent(&self, candidates: Vec<Product>) -> Option<Product> {
+        let color = normalize_color(&self.color);
+
+        candidates
+            .into_iter()
+            .filter(|p| normalize_color(&p.color) == color)
+            .min_by_key(|p| (p.retailer != self.retailer, p.price_per_kg))
+    }
+
+    fn equivalent_other_diameter_statement(&self, brand: &str) -> Statement {
+        let mut builder = QueryBuilder::default();
+        builder
+            .and(
+                "material = $material",
+                [("material", self.material.to_string().into())],
+            )
+            .and(
+                "diameter != $diameter",
+                [("diameter", u16::from(self.diameter).into())],
+            )
+            .and(BRAND_CONDITION, [("brand", brand.into())]);
+
+        builder.build(&select_products(), "")
+    }
+
+    /// Cheapest per kg member of this product's variant group: the same brand, material and
+    /// spool weight in any color or diameter. Products don't record a variant group id, so
+    /// the group is matched on those fields. May be this product itself. `None` for products
+    /// without a brand, which can't be matched reliably.
+    pub async fn group_best_offer(&self) -> Result<Option<Product>, SurrealSocketError> {
+        let Some(brand) = self
+            .brand
+            .as_deref()
+            .map(str::trim)
+            .filter(|b| !b.is_empty())
+        else {
+            return Ok(None);
+        };
+
+        let members: Vec<Product> = run(self.group_best_offer_statement(brand)).await?.take(0)?;
+        Ok(cheapest_offer(members))
+    }
+
+    /// The query `group_best_offer` reads the group with
+    fn group_best_offer_statement(&self, brand: &str) -> Statement {
+        let mut builder = QueryBuilder::default();
+        builder
+            .and(
+                "material = $material",
+                [("material", self.material.to_string().into())],
+            )
+            .and("weight = $weight", [("weight", self.weight.0.into())])
+            .and(BRAND_CONDITION, [("brand", brand.into())]);
+
+        builder.build(&select_products(), "")
+    }
+
+    /// Other products of the same material and diameter whose price per kg is within
+    /// `tolerance_pct` percent of this one's, closest first
+    pub async fn nearby_by_value(
+        &self,
+        tolerance_pct: u8,
+        limit: usize,
+    ) -> Result<Vec<Product>, SurrealSocketError> {
+        let statement = self.nearby_by_value_statement(tolerance_pct)?;
+        let candidates: Vec<Product> = run(statement).await?.take(0)?;
+
+        Ok(self.closest_by_value(candidates, limit))
+    }
+
+    /// The `limit` products of `candidates` closest to this one in price per kg, closest first
+    fn closest_by_value(&self, mut candidates: Vec<Product>, limit: usize) -> Vec<Product> {
+        let price_per_kg = self.price_per_kg.0 as u64;
+
+        candidates.sort_by_key(|p| (p.price_per_kg.0 as u64).abs_diff(price_per_kg));
+        candidates.truncate(limit);
+        candidates
+    }
+
+    fn nearby_by_value_statement(
+        &self,
+        tolerance_pct: u8,
+    ) -> Result<Statement, SurrealSocketError> {
+        let price_per_kg = self.price_per_kg.0 as u64;
+        let tolerance = price_per_kg * tolerance_pct as u64 / 100;
+
+        let mut builder = QueryBuilder::default();
+        builder
+            .and(
+                "material = $material",
+                [("material", self.material.to_string().into())],
+            )
+            .and(
+                "diameter = $diameter",
+                [("diameter", u16::from(self.diameter).into())],
+            )
+            .and(
+                "price_per_kg >= $low AND price_per_kg <= $high",
+                [
+                    ("low", price_per_kg.saturating_sub(tolerance).into()),
+                    ("high", (price_per_kg + tolerance).into()),
+                ],
+            )
+            .and(
+                format!("{} != $uuid", Self::UUID_FIELD),
+                [("uuid", serde_json::to_value(self.uuid())?)],
+            );
+
+        Ok(builder.build(&select_products(), ""))
+    }
+}
+
+/// Cheapest of `products` per normalized color, leaving out blank colors
+fn cheapest_per_color(products: Vec<Product>) -> HashMap<String, Product> {
+    cheapest_by(products, |p| {
+        Some(normalize_color(&p.color)).filter(|color| !color.is_empty())
+    })
+}
+
+/// Totals per value of `(value, count)` rows, most common first, ties by value
+fn facet_counts(rows: impl IntoIterator<Item = (String, u64)>) -> Vec<FacetCount> {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+
+    for (value, count) in rows {
+        *counts.entry(value).or_default() += count;
+    }
+
+    let mut counts: Vec<FacetCount> = counts
+        .into_iter()
+        .map(|(value, count)| FacetCount { value, count })
+        .collect();
+
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::product::{Availability, Grams};
+
+    #[test]
+    fn each_facet_is_grouped_without_its_own_filter() {
+        let query = ProductQuery {
+            material: Some(FilamentMaterial::PLA),
+            retailer: Some(Retailer::Amazon),
+            diameter: Some(FilamentDiameter::D175),
+            limit: Some(10),
+            ..ProductQuery::default()
+        };
+
+        let [materials, retailers, colors] = query.facet_queries().map(|q| {
+            q.group_count_statement(&VisibilityConfig::default(), "value")
+                .0
+        });
+
+        assert!(!materials.contains("material = $material"));
+        assert!(materials.contains("retailer = $retailer"));
+        assert!(retailers.contains("material = $material"));
+        assert!(!retailers.contains("retailer = $retailer"));
+        assert!(colors.contains("material = $material") && colors.contains("retailer = $retailer"));
+
+        for statement in [materials, retailers, colors] {
+            assert!(statement.contains("diameter = $diameter"));
+            assert!(statement.ends_with(" GROUP BY value"));
+            assert!(!statement.contains("LIMIT"));
+        }
+    }
+
+    #[test]
+    fn facet_counts_merge_grouped_rows() {
+        let families = facet_counts(
+            [("Black", 2), ("Galaxy Black", 1), ("Red", 3), ("Blue", 1)]
+                .into_iter()
+                .map(|(color, count)| (color_family(color).to_owned(), count)),
+        );
+
+        assert_eq!(
+            families,
+            vec![
+                FacetCount {
+                    value: "Black".to_owned(),
+                    count: 3
+                },
+                FacetCount {
+                    value: "Red".to_owned(),
+                    count: 3
+                },
+                FacetCount {
+                    value: "Blue".to_owned(),
+                    count: 1
+                },
+            ]
+        );
+    }
+
+    fn listing(material: FilamentMaterial, retailer: Retailer, color: &str) -> Product {
+        Product {
+            material,
+            retailer,
+            color: color.to_owned(),
+            ..Product::example()
+        }
+    }
+
+    fn count(facets: &[FacetCount], value: &str) -> u64 {
+        facets
+            .iter()
+            .find(|f| f.value == value)
+            .map_or(0, |f| f.count)
+    }
+
+    #[tokio::test]
+    async fn facets_count_seeded_products() {
+        let shop = Retailer::Other("SomeShop".to_owned());
+        let client = crate::seeded_client(vec![
+            listing(FilamentMaterial::PLA, Retailer::Amazon, "Black"),
+            listing(FilamentMaterial::PLA, Retailer::Amazon, "Galaxy Black"),
+            listing(FilamentMaterial::PLA, shop.clone(), "Red"),
+            listing(FilamentMaterial::PETG, Retailer::Amazon, "Blue"),
+            listing(FilamentMaterial::ABS, shop, "White"),
+        ])
+        .await;
+
+        let query = ProductQuery {
+            material: Some(FilamentMaterial::PLA),
+            retailer: Some(Retailer::Amazon),
+            limit: Some(1),
+            ..ProductQuery::default()
+        };
+        let results = Product::search_with_facets_on(&client, &query)
+            .await
+            .unwrap();
+
+        assert_eq!(results.results.len(), 1);
+        assert_eq!(results.total, 2);
+
+        // Each facet ignores its own filter
+        let facets = results.facets;
+        assert_eq!(count(&facets.materials, "PLA"), 2);
+        assert_eq!(count(&facets.materials, "PETG"), 1);
+        assert_eq!(count(&facets.materials, "ABS"), 0);
+        assert_eq!(count(&facets.retailers, "Amazon"), 2);
+        assert_eq!(count(&facets.retailers, "SomeShop"), 1);
+
+        assert_eq!(
+            facets.color_families,
+            vec![FacetCount {
+                value: "Black".to_owned(),
+                count: 2
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn public_facets_leave_out_hidden_products() {
+        let client = crate::seeded_client(vec![
+            listing(FilamentMaterial::PLA, Retailer::Amazon, "Black"),
+            Product {
+                weight: Grams(0),
+                ..listing(FilamentMaterial::PLA, Retailer::Amazon, "Red")
+            },
+            Product {
+                availability: Availability::OutOfStock,
+                ..listing(FilamentMaterial::PLA, Retailer::Amazon, "Blue")
+            },
+            listing(FilamentMaterial::PETG, Retailer::Amazon, "Blue"),
+        ])
+        .await;
+
+        let query = ProductQuery {
+            material: Some(FilamentMaterial::PLA),
+            public_only: true,
+            ..ProductQuery::default()
+        };
+        let results = Product::search_with_facets_on(&client, &query)
+            .await
+            .unwrap();
+
+        assert_eq!(results.results.len(), 1);
+        assert_eq!(results.total, 1);
+        assert_eq!(count(&results.facets.materials, "PLA"), 1);
+        assert_eq!(count(&results.facets.materials, "PETG"), 1);
+        assert_eq!(count(&results.facets.retailers, "Amazon"), 1);
+        assert_eq!(count(&results.facets.color_families, "Black"), 1);
+        assert_eq!(query.count_on(&client).await.unwrap(), 1);
+    }
+
+    #[test]
+    fn nearby_values_closest_first_within_limit() {
+        let product = Product::example();
+        let priced = |cents: u32| Product {
+            price_per_kg: CentsPerKg(cents),
+            ..Product::example()
+        };
+
+        let (_, bindings) = product.nearby_by_value_statement(5).unwrap();
+        assert!(bindings.contains(&("low", 2090u64.into())));
+        assert!(bindings.contains(&("high", 2308u64.into())));
+
+        let nearby = product.closest_by_value(vec![priced(2300), priced(2150), priced(2220)], 2);
+        let prices: Vec<u32> = nearby.iter().map(|p| p.price_per_kg.0).collect();
+        assert_eq!(prices, [2220, 2150]);
+    }
+
+    #[test]
+    fn count_selects_the_same_rows_as_search() {
+        let query = ProductQuery {
+            material: Some(FilamentMaterial::PETG),
+            max_price_per_kg: Some(CentsPerKg(2500)),
+            limit: Some(10),
+            ..ProductQuery::default()
+        };
+
+        let visibility = VisibilityConfig::default();
+        let (search, search_bindings) = query.search_statement(&visibility, Some(10), 0);
+        let (count, count_bindings) = query.count_statement(&visibility);
+
+        let where_clause = |sql: &str| {
+            let start = sql.find(" WHERE ").unwrap();
+            let end = sql.find(" ORDER BY ").or(sql.find(" GROUP ALL")).unwrap();
+            sql[start..end].to_owned()
+        };
+
+        assert_eq!(where_clause(&search), where_clause(&count));
+        assert_eq!(search_bindings, count_bindings);
+        assert!(count.starts_with("SELECT count() AS count FROM products WHERE "));
+        assert!(!count.contains("LIMIT"));
+    }
+
+    #[test]
+    fn cursor_advances_to_the_last_row_of_each_batch() {
+        let at = |secs: i64| Product {
+            updated_at: DateTime::from_timestamp(secs, 0).unwrap(),
+            ..Product::example()
+        };
+        let first_batch = [at(100), at(200)];
+        let second_batch = [at(200), at(300)];
+
+        let cursor = Cursor::default().after(&first_batch);
+        assert_eq!(cursor.updated_at, first_batch[1].updated_at);
+        assert!(cursor.uuid == Some(first_batch[1].uuid.clone()));
+
+        // Rows sharing the last timestamp come after it only by uuid, so none are read twice
+        let (sql, _) = Product::changes_since_statement(&cursor, 2).unwrap();
+        assert!(sql.contains("updated_at = $ts AND"));
+        assert!(!sql.contains("updated_at >= $ts"));
+
+        let cursor = cursor.after(&second_batch);
+        assert!(cursor.uuid == Some(second_batch[1].uuid.clone()));
+
+        let exhausted = cursor.clone().after(&[]);
+        assert!(exhausted == cursor);
+    }
+
+    #[test]
+    fn samples_excluded_by_weight_and_whole_word() {
+        let (query, bindings) = ProductQuery::default()
+            .builder(&VisibilityConfig::default())
+            .build("SELECT * FROM products", "");
+
+        assert!(query.contains("weight >= $sample_max_weight"));
+        assert!(query.contains("(^|[^a-z0-9])(sample|trial)([^a-z0-9]|$)"));
+        assert!(!query.contains("string::contains"));
+        assert_eq!(
+            bindings,
+            vec![("sample_max_weight", SAMPLE_MAX_WEIGHT.0.into())]
+        );
+
+        let everything = ProductQuery {
+            exclude_samples: false,
+            ..ProductQuery::default()
+        };
+        let (query, bindings) = everything
+            .builder(&VisibilityConfig::default())
+            .build("SELECT * FROM products", "");
+
+        assert_eq!(query, "SELECT * FROM products");
+        assert!(bindings.is_empty());
+    }
+
+    #[test]
+    fn cheapest_listing_per_color() {
+        let listing = |color: &str, cents_per_kg: u32| Product {
+            color: color.to_owned(),
+            price_per_kg: CentsPerKg(cents_per_kg),
+            ..Product::example()
+        };
+
+        let cheapest = cheapest_per_color(vec![
+            listing("Black", 2199),
+            listing("black ", 1999),
+            listing("Galaxy Black", 2499),
+            listing("Red", 2299),
+            listing("Red", 2099),
+            listing(" ", 999),
+        ]);
+
+        assert_eq!(cheapest.len(), 3);
+        assert_eq!(cheapest["Black"].price_per_kg, CentsPerKg(1999));
+        assert_eq!(cheapest["Galaxy Black"].price_per_kg, CentsPerKg(2499));
+        assert_eq!(cheapest["Red"].price_per_kg, CentsPerKg(2099));
+    }
+
+    #[tokio::test]
+    async fn search_filters_and_orders_seeded_products() {
+        let product = |material, diameter, cents_per_kg| Product {
+            material,
+            diameter,
+            price_per_kg: CentsPerKg(cents_per_kg),
+            ..Product::example()
+        };
+        let client = crate::seeded_client(vec![
+            product(FilamentMaterial::PLA, FilamentDiameter::D175, 2199),
+            product(FilamentMaterial::PLA, FilamentDiameter::D175, 1899),
+            product(FilamentMaterial::PLA, FilamentDiameter::D285, 1899),
+            product(FilamentMaterial::PETG, FilamentDiameter::D175, 1699),
+            product(FilamentMaterial::PLA, FilamentDiameter::D175, 1899),
+        ])
+        .await;
+
+        let pla = ProductQuery {
+            material: Some(FilamentMaterial::PLA),
+            ..ProductQuery::default()
+        };
+        let results = pla.fetch_on(&client).await.unwrap();
+
+        let prices: Vec<u32> = results.iter().map(|p| p.price_per_kg.0).collect();
+        assert_eq!(prices, [1899, 1899, 1899, 2199]);
+
+        // Equal prices come back in uuid order, so a limit always cuts at the same row
+        let tied: Vec<String> = results[..3]
+            .iter()
+            .map(|p| serde_json::to_string(&p.uuid).unwrap())
+            .collect();
+        assert!(tied.is_sorted());
+
+        let first_two = ProductQuery {
+            limit: Some(2),
+            ..pla.clone()
+        };
+        let limited = first_two.fetch_on(&client).await.unwrap();
+        assert!(
+            limited
+                .iter()
+                .map(|p| &p.uuid)
+                .eq(results[..2].iter().map(|p| &p.uuid))
+        );
+
+        let combined = ProductQuery {
+            diameter: Some(FilamentDiameter::D175),
+            max_price_per_kg: Some(CentsPerKg(2000)),
+            ..pla
+        };
+        let matching = combined.fetch_on(&client).await.unwrap();
+        assert_eq!(matching.len(), 2);
+        assert!(matching.iter().all(|p| p.diameter == FilamentDiameter::D175
+            && p.price_per_kg == CentsPerKg(1899)));
+    }
+
+    #[test]
+    fn matches_brand_retailer_and_price() {
+        let product = Product::example();
+
+        let included = [
+            ProductQuery {
+                brand: Some(" hatchbox ".to_owned()),
+                ..ProductQuery::default()
+            },
+            ProductQuery {
+                material: Some(FilamentMaterial::PLA),
+                diameter: Some(FilamentDiameter::D175),
+                retailer: Some(Retailer::Amazon),
+                ..ProductQuery::default()
+            },
+            ProductQuery {
+                max_price_per_kg: Some(product.price_per_kg),
+                ..ProductQuery::default()
+            },
+            ProductQuery {
+                region: Some("us".to_owned()),
+                ..ProductQuery::default()
+            },
+        ];
+        let excluded = [
+            ProductQuery {
+                brand: Some("eSUN".to_owned()),
+                ..ProductQuery::default()
+            },
+            ProductQuery {
+                material: Some(FilamentMaterial::PETG),
+                ..ProductQuery::default()
+            },
+            ProductQuery {
+                retailer: Some(Retailer::Other("SomeShop".to_owned())),
+                ..ProductQuery::default()
+            },
+            ProductQuery {
+                max_price_per_kg: Some(CentsPerKg(product.price_per_kg.0 - 1)),
+                ..ProductQuery::default()
+            },
+            ProductQuery {
+                region: Some("GB".to_owned()),
+                ..ProductQuery::default()
+            },
+        ];
+
+        for query in &included {
+            assert!(product.matches(query), "{:?}", query);
+        }
+        for query in &excluded {
+            assert!(!product.matches(query), "{:?}", query);
+        }
+
+        let sample = Product {
+            weight: crate::product::Grams(50),
+            ..Product::example()
+        };
+        assert!(!sample.matches(&ProductQuery::default()));
+        assert!(sample.matches(&ProductQuery {
+            exclude_samples: false,
+            ..ProductQuery::default()
+        }));
+    }
+
+    #[test]
+    fn region_filter_is_bound_uppercase() {
+        let query = ProductQuery {
+            region: Some(" gb ".to_owned()),
+            ..ProductQuery::default()
+        };
+
+        let (sql, bindings) = query.search_statement(&VisibilityConfig::default(), None, 0);
+
+        assert!(sql.contains("region = $region"));
+        assert!(bindings.contains(&("region", "GB".into())));
+    }
+
+    #[test]
+    fn empty_retailer_filter_matches_every_retailer() {
+        let query = ProductQuery {
+            retailer: Some(Retailer::Other(String::new())),
+            exclude_samples: false,
+            ..ProductQuery::default()
+        };
+        for retailer in [
+            Retailer::Amazon,
+            Retailer::Other("SomeShop".to_owned()),
+            Retailer::Other(String::new()),
+        ] {
+            let product = Product {
+                retailer,
+                ..Product::example()
+            };
+            assert!(product.matches(&query));
+        }
+    }
+
+    #[test]
+    fn builder_joins_filters_in_order() {
+        let query = ProductQuery {
+            material: Some(FilamentMaterial::PLA),
+            diameter: Some(FilamentDiameter::D175),
+            brand: Some(" Hatchbox ".to_owned()),
+            exclude_samples: false,
+            ..ProductQuery::default()
+        };
+
+        let (sql, bindings) = query
+            .builder(&VisibilityConfig::default())
+            .build("SELECT * FROM products", " LIMIT 5");
+
+        assert_eq!(
+            sql,
+            "SELECT * FROM products WHERE material = $material AND diameter = $diameter \
+             AND string::lowercase(brand ?? '') = string::lowercase($brand) LIMIT 5"
+        );
+        assert_eq!(
+            bindings,
+            vec![
+                ("material", "PLA".into()),
+                ("diameter", 175.into()),
+                ("brand", "Hatchbox".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn builder_ignores_blank_other_values() {
+        let query = ProductQuery {
+            material: Some(FilamentMaterial::Other(" ".to_owned())),
+            retailer: Some(Retailer::Other(String::new())),
+            exclude_samples: false,
+            ..ProductQuery::default()
+        };
+
+        let (sql, bindings) = query
+            .builder(&VisibilityConfig::default())
+            .build("SELECT * FROM products", "");
+
+        assert_eq!(sql, "SELECT * FROM products");
+        assert!(bindings.is_empty());
+    }
+
+    #[test]
+    fn change_feed_statements() {
+        let ts = DateTime::from_timestamp(1_760_000_000, 0).unwrap();
+
+        let (sql, bindings) = Product::updated_since_statement(ts);
+        assert_eq!(
+            sql,
+            "SELECT * FROM products WHERE updated_at > $ts ORDER BY updated_at ASC"
+        );
+        assert_eq!(bindings, vec![("ts", 1_760_000_000_000i64.into())]);
+
+        let start = Cursor {
+            updated_at: ts,
+            uuid: None,
+        };
+        let (sql, bindings) = Product::changes_since_statement(&start, 10).unwrap();
+        assert_eq!(
+            sql,
+            format!(
+                "SELECT * FROM products WHERE updated_at >= $ts ORDER BY updated_at ASC, {} ASC LIMIT 10",
+                Product::UUID_FIELD
+            )
+        );
+        assert_eq!(bindings.len(), 1);
+
+        let resumed = Cursor {
+            updated_at: ts,
+            uuid: Some(Product::example().uuid()),
+        };
+        let (sql, bindings) = Product::changes_since_statement(&resumed, 10).unwrap();
+        assert!(sql.contains(&format!(
+            "(updated_at > $ts OR (updated_at = $ts AND {} > $uuid))",
+            Product::UUID_FIELD
+        )));
+        assert_eq!(
+            bindings.iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+            ["ts", "uuid"]
+        );
+    }
+
+    #[test]
+    fn min_stock_excludes_low_stock_but_not_unknown_stock() {
+        let query = ProductQuery {
+            min_stock: Some(5),
+            ..ProductQuery::default()
+        };
+        let stocked = |stock_quantity: Option<u32>| Product {
+            stock_quantity,
+            ..Product::example()
+        };
+
+        assert!(!stocked(Some(3)).matches(&query));
+        assert!(stocked(Some(5)).matches(&query));
+        assert!(stocked(None).matches(&query));
+
+        let (sql, bindings) = query.search_statement(&VisibilityConfig::default(), None, 0);
+        assert!(sql.contains("(stock_quantity = NONE OR stock_quantity >= $min_stock)"));
+        assert!(bindings.contains(&("min_stock", 5.into())));
+    }
+
+    #[test]
+    fn counterpart_in_the_other_diameter() {
+        let thin = Product::example();
+        let thick = Product {
+            diameter: FilamentDiameter::D285,
+            name: "Hatchbox PLA 3D Printer Filament, 2.85 mm, 1 kg Spool, Black".to_owned(),
+            color: "black".to_owned(),
+            ..Product::example()
+        };
+        let cheaper_elsewhere = Product {
+            retailer: Retailer::Other("SomeShop".to_owned()),
+            price_per_kg: CentsPerKg(1500),
+            ..thick.clone()
+        };
+        let other_color = Product {
+            color: "White".to_owned(),
+            price_per_kg: CentsPerKg(1000),
+            ..thick.clone()
+        };
+
+        let (sql, bindings) = thin.equivalent_other_diameter_statement("Hatchbox");
+        assert!(sql.contains("diameter != $diameter"));
+        assert!(sql.contains(BRAND_CONDITION));
+        assert!(bindings.contains(&("diameter", 175.into())));
+        assert!(bindings.contains(&("material", "PLA".into())));
+
+        let found =
+            thin.closest_equivalent(vec![other_color.clone(), cheaper_elsewhere, thick.clone()]);
+        assert!(found == Some(thick));
+        assert!(thin.closest_equivalent(vec![other_color]).is_none());
+    }
+
+    #[test]
+    fn bulk_add_tag_only_tags_the_filtered_subset() {
+        let query = ProductQuery {
+            material: Some(FilamentMaterial::PETG),
+            ..ProductQuery::default()
+        };
+
+        let now = Product::example().updated_at + chrono::Duration::days(1);
+        let (sql, bindings) = Product::bulk_add_tag_statement(&query, " Clearance ", now).unwrap();
+        assert!(sql.starts_with(
+            "UPDATE products SET tags = array::append(tags ?? [], $tag), \
+             updated_at = $updated_at WHERE "
+        ));
+        assert!(sql.contains("material = $material"));
+        assert!(sql.ends_with(&format!(
+            "AND $tag NOTINSIDE (tags ?? []) RETURN {}",
+            Product::UUID_FIELD
+        )));
+        assert!(bindings.contains(&("material", "PETG".into())));
+        assert!(bindings.contains(&("tag", "clearance".into())));
+        assert!(bindings.contains(&("updated_at", now.timestamp_millis().into())));
+
+        let pla = Product::example();
+        let petg = Product {
+            material: FilamentMaterial::PETG,
+            ..Product::example()
+        };
+        let mut products = vec![pla, petg];
+
+        for product in &mut products {
+            if product.matches(&query) {
+                product.merge_tags(&["Clearance".to_owned()]);
+            }
+        }
+
+        assert!(!products[0].tags.contains(&"clearance".to_owned()));
+        assert!(products[1].tags.contains(&"clearance".to_owned()));
+        assert!(Product::bulk_add_tag_statement(&query, "   ", now).is_none());
+    }
+
+    #[test]
+    fn materials_match_any_of_the_set() {
+        let query = ProductQuery {
+            materials: vec![FilamentMaterial::PLA, FilamentMaterial::PETG],
+            ..ProductQuery::default()
+        };
+
+        let (sql, bindings) = query
+            .builder(&VisibilityConfig::default())
+            .build("SELECT * FROM products", "");
+        assert!(sql.contains("material IN $materials"));
+        assert!(bindings.contains(&("materials", serde_json::json!(["PLA", "PETG"]))));
+
+        let with_material = |material| Product {
+            material,
+            ..Product::example()
+        };
+
+        assert!(with_material(FilamentMaterial::PLA).matches(&query));
+        assert!(with_material(FilamentMaterial::PETG).matches(&query));
+        assert!(!with_material(FilamentMaterial::ABS).matches(&query));
+    }
+
+    #[test]
+    fn group_best_offer_picks_the_cheapest_variant() {
+        let variant = |color: &str, price_per_kg| Product {
+            uuid: SsUuid::new(),
+            color: color.to_owned(),
+            price_per_kg: CentsPerKg(price_per_kg),
+            ..Product::example()
+        };
+        let variants = vec![
+            variant("Black", 2199),
+            variant("Red", 1899),
+            variant("Blue", 2499),
+        ];
+
+        let (sql, bindings) = Product::example().group_best_offer_statement("Hatchbox");
+        assert!(sql.contains("weight = $weight"));
+        assert!(sql.contains(BRAND_CONDITION));
+        assert_eq!(bindings.len(), 3);
+
+        let best = cheapest_offer(variants).unwrap();
+
+        assert_eq!(best.color, "Red");
+        assert_eq!(best.price_per_kg, CentsPerKg(1899));
+        assert!(cheapest_offer(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn public_only_hides_out_of_stock_as_configured() {
+        let public = ProductQuery {
+            exclude_samples: false,
+            public_only: true,
+            ..ProductQuery::default()
+        };
+        let condition = "availability != 'OutOfStock'";
+
+        let (query, _) = public
+            .builder(&VisibilityConfig::default())
+            .build("SELECT * FROM products", "");
+        assert!(query.contains(condition));
+
+        let show = VisibilityConfig {
+            hide_out_of_stock: false,
+        };
+        let (query, _) = public.builder(&show).build("SELECT * FROM products", "");
+        assert!(!query.contains(condition));
+
+        let everything = ProductQuery {
+            public_only: false,
+            ..public
+        };
+        let (query, _) = everything
+            .builder(&VisibilityConfig::default())
+            .build("SELECT * FROM products", "");
+        assert!(!query.contains(condition));
+    }
+
+    #[test]
+    fn public_only_matches_each_condition() {
+        let public = ProductQuery {
+            public_only: true,
+            ..ProductQuery::default()
+        };
+        let hide = VisibilityConfig::default();
+        let show = VisibilityConfig {
+            hide_out_of_stock: false,
+        };
+
+        let listed = Product::example();
+        assert!(listed.matches_with(&public, &hide));
+
+        let sold_out = Product {
+            availability: Availability::OutOfStock,
+            ..Product::example()
+        };
+        assert!(!sold_out.matches_with(&public, &hide));
+        assert!(sold_out.matches_with(&public, &show));
+
+        let unreviewed = Product {
+            weight: Grams(0),
+            ..Product::example()
+        };
+        assert!(unreviewed.needs_review());
+        assert!(!unreviewed.matches_with(&public, &show));
+
+        let everything = ProductQuery {
+            public_only: false,
+            ..public
+        };
+        assert!(sold_out.matches_with(&everything, &hide));
+        assert!(unreviewed.matches_with(&everything, &hide));
+    }
+
+    #[tokio::test]
+    async fn bulk_add_tag_rejects_public_only_queries() {
+        let public = ProductQuery {
+            public_only: true,
+            ..ProductQuery::default()
+        };
+
+        assert!(Product::bulk_add_tag(&public, "clearance").await.is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use futures::{StreamExt, stream};
+use surreal_socket::{
+    dbrecord::{DBRecord, SsUuid},
+    error::SurrealSocketError,
+};
+
+use crate::{product::Product, surrealdb_client};
+
+const REACHABILITY_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Product {
+    /// Whether `url` answers a HEAD request with a 2xx or 3xx status. Network errors and
+    /// timeouts count as unreachable.
+    pub async fn url_is_reachable(&self) -> bool {
+        let Ok(client) = reqwest::Client::builder()
+            .timeout(REACHABILITY_TIMEOUT)
+            .build()
+        else {
+            return false;
+        };
+
+        url_is_reachable(&client, &self.url).await
+    }
+
+    /// Checks every product's URL, at most `concurrency` at a time, and returns the
+    /// products whose URL is unreachable
+    pub async fn find_dead_links(
+        concurrency: usize,
+    ) -> Result<Vec<SsUuid<Product>>, SurrealSocketError> {
+        let client = surrealdb_client().await?;
+        let query = format!("SELECT * FROM {}", Self::table());
+        let products: Vec<Product> = client.query(query).await?.take(0)?;
+
+        let http = reqwest::Client::builder()
+            .timeout(REACHABILITY_TIMEOUT)
+            .build()
+            .map_err(|e| "Error building HTTP client: ".to_owned() + &e.to_string())?;
+
+        Ok(dead_links(&http, products, concurrency).await)
+    }
+}
+
+/// Products among `products` whose URL is unreachable, checked `concurrency` at a time
+async fn dead_links(
+    http: &reqwest::Client,
+    products: Vec<Product>,
+    concurrency: usize,
+) -> Vec<SsUuid<Product>> {
+    stream::iter(products)
+        .map(|product| async move {
+            let reachable = url_is_reachable(http, &product.url).await;
+            (!reachable).then_some(product.uuid)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .filter_map(|uuid| async move { uuid })
+        .collect()
+        .await
+}
+
+async fn url_is_reachable(client: &reqwest::Client, url: &str) -> bool {
+    match client.head(url).send().await {
+        Ok(response) => {
+            let status = response.status();
+            status.is_success() || status.is_redirection()
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    /// Serves `connections` requests on a local port, answering 200 for paths starting with
+    /// "/live" and 404 otherwise, and returns the server's base URL
+    fn mock_server(connections: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+
+                // Drain the headers so closing the socket doesn't reset the connection
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+
+                let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+                let status = if path.starts_with("/live") {
+                    "200 OK"
+                } else {
+                    "404 Not Found"
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+        });
+
+        base
+    }
+
+    #[tokio::test]
+    async fn reachable_and_missing_urls() {
+        let base = mock_server(2);
+
+        let live = Product {
+            url: format!("{}/live", base),
+            ..Product::example()
+        };
+        let missing = Product {
+            url: format!("{}/gone", base),
+            ..Product::example()
+        };
+
+        assert!(live.url_is_reachable().await);
+        assert!(!missing.url_is_reachable().await);
+    }
+
+    #[tokio::test]
+    async fn dead_links_among_live_ones() {
+        let base = mock_server(4);
+        let products: Vec<Product> = ["/live-1", "/gone-1", "/live-2", "/gone-2"]
+            .iter()
+            .map(|path| Product {
+                url: format!("{}{}", base, path),
+                ..Product::example()
+            })
+            .collect();
+
+        let dead = dead_links(&reqwest::Client::new(), products.clone(), 2).await;
+
+        assert_eq!(dead.len(), 2);
+        for (product, expect_dead) in products.iter().zip([false, true, false, true]) {
+            assert_eq!(dead.iter().any(|uuid| *uuid == product.uuid), expect_dead);
+        }
+    }
+}
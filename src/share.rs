@@ -0,0 +1,141 @@
+use surreal_socket::{
+    dbrecord::{DBRecord, SsUuid},
+    error::SurrealSocketError,
+};
+
+use crate::{product::Product, surrealdb_client};
+
+/// Digits of the short ids, in value order
+const BASE62_ALPHABET: &[u8; 62] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Length of a short id: enough base62 digits for any 128-bit uuid
+const SHORT_ID_LEN: usize = 22;
+
+/// Path segment share links put before the short id
+const SHARE_PATH: &str = "p";
+
+impl Product {
+    /// Id for share links, e.g. "3hX9kQ2bLr0aTmVw8c1ZpN": the uuid's 128 bits in base62,
+    /// padded to 22 characters instead of 36. It encodes the whole uuid, so every product
+    /// has a distinct one and `find_by_short_id` needs no stored mapping. `None` if the uuid
+    /// doesn't hold 32 hex digits.
+    pub fn short_id(&self) -> Option<String> {
+        let hex: Vec<char> = self
+            .uuid
+            .to_uuid_string()
+            .chars()
+            .filter(char::is_ascii_hexdigit)
+            .collect();
+        let hex: String = hex[hex.len().checked_sub(32)?..].iter().collect();
+
+        let mut value = u128::from_str_radix(&hex, 16).ok()?;
+        let mut digits = [BASE62_ALPHABET[0]; SHORT_ID_LEN];
+
+        for digit in digits.iter_mut().rev() {
+            *digit = BASE62_ALPHABET[(value % 62) as usize];
+            value /= 62;
+        }
+
+        Some(String::from_utf8_lossy(&digits).into_owned())
+    }
+
+    /// Share link to encode in a QR code, e.g. "https://filamentseek.com/p/3hX9kQ2bLr0aTmVw8c1ZpN":
+    /// `base_url` (trailing slashes dropped) followed by `SHARE_PATH` and the short id. `None`
+    /// when there is no short id.
+    pub fn qr_payload(&self, base_url: &str) -> Option<String> {
+        Some(format!(
+            "{}/{}/{}",
+            base_url.trim().trim_end_matches('/'),
+            SHARE_PATH,
+            self.short_id()?
+        ))
+    }
+
+    /// Product whose `short_id` is `short`, if any. Malformed ids find nothing.
+    pub async fn find_by_short_id(short: &str) -> Result<Option<Product>, SurrealSocketError> {
+        let Some(uuid) = decode_short_id(short.trim()) else {
+            return Ok(None);
+        };
+
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT * FROM {} WHERE {} = $uuid LIMIT 1",
+            Self::table(),
+            Self::UUID_FIELD
+        );
+
+        let mut response = client.query(query).bind(("uuid", uuid)).await?;
+        Ok(response.take(0)?)
+    }
+}
+
+/// Uuid encoded by `Product::short_id`
+fn decode_short_id(short: &str) -> Option<SsUuid<Product>> {
+    if short.len() != SHORT_ID_LEN {
+        return None;
+    }
+
+    let value = short.bytes().try_fold(0u128, |value, byte| {
+        let digit = BASE62_ALPHABET.iter().position(|b| *b == byte)?;
+        value.checked_mul(62)?.checked_add(digit as u128)
+    })?;
+
+    let hex = format!("{:032x}", value);
+    let uuid = format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    );
+
+    serde_json::from_value(serde_json::Value::String(uuid)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_id_round_trips() {
+        let product = Product::example();
+        let short = product.short_id().unwrap();
+
+        assert_eq!(short.len(), SHORT_ID_LEN);
+        assert!(decode_short_id(&short) == Some(product.uuid()));
+    }
+
+    #[test]
+    fn distinct_products_get_distinct_short_ids() {
+        let first = Product::example().short_id();
+
+        assert!(first.is_some());
+        assert_ne!(first, Product::example().short_id());
+    }
+
+    #[test]
+    fn malformed_short_ids_decode_to_nothing() {
+        assert!(decode_short_id("").is_none());
+        assert!(decode_short_id("tooshort").is_none());
+        assert!(decode_short_id("3hX9kQ2bLr0aTmVw8c1Zp!").is_none());
+        assert!(decode_short_id("zzzzzzzzzzzzzzzzzzzzzz").is_none());
+    }
+
+    #[test]
+    fn qr_payload_joins_base_url() {
+        let product = Product::example();
+        let expected = format!("https://filamentseek.com/p/{}", product.short_id().unwrap());
+
+        assert_eq!(
+            product.qr_payload("https://filamentseek.com/ ").as_ref(),
+            Some(&expected)
+        );
+        assert_eq!(
+            product.qr_payload("https://filamentseek.com//").as_ref(),
+            Some(&expected)
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use surreal_socket::{dbrecord::DBRecord, error::SurrealSocketError};
+
+use crate::{
+    product::{
+        Cents, CentsPerKg, Currency, FilamentDiameter, FilamentMaterial, Grams, Product, Retailer,
+    },
+    query::ProductQuery,
+    surrealdb_client,
+};
+
+/// Multiple of the interquartile range beyond the quartiles at which a price is an outlier
+const OUTLIER_IQR_FACTOR: f64 = 3.0;
+
+/// Materials with fewer products than this are too small to judge outliers in
+const OUTLIER_MIN_SAMPLE: usize = 4;
+
+impl Product {
+    /// Sum of `price` per currency across all products, or only those of `retailer`, in
+    /// each currency's minor unit and ordered by currency code. Prices in different
+    /// currencies can't be added, so there is one total per currency present.
+    pub async fn total_catalog_value(
+        retailer: Option<&Retailer>,
+    ) -> Result<Vec<(Currency, u64)>, SurrealSocketError> {
+        #[derive(Deserialize)]
+        struct Total {
+            currency: Option<Currency>,
+            total: u64,
+        }
+
+        let filter = if retailer.is_some() {
+            "WHERE retailer = $retailer"
+        } else {
+            ""
+        };
+
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT currency, math::sum(price) AS total FROM {} {} GROUP BY currency",
+            Self::table(),
+            filter
+        );
+
+        let mut response = client
+            .query(query)
+            .bind(("retailer", retailer.map(Retailer::to_string)))
+            .await?;
+
+        let totals: Vec<Total> = response.take(0)?;
+
+        Ok(totals_by_currency(
+            totals.into_iter().map(|t| (t.currency, t.total)),
+        ))
+    }
+
+    /// Number of products per diameter present, thinnest first
+    pub async fn count_by_diameter() -> Result<Vec<(FilamentDiameter, u64)>, SurrealSocketError> {
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT diameter, count() AS count FROM {} GROUP BY diameter",
+            Self::table()
+        );
+
+        let rows: Vec<DiameterCount> = client.query(query).await?.take(0)?;
+        Ok(diameter_counts(rows))
+    }
+
+    /// Price alert threshold for this product: what it would cost at the 25th percentile
+    /// price per kg of its material, among listings in the same currency with a known
+    /// weight. Falls back to the current price without other listings or a weight of its own.
+    pub async fn suggested_alert_price(&self) -> Result<Cents, SurrealSocketError> {
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT VALUE price_per_kg FROM {} \
+             WHERE material = $material AND currency = $currency AND weight > 0",
+            Self::table()
+        );
+
+        let mut response = client
+            .query(query)
+            .bind(("material", self.material.to_string()))
+            .bind(("currency", self.currency.to_string()))
+            .await?;
+
+        let prices: Vec<CentsPerKg> = response.take(0)?;
+        Ok(self.alert_price_among(&prices))
+    }
+
+    /// `suggested_alert_price` given the prices per kg of the material's listings
+    fn alert_price_among(&self, prices_per_kg: &[CentsPerKg]) -> Cents {
+        if prices_per_kg.is_empty() || self.weight.0 == 0 {
+            return self.price;
+        }
+
+        let mut prices: Vec<f64> = prices_per_kg.iter().map(|p| p.0 as f64).collect();
+        prices.sort_by(f64::total_cmp);
+        let price_per_kg = quantile(&prices, 0.25);
+
+        Cents((price_per_kg * self.weight.0 as f64 / 1000.0).round() as u32)
+    }
+
+    /// Position of this product among the same material at the same retailer by price per kg,
+    /// as (rank, total) with 1 the cheapest, e.g. "3rd cheapest PLA on Amazon". Only listings
+    /// in the same currency with a known weight are compared, and products priced the same
+    /// share a rank. A product not yet stored counts itself in the total.
+    pub async fn rank_within_retailer(&self) -> Result<(u32, u32), SurrealSocketError> {
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT VALUE price_per_kg FROM {} \
+             WHERE material = $material AND retailer = $retailer AND currency = $currency \
+             AND weight > 0 AND {} != $uuid",
+            Self::table(),
+            Self::UUID_FIELD
+        );
+
+        let mut response = client
+            .query(query)
+            .bind(("material", self.material.to_string()))
+            .bind(("retailer", self.retailer.to_string()))
+            .bind(("currency", self.currency.to_string()))
+            .bind(("uuid", self.uuid.clone()))
+            .await?;
+
+        let others = response.take::<Vec<CentsPerKg>>(0)?;
+        Ok(self.rank_among(&others))
+    }
+
+    /// (rank, total) of this product's price per kg among `others`, which leave it out
+    fn rank_among(&self, others: &[CentsPerKg]) -> (u32, u32) {
+        let price_per_kg = self.calculate_price_per_kg();
+        let cheaper = others.iter().filter(|p| **p < price_per_kg).count();
+
+        (cheaper as u32 + 1, others.len() as u32 + 1)
+    }
+
+    /// Products whose price per kg is more than three interquartile ranges outside the
+    /// quartiles of their material, among products priced in the same currency
+    pub async fn price_outliers() -> Result<Vec<Product>, SurrealSocketError> {
+        let products = Self::search(&ProductQuery {
+            exclude_samples: false,
+            ..Default::default()
+        })
+        .await?;
+
+        Ok(iqr_outliers(products))
+    }
+}
+
+/// Row of the `GROUP BY diameter` query in `Product::count_by_diameter`
+#[derive(Deserialize)]
+struct DiameterCount {
+    diameter: FilamentDiameter,
+    count: u64,
+}
+
+/// Counts per diameter, smallest diameter first
+fn diameter_counts(rows: Vec<DiameterCount>) -> Vec<(FilamentDiameter, u64)> {
+    let mut counts: Vec<(FilamentDiameter, u64)> =
+        rows.into_iter().map(|r| (r.diameter, r.count)).collect();
+    counts.sort_by_key(|(diameter, _)| u16::from(*diameter));
+    counts
+}
+
+/// Products whose price per kg lies more than `OUTLIER_IQR_FACTOR` interquartile ranges
+/// outside the quartiles of their material and currency, for groups with at least
+/// `OUTLIER_MIN_SAMPLE` products. Products without a weight have no price per kg and are
+/// neither counted nor flagged.
+fn iqr_outliers(products: Vec<Product>) -> Vec<Product> {
+    let mut by_group: HashMap<(FilamentMaterial, Currency), Vec<f64>> = HashMap::new();
+
+    for product in products.iter().filter(|p| p.weight > Grams(0)) {
+        by_group
+            .entry((product.material.clone(), product.currency.clone()))
+            .or_default()
+            .push(product.price_per_kg.0 as f64);
+    }
+
+    let bounds: HashMap<(FilamentMaterial, Currency), (f64, f64)> = by_group
+        .into_iter()
+        .filter(|(_, prices)| prices.len() >= OUTLIER_MIN_SAMPLE)
+        .map(|(group, mut prices)| {
+            prices.sort_by(f64::total_cmp);
+            let q1 = quantile(&prices, 0.25);
+            let q3 = quantile(&prices, 0.75);
+            let margin = (q3 - q1) * OUTLIER_IQR_FACTOR;
+            (group, (q1 - margin, q3 + margin))
+        })
+        .collect();
+
+    products
+        .into_iter()
+        .filter(|p| p.weight > Grams(0))
+        .filter(|p| {
+            bounds
+                .get(&(p.material.clone(), p.currency.clone()))
+                .is_some_and(|(low, high)| {
+                    let price = p.price_per_kg.0 as f64;
+                    price < *low || price > *high
+                })
+        })
+        .collect()
+}
+
+/// Linearly interpolated quantile `q` (0.0 to 1.0) of ascending `sorted` values
+pub(crate) fn quantile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+
+    let position = q.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    let fraction = position - lower as f64;
+
+    sorted[lower] + (sorted[upper] - sorted[lower]) * fraction
+}
+
+/// Per-currency totals from `(currency, total)` rows, with a missing currency counted as the
+/// default (records predating the field are USD), ordered by currency code
+fn totals_by_currency(
+    rows: impl IntoIterator<Item = (Option<Currency>, u64)>,
+) -> Vec<(Currency, u64)> {
+    let mut totals: HashMap<Currency, u64> = HashMap::new();
+
+    for (currency, total) in rows {
+        *totals.entry(currency.unwrap_or_default()).or_default() += total;
+    }
+
+    let mut totals: Vec<(Currency, u64)> = totals.into_iter().collect();
+    totals.sort_by_key(|(currency, _)| currency.to_string());
+    totals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catalog_value_is_totalled_per_currency() {
+        let totals = totals_by_currency([
+            (Some(Currency::USD), 2199),
+            (Some(Currency::JPY), 1995),
+            (None, 1000),
+            (Some(Currency::EUR), 1850),
+        ]);
+
+        assert_eq!(
+            totals,
+            vec![
+                (Currency::EUR, 1850),
+                (Currency::JPY, 1995),
+                (Currency::USD, 3199),
+            ]
+        );
+        assert!(totals_by_currency(Vec::<(Option<Currency>, u64)>::new()).is_empty());
+    }
+
+    #[test]
+    fn quantile_interpolates() {
+        let sorted = [1.0, 2.0, 3.0, 4.0];
+
+        assert_eq!(quantile(&sorted, 0.0), 1.0);
+        assert_eq!(quantile(&sorted, 0.5), 2.5);
+        assert_eq!(quantile(&sorted, 1.0), 4.0);
+        assert_eq!(quantile(&[], 0.5), 0.0);
+    }
+
+    fn priced(material: FilamentMaterial, cents_per_kg: u32) -> Product {
+        Product {
+            material,
+            price_per_kg: CentsPerKg(cents_per_kg),
+            ..Product::example()
+        }
+    }
+
+    #[test]
+    fn clear_outlier_among_normal_prices() {
+        let mut products: Vec<Product> = [2000, 2100, 2200, 2300, 2400, 9000]
+            .into_iter()
+            .map(|cents| priced(FilamentMaterial::PLA, cents))
+            .collect();
+        // Too few ABS listings to judge, however far apart
+        products.push(priced(FilamentMaterial::ABS, 2000));
+        products.push(priced(FilamentMaterial::ABS, 20000));
+
+        let outliers = iqr_outliers(products);
+
+        assert_eq!(outliers.len(), 1);
+        assert_eq!(outliers[0].price_per_kg, CentsPerKg(9000));
+    }
+
+    #[test]
+    fn outliers_are_judged_per_currency_and_skip_weightless() {
+        let mut products: Vec<Product> = [2000, 2100, 2200, 2300]
+            .into_iter()
+            .map(|cents| priced(FilamentMaterial::PLA, cents))
+            .collect();
+        // Far above the USD prices, but the only yen listings
+        products.extend(
+            [300_000, 310_000, 320_000, 330_000]
+                .into_iter()
+                .map(|cents| Product {
+                    currency: Currency::JPY,
+                    ..priced(FilamentMaterial::PLA, cents)
+                }),
+        );
+        // No weight, so no meaningful price per kg either way
+        products.push(Product {
+            weight: Grams(0),
+            ..priced(FilamentMaterial::PLA, 0)
+        });
+
+        assert!(iqr_outliers(products).is_empty());
+    }
+
+    #[test]
+    fn diameter_counts_from_grouped_rows() {
+        let rows: Vec<DiameterCount> = serde_json::from_value(serde_json::json!([
+            { "diameter": 285, "count": 3 },
+            { "diameter": 175, "count": 12 },
+            { "diameter": 300, "count": 1 },
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            diameter_counts(rows),
+            vec![
+                (FilamentDiameter::D175, 12),
+                (FilamentDiameter::D285, 3),
+                (FilamentDiameter::Other(300), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn alert_price_below_material_median() {
+        let product = Product::example();
+        let prices = [2400, 1800, 2000, 3000, 2200].map(CentsPerKg);
+
+        let suggestion = product.alert_price_among(&prices);
+        assert_eq!(suggestion, Cents(2000));
+        assert!(suggestion.0 < 2200);
+
+        let half_kg = Product {
+            weight: Grams(500),
+            ..Product::example()
+        };
+        assert_eq!(half_kg.alert_price_among(&prices), Cents(1000));
+
+        let weightless = Product {
+            weight: Grams(0),
+            ..Product::example()
+        };
+        assert_eq!(weightless.alert_price_among(&prices), weightless.price);
+
+        assert_eq!(product.alert_price_among(&[]), product.price);
+    }
+
+    #[test]
+    fn cheapest_pla_on_a_retailer_ranks_first() {
+        let priced = |cents| Product {
+            price: Cents(cents),
+            ..Product::example()
+        };
+        let products = [priced(1599), priced(1999), priced(2199), priced(2499)];
+        let rank = |index: usize| {
+            let others: Vec<CentsPerKg> = products
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != index)
+                .map(|(_, p)| p.calculate_price_per_kg())
+                .collect();
+            products[index].rank_among(&others)
+        };
+
+        assert_eq!(rank(0), (1, 4));
+        assert_eq!(rank(2), (3, 4));
+        assert_eq!(rank(3), (4, 4));
+        assert_eq!(priced(1999).rank_among(&[CentsPerKg(1999)]), (1, 2));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::product::{FilamentMaterial, Product};
+
+/// Fraction of the way up the material's plausible price band below which a product is
+/// tagged "budget"
+const BUDGET_BAND_POSITION: f32 = 0.2;
+
+/// Fraction of the way up the material's plausible price band above which a product is
+/// tagged "premium"
+const PREMIUM_BAND_POSITION: f32 = 0.5;
+
+impl FilamentMaterial {
+    /// Family tag grouping related materials, e.g. "pla-family" for PLA and PLA+
+    pub fn family_tag(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::PLA | Self::PLAPlus => "pla-family",
+            Self::PETG | Self::PCTG => "petg-family",
+            Self::ABS | Self::ASA => "abs-family",
+            Self::TPU => "tpu-family",
+            Self::Nylon => "nylon-family",
+            Self::PC => "pc-family",
+            Self::Unspecified | Self::Unknown | Self::Other(_) => return None,
+        })
+    }
+}
+
+impl Product {
+    /// Tags following from the product's own data: "budget" or "premium" by where its price
+    /// per kg sits in the material's plausible band, "on-sale" when priced below
+    /// `list_price`, and the material family
+    pub fn derived_tags(&self) -> Vec<String> {
+        let mut tags = Vec::new();
+
+        let band = self.material.plausible_price_per_kg();
+        let (low, high) = (band.start().0 as f32, band.end().0 as f32);
+        let position = (self.price_per_kg.0 as f32 - low) / (high - low);
+
+        if self.weight.0 > 0 && self.price_per_kg.0 > 0 {
+            if position < BUDGET_BAND_POSITION {
+                tags.push("budget");
+            } else if position > PREMIUM_BAND_POSITION {
+                tags.push("premium");
+            }
+        }
+
+        if self.list_price.is_some_and(|list| list > self.price) {
+            tags.push("on-sale");
+        }
+
+        tags.extend(self.material.family_tag());
+
+        tags.into_iter().map(str::to_owned).collect()
+    }
+
+    /// Adds `derived_tags` missing from `tags`, keeping existing tags. Returns whether any
+    /// were added.
+    pub fn merge_derived_tags(&mut self) -> bool {
+        let before = self.tags.len();
+        self.merge_tags(&self.derived_tags());
+        self.tags.len() > before
+    }
+
+    /// Appends `other_tags`, normalized as by `normalize_tag`, that aren't already present.
+    /// Existing tags keep their order and new ones follow in the order given; blank tags
+    /// are skipped.
+    pub fn merge_tags(&mut self, other_tags: &[String]) {
+        for tag in other_tags.iter().map(|t| normalize_tag(t)) {
+            if !tag.is_empty() && !self.tags.contains(&tag) {
+                self.tags.push(tag);
+            }
+        }
+    }
+}
+
+/// Lowercases a tag and joins its words with hyphens, so "On Sale" and "on-sale" agree
+pub fn normalize_tag(tag: &str) -> String {
+    tag.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join("-")
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::product::{Cents, CentsPerKg};
+
+    fn priced(price: u32, list_price: Option<u32>) -> Product {
+        Product {
+            price: Cents(price),
+            price_per_kg: CentsPerKg(price),
+            list_price: list_price.map(Cents),
+            tags: vec!["staff-pick".to_owned()],
+            ..Product::example()
+        }
+    }
+
+    #[test]
+    fn cheap_on_sale_pla_tags() {
+        let mut product = priced(1499, Some(1999));
+
+        assert_eq!(
+            product.derived_tags(),
+            vec!["budget", "on-sale", "pla-family"]
+        );
+
+        assert!(product.merge_derived_tags());
+        assert_eq!(
+            product.tags,
+            vec!["staff-pick", "budget", "on-sale", "pla-family"]
+        );
+        assert!(!product.merge_derived_tags());
+    }
+
+    #[test]
+    fn premium_full_price_tags() {
+        let product = priced(6999, None);
+
+        assert_eq!(product.derived_tags(), vec!["premium", "pla-family"]);
+    }
+
+    #[test]
+    fn merge_tags_is_an_ordered_deduplicated_union() {
+        let mut product = Product {
+            tags: vec!["budget".to_owned(), "staff-pick".to_owned()],
+            ..Product::example()
+        };
+
+        product.merge_tags(&[
+            "Staff Pick".to_owned(),
+            "on-sale".to_owned(),
+            "  ".to_owned(),
+            "ON SALE".to_owned(),
+            "eco".to_owned(),
+        ]);
+
+        assert_eq!(product.tags, vec!["budget", "staff-pick", "on-sale", "eco"]);
+    }
+}
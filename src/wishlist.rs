@@ -0,0 +1,91 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use surreal_socket::{
+    dbrecord::{DBRecord, SsUuid},
+    error::SurrealSocketError,
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    product::{Cents, Product},
+    surrealdb_client,
+};
+
+/// Product saved to a wishlist, with its price when it was added
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WishlistItem {
+    pub product_uuid: SsUuid<Product>,
+    pub name: String,
+    pub price_at_add: Cents,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub added_at: DateTime<Utc>,
+}
+
+impl WishlistItem {
+    /// Change in cents from `price_at_add` to `current`'s price, negative when cheaper now
+    pub fn price_delta(&self, current: &Product) -> i64 {
+        current.price.0 as i64 - self.price_at_add.0 as i64
+    }
+
+    /// Looks up the product's current price and returns `price_delta` against it, or `None`
+    /// if the product no longer exists
+    pub async fn current_price_delta(&self) -> Result<Option<i64>, SurrealSocketError> {
+        let client = surrealdb_client().await?;
+
+        let query = format!(
+            "SELECT * FROM {} WHERE {} = $uuid LIMIT 1",
+            Product::table(),
+            Product::UUID_FIELD
+        );
+
+        let product: Option<Product> = client
+            .query(query)
+            .bind(("uuid", self.product_uuid.clone()))
+            .await?
+            .take(0)?;
+
+        Ok(product.map(|p| self.price_delta(&p)))
+    }
+}
+
+impl Product {
+    /// Wishlist entry for this product, snapshotting its current price
+    pub fn to_wishlist_item(&self) -> WishlistItem {
+        self.to_wishlist_item_with(&SystemClock)
+    }
+
+    /// `to_wishlist_item`, with `added_at` taken from `clock`
+    pub fn to_wishlist_item_with(&self, clock: &dyn Clock) -> WishlistItem {
+        WishlistItem {
+            product_uuid: self.uuid.clone(),
+            name: self.name.clone(),
+            price_at_add: self.price,
+            added_at: clock.now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+
+    #[test]
+    fn price_delta_since_the_item_was_added() {
+        let mut product = Product::example();
+        let added_at = DateTime::from_timestamp(1_760_000_000, 0).unwrap();
+        let item = product.to_wishlist_item_with(&FixedClock(added_at));
+
+        assert!(item.product_uuid == product.uuid);
+        assert_eq!(item.name, product.name);
+        assert_eq!(item.price_at_add, Cents(2199));
+        assert_eq!(item.added_at, added_at);
+        assert_eq!(item.price_delta(&product), 0);
+
+        product.price = Cents(1899);
+        assert_eq!(item.price_delta(&product), -300);
+
+        product.price = Cents(2499);
+        assert_eq!(item.price_delta(&product), 300);
+    }
+}